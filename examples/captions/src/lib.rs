@@ -1,7 +1,7 @@
 use std::f32::consts::FRAC_PI_4;
 
 use bevy::{
-    asset::{AsAssetId, RenderAssetUsages},
+    asset::RenderAssetUsages,
     color::palettes::css::GOLD,
     prelude::*,
     render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
@@ -9,7 +9,8 @@ use bevy::{
 };
 use bevy_web_video::{
     EventListenerAppExt, EventSender, ListenerEvent, VideoElement, VideoElementAssetsExt,
-    VideoElementRegistry, WebVideo, WebVideoError, WebVideoPlugin, events, new_event_type,
+    VideoElementRegistry, VideoPlayback, WebVideo, WebVideoError, WebVideoPlugin, events,
+    new_event_type,
 };
 use wasm_bindgen::prelude::*;
 
@@ -34,7 +35,16 @@ pub fn main() {
     app.run();
 }
 
-new_event_type!(CueChange, "cuechange");
+new_event_type!(CueChange, "cuechange", Option<String>, |event| {
+    event
+        .target()?
+        .dyn_into::<web_sys::HtmlTrackElement>()
+        .ok()?
+        .track()?
+        .active_cues()?
+        .get(0)
+        .map(|cue| cue.text())
+});
 
 #[derive(Component)]
 struct Video;
@@ -49,19 +59,22 @@ fn setup(
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut images: ResMut<Assets<Image>>,
     mut video_elements: ResMut<Assets<VideoElement>>,
-    loadedmetadata_event_sender: Res<EventSender<events::LoadedMetadata>>,
     cuechange_event_sender: Res<EventSender<CueChange>>,
     mut registry: NonSendMut<VideoElementRegistry>,
 ) -> Result<()> {
     let video_image = images.reserve_handle();
     let (video_element_handle, element) = video_elements.new_video(&video_image, &mut registry);
     let video_asset_id = video_element_handle.id();
-    let video_entity = commands.spawn(WebVideo::new(video_element_handle)).id();
+    let video_entity = commands
+        .spawn((
+            WebVideo::new(video_element_handle),
+            // User must click to unmute
+            VideoPlayback::playing().with_muted(true).with_looping(true),
+        ))
+        .id();
 
     element.set_cross_origin(Some("anonymous"));
     element.set_src("https://thepaciellogroup.github.io/AT-browser-tests/video/ElephantsDream.mp4");
-    element.set_muted(true); // User must click to unmute
-    element.set_loop(true);
 
     let track = registry
         .document()
@@ -80,12 +93,6 @@ fn setup(
     commands
         .entity(video_entity)
         .observe(loadedmetadata_observer);
-    loadedmetadata_event_sender.enable_element_event_observers(
-        video_asset_id,
-        &element,
-        &mut registry,
-        video_entity,
-    );
 
     commands.entity(video_entity).observe(cuechange_observer);
     cuechange_event_sender.enable_element_event_observers(
@@ -95,8 +102,6 @@ fn setup(
         video_entity,
     );
 
-    let _ = element.play().map_err(WebVideoError::from)?;
-
     commands.spawn((Camera3d::default(), Transform::from_xyz(0.0, 0.0, 3.0)));
 
     const CAPTION_X_SCALE: f32 = 1.5;
@@ -169,40 +174,27 @@ fn setup(
 fn loadedmetadata_observer(
     listener_event: On<ListenerEvent<events::LoadedMetadata>>,
     mut transform: Single<&mut Transform, With<Video>>,
-    registry: NonSend<VideoElementRegistry>,
 ) {
-    if let Some(element) = registry.element(listener_event.asset_id()) {
-        let width = element.video_width();
-        let height = element.video_height();
-        let aspect = width as f32 / height as f32;
-        // Scale plane to match video aspect ratio.
-        transform.scale = Vec3::new(aspect.max(1.0), aspect.min(1.0), 1.0);
-    }
+    let size = listener_event.payload().size;
+    let aspect = size.x as f32 / size.y as f32;
+    // Scale plane to match video aspect ratio.
+    transform.scale = Vec3::new(aspect.max(1.0), aspect.min(1.0), 1.0);
 }
 
 fn cuechange_observer(
     listener_event: On<ListenerEvent<CueChange>>,
     mut text: Single<&mut Text, With<Caption>>,
-    registry: NonSend<VideoElementRegistry>,
 ) {
-    if let Some(element) = registry.element(listener_event.asset_id())
-        && let Some(text_tracks) = element.text_tracks()
-        && let Some(track) = text_tracks.get(0)
-        && let Some(cues) = track.active_cues()
-        && let Some(cue) = cues.get(0)
-    {
-        text.0 = cue.text();
+    if let Some(cue_text) = listener_event.payload() {
+        text.0 = cue_text.clone();
     }
 }
 
 fn toggle_mute(
     mouse_button: Res<ButtonInput<MouseButton>>,
-    video: Single<&WebVideo>,
-    registry: NonSend<VideoElementRegistry>,
+    mut playback: Single<&mut VideoPlayback>,
 ) {
-    if mouse_button.just_pressed(MouseButton::Left)
-        && let Some(element) = registry.element(video.as_asset_id())
-    {
-        element.set_muted(!element.muted());
+    if mouse_button.just_pressed(MouseButton::Left) {
+        playback.muted = !playback.muted;
     }
 }
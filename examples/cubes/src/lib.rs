@@ -4,10 +4,10 @@ use bevy::{
     core_pipeline::prepass::DepthPrepass,
     pbr::decal::{ForwardDecal, ForwardDecalMaterial, ForwardDecalMaterialExt},
 };
-use bevy::{prelude::*, window::WindowResolution};
+use bevy::{image::ImageSampler, prelude::*, window::WindowResolution};
 use bevy_web_video::{
-    EventSender, ListenerEvent, VideoElement, VideoElementAssetsExt, VideoElementRegistry,
-    WebVideo, WebVideoError, WebVideoPlugin, events,
+    ListenerEvent, VideoLoaderSettings, VideoPlayback, VideoSource, VideoTextureSettings, WebVideo,
+    WebVideoImage, WebVideoPlugin, events,
 };
 use wasm_bindgen::prelude::*;
 
@@ -43,40 +43,23 @@ struct DecalMaterial1;
 #[derive(Component)]
 struct DecalMaterial2;
 
-#[allow(clippy::too_many_arguments)]
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut decal_materials: ResMut<Assets<ForwardDecalMaterial<StandardMaterial>>>,
     images: Res<Assets<Image>>,
-    mut video_elements: ResMut<Assets<VideoElement>>,
-    loadedmetadata_event_sender: Res<EventSender<events::LoadedMetadata>>,
-    mut registry: NonSendMut<VideoElementRegistry>,
-) -> Result<()> {
+) {
     let image_handle1 = images.reserve_handle();
-    let (video_element_handle1, element1) = video_elements.new_video(&image_handle1, &mut registry);
-    let video_element_id1 = video_element_handle1.id();
-    let video_entity1 = commands.spawn(WebVideo::new(video_element_handle1)).id();
-
     commands
-        .entity(video_entity1)
+        .spawn((
+            new_web_video("https://cdn.glitch.me/364f8e5a-f12f-4f82-a386-20e6be6b1046/bbb_sunflower_1080p_30fps_normal_10min.mp4"),
+            WebVideoImage(image_handle1.clone()),
+            VideoPlayback::playing().with_muted(true).with_looping(true),
+        ))
         .observe(scale_spincube_listener)
         .observe(scale_decals_listener::<DecalMaterial1>);
 
-    loadedmetadata_event_sender.enable_element_event_observers(
-        video_element_id1,
-        &element1,
-        &mut registry,
-        video_entity1,
-    );
-
-    element1.set_cross_origin(Some("anonymous"));
-    element1.set_src("https://cdn.glitch.me/364f8e5a-f12f-4f82-a386-20e6be6b1046/bbb_sunflower_1080p_30fps_normal_10min.mp4");
-    element1.set_muted(true);
-    element1.set_loop(true);
-    let _ = element1.play().map_err(WebVideoError::from)?;
-
     commands.spawn((
         SpinCube,
         Mesh3d(meshes.add(Cuboid::new(1.0, 1.0, 1.0))),
@@ -88,28 +71,15 @@ fn setup(
     ));
 
     let image_handle2 = images.reserve_handle();
-    let (video_element_handle2, element2) = video_elements.new_video(&image_handle2, &mut registry);
-    let video_element_id2 = video_element_handle2.id();
-
-    let video_entity2 = commands.spawn(WebVideo::new(video_element_handle2)).id();
-
     commands
-        .entity(video_entity2)
+        .spawn((
+            new_web_video(
+                "https://cdn.glitch.me/364f8e5a-f12f-4f82-a386-20e6be6b1046/elephants_dream_1280x720.mp4",
+            ),
+            WebVideoImage(image_handle2.clone()),
+            VideoPlayback::playing().with_muted(true).with_looping(true),
+        ))
         .observe(scale_decals_listener::<DecalMaterial2>);
-    loadedmetadata_event_sender.enable_element_event_observers(
-        video_element_id2,
-        &element2,
-        &mut registry,
-        video_entity2,
-    );
-
-    element2.set_cross_origin(Some("anonymous"));
-    element2.set_src(
-        "https://cdn.glitch.me/364f8e5a-f12f-4f82-a386-20e6be6b1046/elephants_dream_1280x720.mp4",
-    );
-    element2.set_muted(true);
-    element2.set_loop(true);
-    let _ = element2.play().map_err(WebVideoError::from)?;
 
     let decal_material1 = decal_materials.add(new_decal_material(image_handle1));
     let decal_material2 = decal_materials.add(new_decal_material(image_handle2));
@@ -177,21 +147,29 @@ fn setup(
         Msaa::Off,    // workaround https://github.com/bevyengine/bevy/issues/19177
         Transform::from_xyz(0., 0., 3.5),
     ));
-    Ok(())
 }
 
 fn scale_spincube_listener(
     listener_event: On<ListenerEvent<events::LoadedMetadata>>,
     mut transform: Single<&mut Transform, With<SpinCube>>,
-    registry: NonSend<VideoElementRegistry>,
 ) {
-    if let Some(element) = registry.element(listener_event.asset_id()) {
-        let width = element.video_width();
-        let height = element.video_height();
-        let aspect = width as f32 / height as f32;
-        // Scale cube to match video aspect ratio.
-        transform.scale = Vec3::new(aspect.max(1.0), aspect.min(1.0), 1.0);
-    }
+    let size = listener_event.payload().size;
+    let aspect = size.x as f32 / size.y as f32;
+    // Scale cube to match video aspect ratio.
+    transform.scale = Vec3::new(aspect.max(1.0), aspect.min(1.0), 1.0);
+}
+
+fn new_web_video(url: &str) -> WebVideo {
+    WebVideo::from_source(VideoSource::Url(url.into())).with_settings(VideoLoaderSettings {
+        cross_origin: Some("anonymous".into()),
+        // Avoid shimmering as the cube tumbles away
+        texture: VideoTextureSettings {
+            generate_mipmaps: true,
+            sampler: ImageSampler::linear(),
+            ..default()
+        },
+        ..default()
+    })
 }
 
 fn new_decal_material(image: Handle<Image>) -> ForwardDecalMaterial<StandardMaterial> {
@@ -209,18 +187,17 @@ fn new_decal_material(image: Handle<Image>) -> ForwardDecalMaterial<StandardMate
 fn scale_decals_listener<V: Component>(
     listener_event: On<ListenerEvent<events::LoadedMetadata>>,
     mut decals: Query<&mut Transform, (With<ForwardDecal>, With<V>)>,
-    registry: NonSend<VideoElementRegistry>,
 ) {
-    if let Some(element) = registry.element(listener_event.asset_id()) {
-        let width = element.video_width();
-        let height = element.video_height();
-        for mut transform in &mut decals {
-            // Scale decal to match video aspect ratio
-            if width > height {
-                transform.scale.z = height as f32 / width as f32;
-            } else {
-                transform.scale.x = width as f32 / height as f32;
-            }
+    let UVec2 {
+        x: width,
+        y: height,
+    } = listener_event.payload().size;
+    for mut transform in &mut decals {
+        // Scale decal to match video aspect ratio
+        if width > height {
+            transform.scale.z = height as f32 / width as f32;
+        } else {
+            transform.scale.x = width as f32 / height as f32;
         }
     }
 }
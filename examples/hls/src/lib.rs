@@ -0,0 +1,172 @@
+//! Plays the HLS playlist of the `src` query parameter, or a test stream without one.
+//!
+//! Press 1-9 to fix the variant and A for automatic selection by the
+//! [`AbrController`](bevy_web_video::AbrController), sized to the sprite. Scroll to zoom.
+//!
+//! To test against local fixtures, segment a video next to the built example and serve it:
+//! ```sh-session
+//! $ ffmpeg -i input.mp4 -c:v libx264 -c:a aac -f hls -hls_playlist_type vod \
+//!     examples/hls/fixture/index.m3u8
+//! $ python3 -m http.server -d examples/hls  # now open http://localhost:8000/?src=fixture/index.m3u8
+//! ```
+//! Add `-hls_segment_type fmp4` for fragmented MP4 segments instead of MPEG-TS.
+
+use bevy::{input::mouse::MouseWheel, prelude::*, window::WindowResolution};
+use bevy_web_video::{
+    AbrController, HlsVariantSelection, ListenerEvent, RenditionChanged, RenditionSelection,
+    VideoLoaderSettings, VideoPlayback, VideoSource, WebVideo, WebVideoImage, WebVideoPlugin,
+    events,
+};
+use wasm_bindgen::prelude::*;
+
+const DEFAULT_SRC: &str = "https://test-streams.mux.dev/x36xhzz/x36xhzz.m3u8";
+
+#[wasm_bindgen(start)]
+pub fn main() {
+    console_error_panic_hook::set_once();
+    let mut app = App::new();
+    app.add_plugins((
+        DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                resolution: WindowResolution::new(1280, 720),
+                ..default()
+            }),
+            ..default()
+        }),
+        WebVideoPlugin,
+    ))
+    .add_systems(Startup, setup)
+    .add_systems(Update, (select_variant, zoom, show_variant));
+
+    app.run();
+}
+
+#[derive(Component)]
+struct VariantText;
+
+fn src() -> Option<String> {
+    let search = web_sys::window()?.location().search().ok()?;
+    web_sys::UrlSearchParams::new_with_str(&search)
+        .ok()?
+        .get("src")
+}
+
+fn setup(mut commands: Commands, images: Res<Assets<Image>>) {
+    let image_handle = images.reserve_handle();
+    commands
+        .spawn((
+            WebVideo::from_source(VideoSource::Hls(
+                src().unwrap_or_else(|| DEFAULT_SRC.to_string()),
+            ))
+            .with_settings(VideoLoaderSettings {
+                cross_origin: Some("anonymous".into()),
+                ..default()
+            }),
+            WebVideoImage(image_handle.clone()),
+            HlsVariantSelection::default(),
+            AbrController::default(),
+            VideoPlayback::playing().with_muted(true).with_looping(true),
+            Sprite {
+                image: image_handle,
+                custom_size: Some(Vec2::new(1280.0, 720.0)),
+                ..default()
+            },
+        ))
+        .observe(|error: On<ListenerEvent<events::Error>>| {
+            error!("Video failed: {}", error.payload());
+        })
+        .observe(|changed: On<RenditionChanged>| {
+            info!(
+                "Rendition {:?} -> {} ({} kbps)",
+                changed.previous,
+                changed.rendition,
+                changed.bandwidth / 1000
+            );
+        });
+    commands.spawn((
+        Text::default(),
+        Node {
+            position_type: PositionType::Absolute,
+            top: px(12),
+            left: px(12),
+            ..default()
+        },
+        VariantText,
+    ));
+    commands.spawn(Camera2d);
+}
+
+fn select_variant(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut selections: Query<&mut HlsVariantSelection>,
+) {
+    const DIGITS: [KeyCode; 9] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    let selection = if keys.just_pressed(KeyCode::KeyA) {
+        RenditionSelection::Auto
+    } else if let Some(variant) = DIGITS.iter().position(|key| keys.just_pressed(*key)) {
+        RenditionSelection::Fixed(variant)
+    } else {
+        return;
+    };
+    for mut variant_selection in &mut selections {
+        variant_selection.selection = selection;
+    }
+}
+
+fn show_variant(
+    selections: Query<&HlsVariantSelection, Changed<HlsVariantSelection>>,
+    mut text: Single<&mut Text, With<VariantText>>,
+) {
+    let Some(selection) = selections.iter().next() else {
+        return;
+    };
+    let mut lines = vec![format!("Selection: {:?}", selection.selection)];
+    lines.extend(
+        selection
+            .variants
+            .iter()
+            .enumerate()
+            .map(|(index, variant)| {
+                let resolution = variant
+                    .resolution
+                    .map(|resolution| format!("{}x{}", resolution.x, resolution.y))
+                    .unwrap_or_default();
+                let current = if selection.current == Some(index) {
+                    ">"
+                } else {
+                    " "
+                };
+                format!(
+                    "{current} {}: {resolution} {} kbps",
+                    index + 1,
+                    variant.bandwidth / 1000
+                )
+            }),
+    );
+    text.0 = lines.join("\n");
+}
+
+/// Scale the sprite so the [`AbrController`] sees a different display size
+fn zoom(
+    mut wheel: MessageReader<MouseWheel>,
+    mut transforms: Query<&mut Transform, With<HlsVariantSelection>>,
+) {
+    let scroll: f32 = wheel.read().map(|event| event.y.signum()).sum();
+    if scroll == 0.0 {
+        return;
+    }
+    for mut transform in &mut transforms {
+        transform.scale =
+            (transform.scale * 1.1f32.powf(scroll)).clamp(Vec3::splat(0.1), Vec3::ONE);
+    }
+}
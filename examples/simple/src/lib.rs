@@ -1,15 +1,21 @@
 use bevy::{prelude::*, window::WindowResolution};
 use bevy_web_video::{
-    VideoElement, VideoElementAssetsExt, VideoElementRegistry, WebVideo, WebVideoError,
-    WebVideoPlugin,
+    VIDEO_IMAGE_LABEL, VideoAssetSourcePlugin, VideoElement, VideoLoaderSettings, VideoPlayback,
+    WebVideo, WebVideoPlugin,
 };
 use wasm_bindgen::prelude::*;
 
+const VIDEO_PATH: &str =
+    "video://https://commondatastorage.googleapis.com/gtv-videos-bucket/sample/BigBuckBunny.mp4";
+
 #[wasm_bindgen(start)]
 pub fn main() {
     console_error_panic_hook::set_once();
     let mut app = App::new();
     app.add_plugins((
+        // Videos load from video:// so only the element downloads them,
+        // the source must be registered before the AssetPlugin
+        VideoAssetSourcePlugin,
         DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 resolution: WindowResolution::new(1280, 720),
@@ -24,23 +30,16 @@ pub fn main() {
     app.run();
 }
 
-fn setup(
-    mut commands: Commands,
-    images: Res<Assets<Image>>,
-    mut video_elements: ResMut<Assets<VideoElement>>,
-    mut registry: NonSendMut<VideoElementRegistry>,
-) -> Result<()> {
-    let image_handle = images.reserve_handle();
-    let (video_element_handle, element) = video_elements.new_video(&image_handle, &mut registry);
-    element.set_cross_origin(Some("anonymous"));
-    element.set_src(
-        "https://commondatastorage.googleapis.com/gtv-videos-bucket/sample/BigBuckBunny.mp4",
-    );
-    element.set_muted(true);
-    element.set_loop(true);
-    let _ = element.play().map_err(WebVideoError::from)?;
-    commands.spawn(WebVideo::new(video_element_handle));
-    commands.spawn(Sprite::from_image(image_handle));
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let video = asset_server
+        .load_with_settings::<VideoElement, VideoLoaderSettings>(VIDEO_PATH, |settings| {
+            settings.cross_origin = Some("anonymous".into())
+        });
+    let image = asset_server.load(format!("{VIDEO_PATH}#{VIDEO_IMAGE_LABEL}"));
+    commands.spawn((
+        WebVideo::new(video),
+        VideoPlayback::playing().with_muted(true).with_looping(true),
+        Sprite::from_image(image),
+    ));
     commands.spawn(Camera2d);
-    Ok(())
 }
@@ -7,22 +7,19 @@ use bevy::{
     asset::AssetEventSystems, ecs::entity_disabling::Disabled, prelude::*, tasks::IoTaskPool,
 };
 use bevy_web_video::{
-    EventListenerAppExt, EventSender, ListenerEvent, VideoElement, VideoElementAssetsExt,
-    VideoElementRegistry, WebVideo, WebVideoError, WebVideoPlugin, events, new_event_type,
+    ListenerEvent, PlaybackState, VideoLoaderSettings, VideoPlayback, VideoSource, WebVideo,
+    WebVideoImage, WebVideoPlugin, events,
 };
 
 const DISTANCE: f32 = 5.0;
 
 pub fn plugin(app: &mut App) {
     app.add_plugins(WebVideoPlugin)
-        .add_listener_event::<TimeUpdate>()
         .add_systems(Startup, setup)
         .add_systems(Update, update)
         .add_systems(PostUpdate, handle_image_resize.after(AssetEventSystems));
 }
 
-new_event_type!(TimeUpdate, "timeupdate");
-
 #[derive(Debug)]
 struct Video {
     url: String,
@@ -38,16 +35,11 @@ struct InitialPosition(Vec3);
 #[derive(Component)]
 struct VideoImage(Handle<Image>);
 
-#[allow(clippy::too_many_arguments)]
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     images: Res<Assets<Image>>,
-    mut video_elements: ResMut<Assets<VideoElement>>,
-    ended_event_sender: Res<EventSender<events::Ended>>,
-    timeupdate_event_sender: Res<EventSender<TimeUpdate>>,
-    mut registry: NonSendMut<VideoElementRegistry>,
 ) {
     let (tx, rx) = async_channel::bounded(5);
     commands.insert_resource(VideoReceiver(rx));
@@ -67,13 +59,13 @@ fn setup(
         Vec3::new(0.5, -0.5, 0.0),
     ] {
         let video_image = images.reserve_handle();
-        let (video_element_handle, element) = video_elements.new_video(&video_image, &mut registry);
-        let video_asset_id = video_element_handle.id();
-        let video_entity = commands
+        // Each video gets a WebVideo playing its playlist into this image
+        commands
             .spawn((
                 Disabled,
                 InitialPosition(pos),
-                WebVideo::new(video_element_handle),
+                WebVideoImage(video_image.clone()),
+                VideoPlayback::default().with_muted(true),
                 VideoImage(video_image.clone()),
                 Mesh3d(plane.clone()),
                 MeshMaterial3d(materials.add(StandardMaterial {
@@ -84,22 +76,7 @@ fn setup(
                 Transform::from_translation(pos),
             ))
             .observe(ended_observer)
-            .observe(timeupdate_observer)
-            .id();
-        element.set_cross_origin(Some("anonymous"));
-        element.set_muted(true);
-        ended_event_sender.enable_element_event_observers(
-            video_asset_id,
-            &element,
-            &mut registry,
-            video_entity,
-        );
-        timeupdate_event_sender.enable_element_event_observers(
-            video_asset_id,
-            &element,
-            &mut registry,
-            video_entity,
-        );
+            .observe(timeupdate_observer);
     }
 }
 
@@ -167,38 +144,46 @@ fn ended_observer(listener_event: On<ListenerEvent<events::Ended>>, mut commands
 }
 
 fn timeupdate_observer(
-    listener_event: On<ListenerEvent<TimeUpdate>>,
+    listener_event: On<ListenerEvent<events::TimeUpdate>>,
     mut web_videos: Query<&mut Transform, With<WebVideo>>,
-    registry: NonSend<VideoElementRegistry>,
 ) {
-    if let Some(element) = registry.element(listener_event.asset_id())
-        && let Ok(mut transform) = web_videos.get_mut(listener_event.entity)
-    {
+    let time = listener_event.payload();
+    if let Ok(mut transform) = web_videos.get_mut(listener_event.entity) {
         transform.translation.z =
-            ((element.current_time() / element.duration()) * (DISTANCE - 2.0) as f64) as f32;
+            ((time.current_time / time.duration) * (DISTANCE - 2.0) as f64) as f32;
     }
 }
 
 fn update(
     videos: Res<VideoReceiver>,
-    mut web_videos: Query<(Entity, &WebVideo, &mut Transform, &InitialPosition), With<Disabled>>,
-    registry: NonSend<VideoElementRegistry>,
+    mut web_videos: Query<
+        (Entity, &mut VideoPlayback, &mut Transform, &InitialPosition),
+        With<Disabled>,
+    >,
     mut commands: Commands,
-) -> Result<()> {
-    for (entity, web_video, mut transform, initial_position) in web_videos.iter_mut() {
-        if let Some(element) = registry.element(web_video.asset_id())
-            && let Ok(video) = videos.try_recv()
-        {
+) {
+    for (entity, mut playback, mut transform, initial_position) in web_videos.iter_mut() {
+        if let Ok(video) = videos.try_recv() {
             transform.translation = initial_position.0;
             transform.scale = Vec3::new(
                 video.aspect_ratio.min(1.0),
                 (1.0 / video.aspect_ratio).min(1.0),
                 1.0,
             );
-            element.set_src(&video.url);
-            let _ = element.play().map_err(WebVideoError::from)?;
-
-            commands.entity(entity).remove::<Disabled>(); //XXX defer this to playing event?
+            playback.state = PlaybackState::Playing;
+
+            // Replacing the WebVideo drops the previous video's element and stream
+            commands
+                .entity(entity)
+                .insert(
+                    WebVideo::from_source(VideoSource::Hls(video.url)).with_settings(
+                        VideoLoaderSettings {
+                            cross_origin: Some("anonymous".into()),
+                            ..default()
+                        },
+                    ),
+                )
+                .remove::<Disabled>(); //XXX defer this to playing event?
             // Workaround broken Bevy Disabled handling https://github.com/bevyengine/bevy/issues/18981
             commands.queue(move |world: &mut World| -> Result<()> {
                 let component_ids: Vec<_> = world
@@ -215,5 +200,4 @@ fn update(
             });
         }
     }
-    Ok(())
 }
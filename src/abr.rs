@@ -0,0 +1,358 @@
+//! Adaptive bitrate for segmented streams, picking renditions from the measured throughput,
+//! the buffer level and how large the video's image is on screen
+
+use crate::{VideoElement, VideoElementRegistry, WebVideo, WebVideoImage};
+use bevy::{
+    camera::{primitives::Aabb, visibility::ViewVisibility},
+    ecs::system::SystemParam,
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (
+            reset_abr_choices,
+            update_abr_controllers,
+            fire_rendition_changes,
+        )
+            .chain(),
+    );
+}
+
+/// A rendition of a segmented stream, i.e. an HLS variant or a DASH video representation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AbrRendition {
+    /// Bits per second
+    pub bandwidth: u64,
+    pub size: Option<UVec2>,
+}
+
+/// What an [`AbrStrategy`] picks a rendition from
+#[derive(Clone, Debug)]
+pub struct AbrContext<'a> {
+    /// In manifest order, not empty
+    pub renditions: &'a [AbrRendition],
+    /// Index into `renditions` of the rendition being fetched
+    pub current: Option<usize>,
+    /// Bits per second measured from segment downloads, `None` before the first segment
+    pub throughput: Option<f64>,
+    /// Seconds of video buffered ahead of the current time
+    pub buffer_level: f64,
+    /// Largest projected size in physical pixels of the sprites and meshes showing the video's
+    /// image, zero if none is visible and `None` if no displaying entity is known
+    pub display_size: Option<UVec2>,
+}
+
+/// Picks the rendition an [`AbrController`] fetches, called every frame
+pub trait AbrStrategy: Send + Sync + 'static {
+    /// Index into `context.renditions`, switched to from the next segment
+    fn select(&mut self, context: &AbrContext) -> usize;
+}
+
+/// The highest bandwidth the throughput sustains, up to the smallest rendition covering
+/// the display size. Switches down at once and up only with enough buffered.
+#[derive(Clone, Debug)]
+pub struct DefaultAbrStrategy {
+    /// Fraction of the throughput a rendition's bandwidth may use
+    pub bandwidth_factor: f64,
+    /// Below these seconds buffered, only half the fraction is used
+    pub low_buffer: f64,
+    /// Seconds buffered before switching to a higher bandwidth
+    pub switch_up_buffer: f64,
+}
+
+impl Default for DefaultAbrStrategy {
+    fn default() -> Self {
+        Self {
+            bandwidth_factor: 0.8,
+            low_buffer: 5.0,
+            switch_up_buffer: 10.0,
+        }
+    }
+}
+
+impl AbrStrategy for DefaultAbrStrategy {
+    fn select(&mut self, context: &AbrContext) -> usize {
+        let renditions = context.renditions;
+        let bandwidth = |rendition: usize| renditions[rendition].bandwidth;
+        let mut by_bandwidth: Vec<_> = (0..renditions.len()).collect();
+        by_bandwidth.sort_by_key(|rendition| bandwidth(*rendition));
+
+        // Larger renditions than the display are downscaled, renditions without a size
+        // are not limited
+        let max_bandwidth = context
+            .display_size
+            .and_then(|display| {
+                by_bandwidth.iter().copied().find(|rendition| {
+                    renditions[*rendition]
+                        .size
+                        .is_some_and(|size| size.x >= display.x && size.y >= display.y)
+                })
+            })
+            .map(bandwidth)
+            .unwrap_or(u64::MAX);
+        by_bandwidth.retain(|rendition| bandwidth(*rendition) <= max_bandwidth);
+        let lowest = by_bandwidth[0];
+        let current = context
+            .current
+            .filter(|current| by_bandwidth.contains(current));
+
+        let Some(throughput) = context.throughput else {
+            return current.unwrap_or(lowest);
+        };
+        let factor = if context.buffer_level < self.low_buffer {
+            self.bandwidth_factor * 0.5
+        } else {
+            self.bandwidth_factor
+        };
+        let sustained = by_bandwidth
+            .iter()
+            .rev()
+            .copied()
+            .find(|rendition| bandwidth(*rendition) as f64 <= throughput * factor)
+            .unwrap_or(lowest);
+        match current {
+            Some(current)
+                if bandwidth(sustained) > bandwidth(current)
+                    && context.buffer_level < self.switch_up_buffer =>
+            {
+                current
+            }
+            _ => sustained,
+        }
+    }
+}
+
+/// Picks the renditions of an HLS or DASH [`WebVideo`] with an [`AbrStrategy`] while its
+/// selection is [`RenditionSelection::Auto`](crate::RenditionSelection::Auto).
+///
+/// The display size is projected from the [`Aabb`] of the entities showing the
+/// [`WebVideoImage`] through every active camera: sprites with the `sprite` feature, meshes
+/// with a `StandardMaterial` base color or emissive texture with the `pbr` feature, and
+/// entities marked with [`AbrDisplay`].
+#[derive(Component)]
+pub struct AbrController {
+    strategy: Box<dyn AbrStrategy>,
+    display_size: Option<UVec2>,
+}
+
+impl AbrController {
+    pub fn new(strategy: impl AbrStrategy) -> Self {
+        Self {
+            strategy: Box::new(strategy),
+            display_size: None,
+        }
+    }
+
+    /// Display size of the last selection, see [`AbrContext::display_size`]
+    pub fn display_size(&self) -> Option<UVec2> {
+        self.display_size
+    }
+}
+
+impl Default for AbrController {
+    fn default() -> Self {
+        Self::new(DefaultAbrStrategy::default())
+    }
+}
+
+/// Marks an entity showing an image with a material the [`AbrController`] does not inspect
+#[derive(Component, Clone, Debug)]
+pub struct AbrDisplay(pub Handle<Image>);
+
+/// Fired on a segmented [`WebVideo`] when a different rendition is fetched, including the first
+#[derive(EntityEvent, Clone, Debug)]
+pub struct RenditionChanged {
+    pub entity: Entity,
+    /// Index of an HLS variant or a DASH video representation
+    pub rendition: usize,
+    pub previous: Option<usize>,
+    /// Bits per second
+    pub bandwidth: u64,
+    pub size: Option<UVec2>,
+}
+
+#[derive(SystemParam)]
+struct Displays<'w, 's> {
+    cameras: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+    bounds: Query<
+        'w,
+        's,
+        (
+            &'static Aabb,
+            &'static GlobalTransform,
+            &'static ViewVisibility,
+        ),
+    >,
+    marked: Query<'w, 's, (Entity, &'static AbrDisplay)>,
+    #[cfg(feature = "sprite")]
+    sprites: Query<'w, 's, (Entity, &'static Sprite)>,
+    #[cfg(feature = "pbr")]
+    meshes: Query<'w, 's, (Entity, &'static MeshMaterial3d<StandardMaterial>)>,
+    #[cfg(feature = "pbr")]
+    materials: Res<'w, Assets<StandardMaterial>>,
+}
+
+impl Displays<'_, '_> {
+    /// Largest projected size of the entities showing each of `images`
+    fn sizes(&self, images: &HashSet<AssetId<Image>>) -> HashMap<AssetId<Image>, UVec2> {
+        let mut sizes = HashMap::new();
+        let mut add = |image: AssetId<Image>, entity: Entity| {
+            if !images.contains(&image) {
+                return;
+            }
+            if let Some(size) = self.projected_size(entity) {
+                let largest = sizes.entry(image).or_insert(UVec2::ZERO);
+                *largest = largest.max(size);
+            }
+        };
+        for (entity, display) in &self.marked {
+            add(display.0.id(), entity);
+        }
+        #[cfg(feature = "sprite")]
+        for (entity, sprite) in &self.sprites {
+            add(sprite.image.id(), entity);
+        }
+        #[cfg(feature = "pbr")]
+        for (entity, material) in &self.meshes {
+            let Some(material) = self.materials.get(&material.0) else {
+                continue;
+            };
+            for texture in [&material.base_color_texture, &material.emissive_texture]
+                .into_iter()
+                .flatten()
+            {
+                add(texture.id(), entity);
+            }
+        }
+        sizes
+    }
+
+    /// Physical pixels of the bounding rect of the projected [`Aabb`], clamped to the
+    /// viewport. `None` without bounds, e.g. with `NoFrustumCulling`.
+    fn projected_size(&self, entity: Entity) -> Option<UVec2> {
+        let (aabb, transform, visibility) = self.bounds.get(entity).ok()?;
+        if !visibility.get() {
+            return Some(UVec2::ZERO);
+        }
+        let center = Vec3::from(aabb.center);
+        let half_extents = Vec3::from(aabb.half_extents);
+        let mut largest = Vec2::ZERO;
+        for (camera, camera_transform) in &self.cameras {
+            if !camera.is_active {
+                continue;
+            }
+            let (Some(viewport), Some(scale)) = (
+                camera.logical_viewport_size(),
+                camera.target_scaling_factor(),
+            ) else {
+                continue;
+            };
+            let mut min = Vec2::MAX;
+            let mut max = Vec2::MIN;
+            for corner in 0..8 {
+                let sign = Vec3::new(
+                    if corner & 1 == 0 { -1.0 } else { 1.0 },
+                    if corner & 2 == 0 { -1.0 } else { 1.0 },
+                    if corner & 4 == 0 { -1.0 } else { 1.0 },
+                );
+                let world = transform.transform_point(center + half_extents * sign);
+                // Corners behind the camera are skipped
+                if let Ok(position) = camera.world_to_viewport(camera_transform, world) {
+                    min = min.min(position);
+                    max = max.max(position);
+                }
+            }
+            let size = (max.min(viewport) - min.max(Vec2::ZERO)).max(Vec2::ZERO);
+            largest = largest.max(size * scale);
+        }
+        Some(largest.ceil().as_uvec2())
+    }
+}
+
+fn update_abr_controllers(
+    mut controllers: Query<(&WebVideo, &WebVideoImage, &mut AbrController)>,
+    displays: Displays,
+    mut registry: NonSendMut<VideoElementRegistry>,
+) {
+    if controllers.is_empty() {
+        return;
+    }
+    let images = controllers
+        .iter()
+        .map(|(_, image, _)| image.id())
+        .collect::<HashSet<_>>();
+    let display_sizes = displays.sizes(&images);
+    for (web_video, image, mut controller) in &mut controllers {
+        let asset_id = web_video.asset_id();
+        let buffer_level = registry.buffer_level(asset_id).unwrap_or_default();
+        let Some(stream) = registry.segmented_stream_mut(asset_id) else {
+            continue;
+        };
+        let renditions = stream.renditions();
+        if renditions.is_empty() {
+            continue;
+        }
+        let context = AbrContext {
+            renditions: &renditions,
+            current: stream.current_rendition(),
+            throughput: stream.measured_throughput(),
+            buffer_level,
+            display_size: display_sizes.get(&image.id()).copied(),
+        };
+        // Selected every frame, only the display size is of interest to change detection
+        let controller = controller.bypass_change_detection();
+        controller.display_size = context.display_size;
+        let rendition = controller.strategy.select(&context);
+        stream.set_abr_choice(Some(rendition.min(renditions.len() - 1)));
+    }
+}
+
+/// Return streams of removed controllers to the built-in selection
+fn reset_abr_choices(
+    mut removed: RemovedComponents<AbrController>,
+    videos: Query<&WebVideo>,
+    mut registry: NonSendMut<VideoElementRegistry>,
+) {
+    for entity in removed.read() {
+        if let Ok(web_video) = videos.get(entity)
+            && let Some(stream) = registry.segmented_stream_mut(web_video.asset_id())
+        {
+            stream.set_abr_choice(None);
+        }
+    }
+}
+
+fn fire_rendition_changes(
+    videos: Query<(Entity, &WebVideo)>,
+    registry: NonSend<VideoElementRegistry>,
+    mut fired: Local<HashMap<AssetId<VideoElement>, usize>>,
+    mut commands: Commands,
+) {
+    let mut changes = HashMap::new();
+    for (entity, web_video) in &videos {
+        let asset_id = web_video.asset_id();
+        let change = changes.entry(asset_id).or_insert_with(|| {
+            let stream = registry.segmented_stream(asset_id)?;
+            let rendition = stream.current_rendition()?;
+            let previous = fired.insert(asset_id, rendition);
+            if previous == Some(rendition) {
+                return None;
+            }
+            let details = stream.renditions().get(rendition).copied()?;
+            Some((rendition, previous, details))
+        });
+        if let Some((rendition, previous, details)) = *change {
+            commands.trigger(RenditionChanged {
+                entity,
+                rendition,
+                previous,
+                bandwidth: details.bandwidth,
+                size: details.size,
+            });
+        }
+    }
+    fired.retain(|asset_id, _| changes.contains_key(asset_id));
+}
@@ -0,0 +1,452 @@
+//! Animated images played like videos: GIF, APNG and animated WebP decoded to frames
+//! by the browser's `ImageDecoder`, or in Rust where it is unavailable.
+
+use crate::VideoError;
+use bevy::{math::URect, prelude::*};
+use std::sync::Arc;
+use wasm_bindgen::{Clamped, prelude::*};
+
+mod apng;
+mod gif;
+mod image_decoder;
+mod player;
+
+pub use player::AnimatedImage;
+
+/// Browsers show frames with a delay of 10ms or less for 100ms, which files rely on
+const MIN_FRAME_DURATION: f64 = 0.011;
+const DEFAULT_FRAME_DURATION: f64 = 0.1;
+/// Decoded frames are kept in memory, longer animations are cut off at this many bytes
+const MAX_DECODED_BYTES: usize = 256 << 20;
+
+/// Formats of animated images
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnimatedImageFormat {
+    Gif,
+    /// Animated PNG
+    Png,
+    /// Only decoded by the browser's `ImageDecoder`
+    WebP,
+}
+
+impl AnimatedImageFormat {
+    /// `apng` for APNG, `.png` files are left to the image loader
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "gif" => Some(AnimatedImageFormat::Gif),
+            "apng" => Some(AnimatedImageFormat::Png),
+            "webp" => Some(AnimatedImageFormat::WebP),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AnimatedImageFormat::Gif => "image/gif",
+            AnimatedImageFormat::Png => "image/apng",
+            AnimatedImageFormat::WebP => "image/webp",
+        }
+    }
+
+    fn matches(&self, data: &[u8]) -> bool {
+        match self {
+            AnimatedImageFormat::Gif => data.starts_with(b"GIF8"),
+            AnimatedImageFormat::Png => data.starts_with(b"\x89PNG\r\n\x1a\n"),
+            AnimatedImageFormat::WebP => {
+                data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP")
+            }
+        }
+    }
+}
+
+/// The bytes of an animated image, decoded to frames when it is played by an [`AnimatedImage`]
+#[derive(Debug)]
+pub struct EncodedImage {
+    data: Vec<u8>,
+    format: AnimatedImageFormat,
+}
+
+impl EncodedImage {
+    pub fn new(data: Vec<u8>, format: AnimatedImageFormat) -> Result<Arc<Self>, VideoError> {
+        if !format.matches(&data) {
+            return Err(VideoError::Demux(format!("not a {format:?} image")));
+        }
+        Ok(Arc::new(Self { data, format }))
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn format(&self) -> AnimatedImageFormat {
+        self.format
+    }
+}
+
+/// A decoded frame, shown for `duration` seconds
+struct AnimationFrame {
+    bitmap: web_sys::ImageBitmap,
+    duration: f64,
+}
+
+/// Frames of `image` from the browser's `ImageDecoder`, or from the Rust decoders without it
+async fn decode(image: Arc<EncodedImage>) -> Result<Vec<AnimationFrame>, VideoError> {
+    if image_decoder::is_supported() {
+        match image_decoder::decode(&image).await {
+            Ok(frames) => return Ok(frames),
+            Err(error) if image.format == AnimatedImageFormat::WebP => return Err(error),
+            // GIF and APNG can still be decoded in Rust
+            Err(_) => {}
+        }
+    }
+    let mut decoder: Box<dyn FrameDecoder> = match image.format {
+        AnimatedImageFormat::Gif => Box::new(gif::GifDecoder::new(&image.data)?),
+        AnimatedImageFormat::Png => Box::new(apng::ApngDecoder::new(&image.data)?),
+        AnimatedImageFormat::WebP => {
+            return Err(VideoError::NotSupported);
+        }
+    };
+    let size = decoder.size();
+    let mut frames = Vec::new();
+    // Uploaded as decoded, only the bitmaps are kept
+    while let Some(frame) = decoder.next_frame()? {
+        frames.push(AnimationFrame {
+            bitmap: image_bitmap(&frame.pixels, size).await?,
+            duration: frame.duration,
+        });
+    }
+    Ok(frames)
+}
+
+async fn image_bitmap(pixels: &[u8], size: UVec2) -> Result<web_sys::ImageBitmap, VideoError> {
+    let window = web_sys::window().ok_or_else(|| VideoError::Other("no window".into()))?;
+    let image_data =
+        web_sys::ImageData::new_with_u8_clamped_array_and_sh(Clamped(pixels), size.x, size.y)
+            .map_err(|err| VideoError::from(&err))?;
+    let promise = window
+        .create_image_bitmap_with_image_data(&image_data)
+        .map_err(|err| VideoError::from(&err))?;
+    wasm_bindgen_futures::JsFuture::from(promise)
+        .await
+        .map(JsCast::unchecked_into)
+        .map_err(|err| VideoError::from(&err))
+}
+
+/// Frames of `size` that fit in [`MAX_DECODED_BYTES`], at least one
+fn max_frames(size: UVec2) -> usize {
+    let frame_bytes = size.x as usize * size.y as usize * 4;
+    (MAX_DECODED_BYTES / frame_bytes.max(1)).max(1)
+}
+
+/// Frame duration as browsers show it
+fn frame_duration(seconds: f64) -> f64 {
+    if seconds < MIN_FRAME_DURATION {
+        DEFAULT_FRAME_DURATION
+    } else {
+        seconds
+    }
+}
+
+/// Frames decoded in Rust, one at a time
+trait FrameDecoder {
+    fn size(&self) -> UVec2;
+
+    /// The next full frame, an error if the image has none
+    fn next_frame(&mut self) -> Result<Option<DecodedFrame>, VideoError>;
+}
+
+struct DecodedFrame {
+    /// RGBA, straight alpha
+    pixels: Vec<u8>,
+    /// Seconds
+    duration: f64,
+}
+
+/// What happens to the region of a frame before the next one is drawn
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Disposal {
+    Keep,
+    /// Cleared to transparent
+    Clear,
+    /// Restored to the canvas from before the frame
+    Restore,
+}
+
+/// Builds full frames from the partial frames of GIF and APNG files
+struct Compositor {
+    size: UVec2,
+    canvas: Vec<u8>,
+}
+
+impl Compositor {
+    fn new(size: UVec2) -> Self {
+        Self {
+            size,
+            canvas: vec![0; size.x as usize * size.y as usize * 4],
+        }
+    }
+
+    /// Draw the RGBA `pixels` of `region`, blended over the canvas or replacing it,
+    /// and return the full frame
+    fn draw(&mut self, region: URect, pixels: &[u8], blend: bool, disposal: Disposal) -> Vec<u8> {
+        let previous = (disposal == Disposal::Restore).then(|| self.canvas.clone());
+        let visible = region.intersect(URect::from_corners(UVec2::ZERO, self.size));
+        let width = region.width() as usize;
+        for y in visible.min.y..visible.max.y {
+            for x in visible.min.x..visible.max.x {
+                let source =
+                    ((y - region.min.y) as usize * width + (x - region.min.x) as usize) * 4;
+                let Some(source) = pixels.get(source..source + 4) else {
+                    continue;
+                };
+                let target = (y as usize * self.size.x as usize + x as usize) * 4;
+                let target = &mut self.canvas[target..target + 4];
+                if blend {
+                    blend_over(target, source);
+                } else {
+                    target.copy_from_slice(source);
+                }
+            }
+        }
+        let frame = self.canvas.clone();
+
+        match (disposal, previous) {
+            (Disposal::Clear, _) => {
+                for y in visible.min.y..visible.max.y {
+                    let row = y as usize * self.size.x as usize;
+                    self.canvas
+                        [(row + visible.min.x as usize) * 4..(row + visible.max.x as usize) * 4]
+                        .fill(0);
+                }
+            }
+            (Disposal::Restore, Some(previous)) => self.canvas = previous,
+            _ => {}
+        }
+        frame
+    }
+}
+
+/// Straight alpha `source` pixel over `target`
+fn blend_over(target: &mut [u8], source: &[u8]) {
+    let source_alpha = source[3] as u32;
+    match source_alpha {
+        0 => {}
+        255 => target.copy_from_slice(source),
+        _ => {
+            let target_alpha = target[3] as u32 * (255 - source_alpha) / 255;
+            let alpha = source_alpha + target_alpha;
+            for channel in 0..3 {
+                target[channel] = ((source[channel] as u32 * source_alpha
+                    + target[channel] as u32 * target_alpha)
+                    / alpha) as u8;
+            }
+            target[3] = alpha as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MediaPlayer,
+        player::{PlayerEvent, PlayerInternal},
+    };
+    use wasm_bindgen_futures::JsFuture;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const CLEAR: [u8; 4] = [0; 4];
+
+    fn pixels(pixels: &[[u8; 4]]) -> Vec<u8> {
+        pixels.concat()
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> URect {
+        URect::new(x, y, x + width, y + height)
+    }
+
+    fn frames(mut decoder: impl FrameDecoder) -> Vec<DecodedFrame> {
+        std::iter::from_fn(|| decoder.next_frame().unwrap()).collect()
+    }
+
+    /// 2x2 GIF of a red frame, then a green pixel at the bottom right after `dispose`
+    fn gif_fixture(dispose: ::gif::DisposalMethod) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = ::gif::Encoder::new(&mut data, 2, 2, &[]).unwrap();
+        let mut first = ::gif::Frame::from_palette_pixels(2, 2, [0; 4], [255, 0, 0], None);
+        first.delay = 5;
+        first.dispose = dispose;
+        encoder.write_frame(&first).unwrap();
+        let mut second = ::gif::Frame::from_palette_pixels(1, 1, [0], [0, 255, 0], None);
+        (second.left, second.top) = (1, 1);
+        encoder.write_frame(&second).unwrap();
+        drop(encoder);
+        data
+    }
+
+    /// 2x2 APNG of a red frame, then a green pixel at the bottom right after `dispose`
+    fn apng_fixture(dispose: png::DisposeOp, default_image_shown: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 2, 2);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_animated(2, 0).unwrap();
+        encoder.set_sep_def_img(!default_image_shown).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        if !default_image_shown {
+            writer.write_image_data(&pixels(&[GREEN; 4])).unwrap();
+        }
+        writer.set_frame_delay(1, 20).unwrap();
+        writer.set_dispose_op(dispose).unwrap();
+        writer.write_image_data(&pixels(&[RED; 4])).unwrap();
+        writer.set_frame_dimension(1, 1).unwrap();
+        writer.set_frame_position(1, 1).unwrap();
+        writer.set_frame_delay(0, 0).unwrap();
+        writer.set_blend_op(png::BlendOp::Over).unwrap();
+        writer.write_image_data(&GREEN).unwrap();
+        writer.finish().unwrap();
+        data
+    }
+
+    #[wasm_bindgen_test]
+    fn keeps_drawn_regions() {
+        let mut compositor = Compositor::new(UVec2::splat(2));
+        compositor.draw(rect(0, 0, 2, 2), &pixels(&[RED; 4]), false, Disposal::Keep);
+        let frame = compositor.draw(rect(1, 1, 1, 1), &GREEN, false, Disposal::Keep);
+        assert_eq!(frame, pixels(&[RED, RED, RED, GREEN]));
+    }
+
+    #[wasm_bindgen_test]
+    fn clears_disposed_regions() {
+        let mut compositor = Compositor::new(UVec2::splat(2));
+        compositor.draw(rect(0, 0, 2, 2), &pixels(&[RED; 4]), false, Disposal::Keep);
+        let frame = compositor.draw(rect(0, 0, 1, 1), &GREEN, false, Disposal::Clear);
+        assert_eq!(frame, pixels(&[GREEN, RED, RED, RED]));
+        let frame = compositor.draw(rect(1, 1, 1, 1), &CLEAR, true, Disposal::Keep);
+        assert_eq!(frame, pixels(&[CLEAR, RED, RED, RED]));
+    }
+
+    #[wasm_bindgen_test]
+    fn restores_previous_canvas() {
+        let mut compositor = Compositor::new(UVec2::splat(2));
+        compositor.draw(rect(0, 0, 2, 2), &pixels(&[RED; 4]), false, Disposal::Keep);
+        let frame = compositor.draw(
+            rect(0, 0, 2, 1),
+            &pixels(&[GREEN; 2]),
+            false,
+            Disposal::Restore,
+        );
+        assert_eq!(frame, pixels(&[GREEN, GREEN, RED, RED]));
+        let frame = compositor.draw(rect(1, 1, 1, 1), &CLEAR, true, Disposal::Keep);
+        assert_eq!(frame, pixels(&[RED; 4]));
+    }
+
+    #[wasm_bindgen_test]
+    fn blends_or_replaces_canvas() {
+        let half_green = [0, 255, 0, 128];
+        let mut compositor = Compositor::new(UVec2::ONE);
+        compositor.draw(rect(0, 0, 1, 1), &RED, false, Disposal::Keep);
+        let frame = compositor.draw(rect(0, 0, 1, 1), &half_green, true, Disposal::Keep);
+        assert_eq!(frame, [127, 128, 0, 255]);
+        let frame = compositor.draw(rect(0, 0, 1, 1), &half_green, false, Disposal::Keep);
+        assert_eq!(frame, half_green);
+        // Over a transparent canvas the color is kept
+        let mut compositor = Compositor::new(UVec2::ONE);
+        let frame = compositor.draw(rect(0, 0, 1, 1), &half_green, true, Disposal::Keep);
+        assert_eq!(frame, half_green);
+    }
+
+    #[wasm_bindgen_test]
+    fn clips_regions_outside_canvas() {
+        let mut compositor = Compositor::new(UVec2::splat(2));
+        let frame = compositor.draw(
+            rect(1, 1, 2, 2),
+            &pixels(&[GREEN; 4]),
+            false,
+            Disposal::Clear,
+        );
+        assert_eq!(frame, pixels(&[CLEAR, CLEAR, CLEAR, GREEN]));
+        let frame = compositor.draw(rect(0, 0, 1, 1), &RED, false, Disposal::Keep);
+        assert_eq!(frame, pixels(&[RED, CLEAR, CLEAR, CLEAR]));
+    }
+
+    #[wasm_bindgen_test]
+    fn composes_gif_frames() {
+        let data = gif_fixture(::gif::DisposalMethod::Keep);
+        let decoded = frames(gif::GifDecoder::new(&data).unwrap());
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].pixels, pixels(&[RED; 4]));
+        assert_eq!(decoded[0].duration, 0.05);
+        assert_eq!(decoded[1].pixels, pixels(&[RED, RED, RED, GREEN]));
+        // Delays of zero are shown for the default duration
+        assert_eq!(decoded[1].duration, DEFAULT_FRAME_DURATION);
+
+        let data = gif_fixture(::gif::DisposalMethod::Background);
+        let decoded = frames(gif::GifDecoder::new(&data).unwrap());
+        assert_eq!(decoded[1].pixels, pixels(&[CLEAR, CLEAR, CLEAR, GREEN]));
+    }
+
+    #[wasm_bindgen_test]
+    fn composes_apng_frames() {
+        let data = apng_fixture(png::DisposeOp::None, true);
+        let decoded = frames(apng::ApngDecoder::new(&data).unwrap());
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].pixels, pixels(&[RED; 4]));
+        assert_eq!(decoded[0].duration, 0.05);
+        assert_eq!(decoded[1].pixels, pixels(&[RED, RED, RED, GREEN]));
+
+        let data = apng_fixture(png::DisposeOp::Background, true);
+        let decoded = frames(apng::ApngDecoder::new(&data).unwrap());
+        assert_eq!(decoded[1].pixels, pixels(&[CLEAR, CLEAR, CLEAR, GREEN]));
+
+        // Restoring before the first frame clears it
+        let data = apng_fixture(png::DisposeOp::Previous, true);
+        let decoded = frames(apng::ApngDecoder::new(&data).unwrap());
+        assert_eq!(decoded[1].pixels, pixels(&[CLEAR, CLEAR, CLEAR, GREEN]));
+    }
+
+    #[wasm_bindgen_test]
+    fn skips_apng_default_image() {
+        let data = apng_fixture(png::DisposeOp::None, false);
+        let decoded = frames(apng::ApngDecoder::new(&data).unwrap());
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].pixels, pixels(&[RED; 4]));
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_gif_without_frames() {
+        let mut data = Vec::new();
+        drop(::gif::Encoder::new(&mut data, 2, 2, &[]).unwrap());
+        assert!(matches!(
+            gif::GifDecoder::new(&data),
+            Err(VideoError::Demux(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    async fn fires_decode_errors() {
+        let image = EncodedImage::new(b"GIF89a".to_vec(), AnimatedImageFormat::Gif).unwrap();
+        let mut animated_image = AnimatedImage::new(image);
+        // The decode task completes between ticks
+        for _ in 0..100 {
+            if animated_image.error().is_some() {
+                break;
+            }
+            JsFuture::from(js_sys::Promise::new(&mut |resolve, _| {
+                web_sys::window()
+                    .unwrap()
+                    .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, 10)
+                    .unwrap();
+            }))
+            .await
+            .unwrap();
+            animated_image.tick(0.0);
+        }
+        assert!(matches!(animated_image.error(), Some(VideoError::Demux(_))));
+        assert!(matches!(
+            animated_image.take_events().as_slice(),
+            [PlayerEvent::Error(VideoError::Demux(_))]
+        ));
+    }
+}
@@ -0,0 +1,141 @@
+use super::{Compositor, DecodedFrame, Disposal, FrameDecoder, frame_duration, max_frames};
+use crate::VideoError;
+use bevy::{math::URect, prelude::*};
+use std::io::Cursor;
+
+/// Composes the frames of an APNG one at a time, a PNG without animation has a single frame
+pub(super) struct ApngDecoder<'a> {
+    reader: png::Reader<Cursor<&'a [u8]>>,
+    buffer: Vec<u8>,
+    compositor: Compositor,
+    frame_count: u32,
+    // Index of the next frame in the file
+    index: u32,
+    frames: usize,
+    max_frames: usize,
+}
+
+impl<'a> ApngDecoder<'a> {
+    pub(super) fn new(data: &'a [u8]) -> Result<Self, VideoError> {
+        let mut decoder = png::Decoder::new(Cursor::new(data));
+        decoder.set_transformations(png::Transformations::ALPHA | png::Transformations::STRIP_16);
+        let reader = decoder.read_info().map_err(error)?;
+        let info = reader.info();
+        let size = UVec2::new(info.width, info.height);
+        // Without frame control the default image precedes the animation and isn't part of it
+        let default_image_shown = info.frame_control().is_some();
+        let frame_count = match info.animation_control() {
+            Some(animation_control) => {
+                animation_control.num_frames + u32::from(!default_image_shown)
+            }
+            None => 1,
+        };
+        let buffer = vec![
+            0;
+            reader
+                .output_buffer_size()
+                .ok_or_else(|| error("image too large"))?
+        ];
+        Ok(Self {
+            reader,
+            buffer,
+            compositor: Compositor::new(size),
+            frame_count,
+            index: 0,
+            frames: 0,
+            max_frames: max_frames(size),
+        })
+    }
+}
+
+impl FrameDecoder for ApngDecoder<'_> {
+    fn size(&self) -> UVec2 {
+        self.compositor.size
+    }
+
+    fn next_frame(&mut self) -> Result<Option<DecodedFrame>, VideoError> {
+        loop {
+            if self.index == self.frame_count {
+                if self.frames == 0 {
+                    return Err(error("no frames"));
+                }
+                return Ok(None);
+            }
+            if self.frames == self.max_frames {
+                warn!(
+                    "apng: only the first {} of {} frames fit in memory",
+                    self.max_frames, self.frame_count
+                );
+                return Ok(None);
+            }
+            let index = self.index;
+            self.index += 1;
+            let output = self.reader.next_frame(&mut self.buffer).map_err(error)?;
+            let pixels = rgba(&self.buffer[..output.buffer_size()], output.color_type);
+            let Some(frame_control) = self.reader.info().frame_control().copied() else {
+                if index == 0 && self.frame_count == 1 {
+                    self.frames += 1;
+                    return Ok(Some(DecodedFrame {
+                        pixels,
+                        duration: frame_duration(0.0),
+                    }));
+                }
+                continue;
+            };
+
+            let region = URect::new(
+                frame_control.x_offset,
+                frame_control.y_offset,
+                frame_control.x_offset + frame_control.width,
+                frame_control.y_offset + frame_control.height,
+            );
+            let disposal = match frame_control.dispose_op {
+                png::DisposeOp::None => Disposal::Keep,
+                png::DisposeOp::Background => Disposal::Clear,
+                // Restoring before the first frame clears it
+                png::DisposeOp::Previous if self.frames == 0 => Disposal::Clear,
+                png::DisposeOp::Previous => Disposal::Restore,
+            };
+            let blend = frame_control.blend_op == png::BlendOp::Over;
+            let pixels = self.compositor.draw(region, &pixels, blend, disposal);
+            // A zero denominator means hundredths of a second
+            let denominator = match frame_control.delay_den {
+                0 => 100,
+                denominator => denominator,
+            };
+            self.frames += 1;
+            return Ok(Some(DecodedFrame {
+                pixels,
+                duration: frame_duration(frame_control.delay_num as f64 / denominator as f64),
+            }));
+        }
+    }
+}
+
+fn error(err: impl std::fmt::Display) -> VideoError {
+    VideoError::Demux(format!("apng: {err}"))
+}
+
+/// Expand 8 bit grayscale and RGB output to RGBA
+fn rgba(pixels: &[u8], color_type: png::ColorType) -> Vec<u8> {
+    match color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels
+            .as_chunks::<3>()
+            .0
+            .iter()
+            .flat_map(|[r, g, b]| [*r, *g, *b, 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .as_chunks::<2>()
+            .0
+            .iter()
+            .flat_map(|[value, alpha]| [*value, *value, *value, *alpha])
+            .collect(),
+        // Palettes are expanded by the ALPHA transformation
+        png::ColorType::Grayscale | png::ColorType::Indexed => pixels
+            .iter()
+            .flat_map(|value| [*value, *value, *value, 255])
+            .collect(),
+    }
+}
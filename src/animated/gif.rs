@@ -0,0 +1,72 @@
+use super::{Compositor, DecodedFrame, Disposal, FrameDecoder, frame_duration, max_frames};
+use crate::VideoError;
+use bevy::{math::URect, prelude::*};
+
+/// Composes the frames of a GIF one at a time
+pub(super) struct GifDecoder<'a> {
+    decoder: ::gif::Decoder<&'a [u8]>,
+    compositor: Compositor,
+    frames: usize,
+    max_frames: usize,
+}
+
+impl<'a> GifDecoder<'a> {
+    pub(super) fn new(data: &'a [u8]) -> Result<Self, VideoError> {
+        let mut options = ::gif::DecodeOptions::new();
+        options.set_color_output(::gif::ColorOutput::RGBA);
+        let decoder = options.read_info(data).map_err(error)?;
+        let size = UVec2::new(decoder.width() as u32, decoder.height() as u32);
+        Ok(Self {
+            decoder,
+            compositor: Compositor::new(size),
+            frames: 0,
+            max_frames: max_frames(size),
+        })
+    }
+}
+
+impl FrameDecoder for GifDecoder<'_> {
+    fn size(&self) -> UVec2 {
+        self.compositor.size
+    }
+
+    fn next_frame(&mut self) -> Result<Option<DecodedFrame>, VideoError> {
+        let Some(frame) = self.decoder.read_next_frame().map_err(error)? else {
+            if self.frames == 0 {
+                return Err(VideoError::Demux("gif: no frames".into()));
+            }
+            return Ok(None);
+        };
+        if self.frames == self.max_frames {
+            warn!(
+                "gif: only the first {} frames fit in memory",
+                self.max_frames
+            );
+            return Ok(None);
+        }
+        let (left, top) = (frame.left as u32, frame.top as u32);
+        let region = URect::new(
+            left,
+            top,
+            left + frame.width as u32,
+            top + frame.height as u32,
+        );
+        let disposal = match frame.dispose {
+            ::gif::DisposalMethod::Any | ::gif::DisposalMethod::Keep => Disposal::Keep,
+            ::gif::DisposalMethod::Background => Disposal::Clear,
+            ::gif::DisposalMethod::Previous => Disposal::Restore,
+        };
+        // Transparent pixels show the canvas below
+        let pixels = self.compositor.draw(region, &frame.buffer, true, disposal);
+        self.frames += 1;
+        Ok(Some(DecodedFrame {
+            pixels,
+            // Hundredths of a second
+            duration: frame_duration(frame.delay as f64 / 100.0),
+        }))
+    }
+}
+
+fn error(err: ::gif::DecodingError) -> VideoError {
+    VideoError::Demux(format!("gif: {err}"))
+}
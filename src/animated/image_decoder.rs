@@ -0,0 +1,100 @@
+//! `ImageDecoder` is called through reflection, web-sys only binds it behind
+//! `web_sys_unstable_apis`
+
+use super::{AnimationFrame, EncodedImage, frame_duration, max_frames};
+use crate::VideoError;
+use bevy::prelude::*;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+/// `ImageDecoder` is missing in some browsers, e.g. Firefox before 133
+pub(super) fn is_supported() -> bool {
+    get(&js_sys::global(), "ImageDecoder").is_ok_and(|image_decoder| !image_decoder.is_undefined())
+}
+
+pub(super) async fn decode(image: &EncodedImage) -> Result<Vec<AnimationFrame>, VideoError> {
+    let init = js_sys::Object::new();
+    set(&init, "data", &js_sys::Uint8Array::from(image.data()))
+        .and_then(|_| {
+            set(
+                &init,
+                "type",
+                &JsValue::from_str(image.format().mime_type()),
+            )
+        })
+        .map_err(|err| VideoError::from(&err))?;
+    let decoder = get(&js_sys::global(), "ImageDecoder")
+        .and_then(JsCast::dyn_into::<js_sys::Function>)
+        .and_then(|constructor| {
+            js_sys::Reflect::construct(&constructor, &js_sys::Array::of1(&init))
+        })
+        .map_err(|err| VideoError::from(&err))?;
+    let frames = decode_frames(&decoder).await;
+    let _ = call(&decoder, "close", &[]);
+    frames.map_err(|err| VideoError::from(&err))
+}
+
+async fn decode_frames(decoder: &JsValue) -> Result<Vec<AnimationFrame>, JsValue> {
+    let window = web_sys::window().ok_or_else(|| JsValue::from_str("no window"))?;
+    let tracks = get(decoder, "tracks")?;
+    JsFuture::from(get(&tracks, "ready")?.dyn_into::<js_sys::Promise>()?).await?;
+    let frame_count = get(&tracks, "selectedTrack")
+        .and_then(|track| get(&track, "frameCount"))
+        .ok()
+        .and_then(|frame_count| frame_count.as_f64())
+        .unwrap_or(1.0) as u32;
+
+    let mut frames = Vec::<AnimationFrame>::new();
+    for frame_index in 0..frame_count {
+        // The frame size is known once the first frame is decoded
+        if let Some(first) = frames.first() {
+            let max_frames = max_frames(UVec2::new(first.bitmap.width(), first.bitmap.height()));
+            if frames.len() == max_frames {
+                warn!(
+                    "Animated image has {frame_count} frames, only the first {max_frames} \
+                     fit in memory"
+                );
+                break;
+            }
+        }
+        frames.push(decode_frame(decoder, &window, frame_index).await?);
+    }
+    Ok(frames)
+}
+
+async fn decode_frame(
+    decoder: &JsValue,
+    window: &web_sys::Window,
+    frame_index: u32,
+) -> Result<AnimationFrame, JsValue> {
+    let options = js_sys::Object::new();
+    set(&options, "frameIndex", &JsValue::from(frame_index))?;
+    let result = call(decoder, "decode", &[options.into()])?.dyn_into::<js_sys::Promise>()?;
+    // A VideoFrame
+    let frame = get(&JsFuture::from(result).await?, "image")?;
+    // Microseconds
+    let duration = get(&frame, "duration")?.as_f64().unwrap_or_default() / 1e6;
+    // Frames hold decoder memory until closed, bitmaps are collected
+    let bitmap = match call(window, "createImageBitmap", std::slice::from_ref(&frame)) {
+        Ok(promise) => JsFuture::from(promise.unchecked_into::<js_sys::Promise>()).await,
+        Err(err) => Err(err),
+    };
+    call(&frame, "close", &[])?;
+    Ok(AnimationFrame {
+        bitmap: bitmap?.unchecked_into(),
+        duration: frame_duration(duration),
+    })
+}
+
+fn get(target: &JsValue, key: &str) -> Result<JsValue, JsValue> {
+    js_sys::Reflect::get(target, &JsValue::from_str(key))
+}
+
+fn set(target: &JsValue, key: &str, value: &JsValue) -> Result<bool, JsValue> {
+    js_sys::Reflect::set(target, &JsValue::from_str(key), value)
+}
+
+fn call(target: &JsValue, method: &str, args: &[JsValue]) -> Result<JsValue, JsValue> {
+    let method = get(target, method)?.dyn_into::<js_sys::Function>()?;
+    js_sys::Reflect::apply(&method, target, &args.iter().collect())
+}
@@ -0,0 +1,216 @@
+use super::{AnimationFrame, EncodedImage, decode};
+use crate::{
+    MediaPlayer, SeekMode, VideoError, WebTextureSource,
+    player::{PlayerEvent, PlayerInternal, PlayerState},
+};
+use bevy::prelude::*;
+use std::{cell::RefCell, rc::Rc, sync::Arc};
+
+type DecodeResult = Result<Vec<AnimationFrame>, VideoError>;
+
+/// An animated image played like a video, e.g. a GIF loop.
+///
+/// All frames are decoded up front, by the browser's `ImageDecoder` where available or in
+/// Rust for GIF and APNG, and each is shown for its delay. Frames past 256 MiB of decoded
+/// pixels are dropped with a warning. The repeat count
+/// stored in the file is ignored, looping is set like for videos. Get it with
+/// [`VideoElementRegistry::animated_image`](crate::VideoElementRegistry::animated_image),
+/// playback is controlled through [`MediaPlayer`].
+pub struct AnimatedImage {
+    image: Arc<EncodedImage>,
+    // Set by the decode task
+    decoded: Rc<RefCell<Option<DecodeResult>>>,
+    frames: Vec<AnimationFrame>,
+    // Start time of each frame in seconds
+    frame_starts: Vec<f64>,
+    // Index of the shown frame
+    index: usize,
+    size: UVec2,
+    duration: f64,
+    state: PlayerState,
+}
+
+impl AnimatedImage {
+    pub(crate) fn new(image: Arc<EncodedImage>) -> Self {
+        let decoded = Rc::new(RefCell::new(None));
+        let task_decoded = decoded.clone();
+        let task_image = image.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = decode(task_image).await;
+            *task_decoded.borrow_mut() = Some(result);
+        });
+        Self {
+            image,
+            decoded,
+            frames: Vec::new(),
+            frame_starts: Vec::new(),
+            index: 0,
+            size: UVec2::ZERO,
+            duration: 0.0,
+            state: PlayerState::default(),
+        }
+    }
+
+    pub fn encoded_image(&self) -> &Arc<EncodedImage> {
+        &self.image
+    }
+
+    /// Zero until decoded
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Index of the shown frame
+    pub fn frame_index(&self) -> usize {
+        self.index
+    }
+
+    /// Seek `frames` frames forward, or backward if negative, from the shown frame
+    pub fn step(&mut self, frames: i64) {
+        let index = (self.index as i64 + frames).clamp(0, self.frames.len() as i64 - 1);
+        if let Some(start) = self.frame_starts.get(index as usize) {
+            self.seek(*start, SeekMode::Exact);
+        }
+    }
+
+    fn frame_at(&self, time: f64) -> usize {
+        self.frame_starts
+            .partition_point(|start| *start <= time)
+            .saturating_sub(1)
+    }
+
+    fn show(&mut self, index: usize) {
+        self.index = index;
+        self.state.presented = true;
+    }
+
+    fn load(&mut self, frames: Vec<AnimationFrame>) {
+        let Some(first) = frames.first() else {
+            self.state.fail(VideoError::Demux("no frames".into()));
+            return;
+        };
+        self.size = UVec2::new(first.bitmap.width(), first.bitmap.height());
+        let mut start = 0.0;
+        self.frame_starts = frames
+            .iter()
+            .map(|frame| {
+                let frame_start = start;
+                start += frame.duration;
+                frame_start
+            })
+            .collect();
+        self.duration = start;
+        self.frames = frames;
+
+        // A seek before loading sets the start position, like on an element
+        let state = &mut self.state;
+        state.current_time = state.current_time.min(self.duration);
+        state.loaded = true;
+        state.events.extend([
+            PlayerEvent::LoadedMetadata,
+            PlayerEvent::LoadedData,
+            PlayerEvent::CanPlay,
+        ]);
+        if !state.paused {
+            state.events.push(PlayerEvent::Playing);
+        }
+        self.show(self.frame_at(self.state.current_time));
+    }
+}
+
+impl MediaPlayer for AnimatedImage {
+    /// Zero until decoded
+    fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    /// Total of the frame delays, zero until decoded
+    fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// [`SeekMode::Fast`] stops at the start of the frame at `time`
+    fn seek(&mut self, time: f64, mode: SeekMode) {
+        if !self.state.loaded {
+            self.state.current_time = time.max(0.0);
+            return;
+        }
+        let time = time.clamp(0.0, self.duration);
+        let index = self.frame_at(time);
+        self.state.current_time = match mode {
+            SeekMode::Exact => time,
+            SeekMode::Fast => self.frame_starts[index],
+        };
+        self.state.ended = false;
+        self.state.since_time_update = 0.0;
+        self.show(index);
+        // Frames are decoded already, seeks complete at once
+        self.state.events.extend([
+            PlayerEvent::Seeking,
+            PlayerEvent::TimeUpdate,
+            PlayerEvent::Seeked,
+        ]);
+    }
+
+    fn seeking(&self) -> bool {
+        false
+    }
+}
+
+impl PlayerInternal for AnimatedImage {
+    fn state(&self) -> &PlayerState {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut PlayerState {
+        &mut self.state
+    }
+
+    fn frame(&self) -> Option<WebTextureSource> {
+        self.frames
+            .get(self.index)
+            .filter(|_| self.state.loaded)
+            .map(|frame| WebTextureSource::ImageBitmap(frame.bitmap.clone()))
+    }
+
+    fn tick(&mut self, delta: f64) {
+        if self.state.error.is_some() {
+            return;
+        }
+        if !self.state.loaded {
+            let decoded = self.decoded.borrow_mut().take();
+            match decoded {
+                Some(Ok(frames)) => self.load(frames),
+                Some(Err(error)) => self.state.fail(error),
+                None => {}
+            }
+            return;
+        }
+        if self.state.paused {
+            return;
+        }
+
+        self.state.advance(delta, self.duration);
+        let index = self.frame_at(self.state.current_time);
+        if index != self.index {
+            self.show(index);
+        }
+        if self.state.current_time >= self.duration {
+            if self.state.looping {
+                self.seek(0.0, SeekMode::Exact);
+                self.state.events.push(PlayerEvent::Looped);
+            } else {
+                self.state.end();
+            }
+        }
+    }
+}
+
+impl Drop for AnimatedImage {
+    fn drop(&mut self) {
+        // Frees the bitmaps without waiting for garbage collection
+        for frame in &self.frames {
+            frame.bitmap.close();
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{VideoTextureSettings, registry::asset::new_target_image};
+use bevy::{math::URect, prelude::*};
+
+/// A grid of equally sized tiles in one shared image, each showing a different video.
+///
+/// Give every video the image from [`VideoAtlas::new_image`] as its
+/// [`WebVideoImage`](crate::WebVideoImage) and the settings from
+/// [`VideoAtlas::tile_settings`], then draw tiles with [`VideoAtlas::layout`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct VideoAtlas {
+    tile_size: UVec2,
+    columns: u32,
+    rows: u32,
+}
+
+impl VideoAtlas {
+    /// # Panics
+    ///
+    /// If `columns` is zero.
+    pub fn new(tile_size: UVec2, columns: u32, rows: u32) -> Self {
+        assert!(columns > 0, "VideoAtlas needs at least one column");
+        Self {
+            tile_size,
+            columns,
+            rows,
+        }
+    }
+
+    pub fn size(&self) -> UVec2 {
+        self.tile_size * UVec2::new(self.columns, self.rows)
+    }
+
+    pub fn len(&self) -> usize {
+        (self.columns * self.rows) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Region of the tile at `index`, in row-major order
+    pub fn rect(&self, index: usize) -> URect {
+        let index = index as u32;
+        let min = UVec2::new(index % self.columns, index / self.columns) * self.tile_size;
+        URect::from_corners(min, min + self.tile_size)
+    }
+
+    /// Shared target image, format, sampler and mipmaps are taken from `settings`
+    pub fn new_image(&self, settings: &VideoTextureSettings) -> Image {
+        let size = self.size();
+        new_target_image(size.x, size.y, settings)
+    }
+
+    /// `settings` with the target region set to the tile at `index`
+    pub fn tile_settings(
+        &self,
+        index: usize,
+        settings: VideoTextureSettings,
+    ) -> VideoTextureSettings {
+        VideoTextureSettings {
+            target_rect: Some(self.rect(index)),
+            ..settings
+        }
+    }
+
+    /// Layout for sprites using the atlas image, indices match [`VideoAtlas::rect`]
+    pub fn layout(&self) -> TextureAtlasLayout {
+        TextureAtlasLayout::from_grid(self.tile_size, self.columns, self.rows, None, None)
+    }
+}
@@ -0,0 +1,246 @@
+//! WebCodecs playback: videos demuxed in Rust and decoded with `VideoDecoder`,
+//! presented on their own clock instead of through an `HTMLVideoElement`.
+
+use crate::VideoError;
+use bevy::prelude::*;
+use std::{ops::Range, sync::Arc};
+
+mod mp4;
+mod player;
+mod webm;
+
+pub use player::CodecVideo;
+
+#[cfg(not(web_sys_unstable_apis))]
+compile_error!("The webcodecs feature requires RUSTFLAGS=--cfg=web_sys_unstable_apis");
+
+/// Container formats that can be demuxed for WebCodecs playback
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VideoContainer {
+    /// MP4 or QuickTime, non-fragmented
+    Mp4,
+    WebM,
+}
+
+impl VideoContainer {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "mp4" | "m4v" | "mov" => Some(VideoContainer::Mp4),
+            "webm" => Some(VideoContainer::WebM),
+            _ => None,
+        }
+    }
+}
+
+/// The video track of a demuxed container, ready to be decoded with WebCodecs
+#[derive(Debug)]
+pub struct EncodedVideo {
+    data: Vec<u8>,
+    codec: String,
+    // avcC/hvcC decoder configuration record
+    description: Option<Vec<u8>>,
+    size: UVec2,
+    // In decode order
+    samples: Vec<EncodedSample>,
+    duration: f64,
+    // Presentation times of all frames in microseconds, sorted
+    frame_times: Vec<i64>,
+}
+
+#[derive(Clone, Debug)]
+struct EncodedSample {
+    /// Presentation time in microseconds
+    timestamp: i64,
+    /// Microseconds
+    duration: i64,
+    key: bool,
+    range: Range<usize>,
+}
+
+impl EncodedVideo {
+    pub fn demux(data: Vec<u8>, container: VideoContainer) -> Result<Arc<Self>, VideoError> {
+        let video = match container {
+            VideoContainer::Mp4 => mp4::demux(data)?,
+            VideoContainer::WebM => webm::demux(data)?,
+        };
+        if video.samples.is_empty() {
+            return Err(VideoError::Demux("no video samples".into()));
+        }
+        if video
+            .samples
+            .iter()
+            .any(|sample| sample.range.end > video.data.len())
+        {
+            return Err(VideoError::Demux(
+                "sample data past the end of the file".into(),
+            ));
+        }
+        Ok(Arc::new(video))
+    }
+
+    /// WebCodecs codec string, e.g. `avc1.64001f`
+    pub fn codec(&self) -> &str {
+        &self.codec
+    }
+
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    /// Seconds
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    fn sample_data(&self, sample: &EncodedSample) -> &[u8] {
+        &self.data[sample.range.clone()]
+    }
+
+    /// Sort out durations and the total duration once samples are collected
+    fn finish_samples(&mut self, last_duration: Option<i64>) {
+        let mut timestamps = self
+            .samples
+            .iter()
+            .map(|sample| sample.timestamp)
+            .collect::<Vec<_>>();
+        timestamps.sort_unstable();
+        // Presentation starts at zero
+        let start = timestamps.first().copied().unwrap_or_default();
+        for sample in &mut self.samples {
+            sample.timestamp -= start;
+            if sample.duration <= 0 {
+                let next = timestamps.partition_point(|&t| t <= sample.timestamp + start);
+                sample.duration = timestamps
+                    .get(next)
+                    .map(|next| next - start - sample.timestamp)
+                    .or(last_duration)
+                    .unwrap_or(DEFAULT_FRAME_DURATION);
+            }
+        }
+        self.frame_times = timestamps.iter().map(|time| time - start).collect();
+        self.duration = self
+            .samples
+            .iter()
+            .map(|sample| sample.timestamp + sample.duration)
+            .max()
+            .unwrap_or_default() as f64
+            / 1_000_000.0;
+    }
+}
+
+/// Used for the last frame when the container has no duration for it, 30fps
+const DEFAULT_FRAME_DURATION: i64 = 33_333;
+
+/// Big endian cursor over container bytes
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], VideoError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| VideoError::Demux("unexpected end of data".into()))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), VideoError> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64, VideoError> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as u64))
+    }
+
+    fn u8(&mut self) -> Result<u8, VideoError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, VideoError> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, VideoError> {
+        Ok(self.uint(4)? as u32)
+    }
+
+    fn u64(&mut self) -> Result<u64, VideoError> {
+        self.uint(8)
+    }
+}
+
+/// Codec string and description from an `avcC` record
+fn avc_codec(avcc: &[u8]) -> Result<(String, Option<Vec<u8>>), VideoError> {
+    let [_, profile, compatibility, level, ..] = *avcc else {
+        return Err(VideoError::Demux("truncated avcC".into()));
+    };
+    Ok((
+        format!("avc1.{profile:02x}{compatibility:02x}{level:02x}"),
+        Some(avcc.to_vec()),
+    ))
+}
+
+/// Codec string and description from an `hvcC` record
+fn hevc_codec(fourcc: &str, hvcc: &[u8]) -> Result<(String, Option<Vec<u8>>), VideoError> {
+    if hvcc.len() < 13 {
+        return Err(VideoError::Demux("truncated hvcC".into()));
+    }
+    let profile_space = ["", "A", "B", "C"][(hvcc[1] >> 6) as usize];
+    let tier = if hvcc[1] & 0x20 != 0 { 'H' } else { 'L' };
+    let profile_idc = hvcc[1] & 0x1f;
+    let compatibility = u32::from_be_bytes([hvcc[2], hvcc[3], hvcc[4], hvcc[5]]).reverse_bits();
+    let level = hvcc[12];
+    let mut constraints = hvcc[6..12].to_vec();
+    while constraints.last() == Some(&0) {
+        constraints.pop();
+    }
+    let mut codec =
+        format!("{fourcc}.{profile_space}{profile_idc}.{compatibility:x}.{tier}{level}");
+    for constraint in constraints {
+        codec.push_str(&format!(".{constraint:x}"));
+    }
+    Ok((codec, Some(hvcc.to_vec())))
+}
+
+/// Codec string from an `av1C` record
+fn av1_codec(av1c: &[u8]) -> Result<String, VideoError> {
+    let [_, profile_level, flags, ..] = *av1c else {
+        return Err(VideoError::Demux("truncated av1C".into()));
+    };
+    let profile = profile_level >> 5;
+    let level = profile_level & 0x1f;
+    let tier = if flags & 0x80 != 0 { 'H' } else { 'M' };
+    let bit_depth = match (flags & 0x40 != 0, flags & 0x20 != 0) {
+        (true, true) if profile == 2 => 12,
+        (true, _) => 10,
+        _ => 8,
+    };
+    Ok(format!("av01.{profile}.{level:02}{tier}.{bit_depth:02}"))
+}
+
+/// Codec string for VP9, defaulting to profile 0, level 1, 8 bit
+fn vp9_codec(profile: u8, level: u8, bit_depth: u8) -> String {
+    let level = if level == 0 { 10 } else { level };
+    let bit_depth = if bit_depth == 0 { 8 } else { bit_depth };
+    format!("vp09.{profile:02}.{level:02}.{bit_depth:02}")
+}
@@ -0,0 +1,415 @@
+use super::{ByteReader, EncodedSample, EncodedVideo, av1_codec, avc_codec, hevc_codec, vp9_codec};
+use crate::VideoError;
+use bevy::prelude::*;
+
+/// Demux the first video track of a non-fragmented MP4
+pub(super) fn demux(data: Vec<u8>) -> Result<EncodedVideo, VideoError> {
+    let moov = child(&data, b"moov").ok_or_else(|| error("no moov box"))?;
+    let trak = boxes(moov)
+        .filter(|(fourcc, _)| fourcc == b"trak")
+        .map(|(_, trak)| trak)
+        .find(|trak| {
+            find(trak, &[b"mdia", b"hdlr"]).is_some_and(|hdlr| hdlr.get(8..12) == Some(b"vide"))
+        })
+        .ok_or_else(|| error("no video track"))?;
+
+    let mdhd = find(trak, &[b"mdia", b"mdhd"]).ok_or_else(|| error("no mdhd box"))?;
+    let mut reader = ByteReader::new(mdhd);
+    let version = reader.u8()?;
+    reader.skip(if version == 1 { 3 + 16 } else { 3 + 8 })?;
+    let timescale = reader.u32()? as i64;
+    if timescale == 0 {
+        return Err(error("zero timescale"));
+    }
+
+    let stbl = find(trak, &[b"mdia", b"minf", b"stbl"]).ok_or_else(|| error("no stbl box"))?;
+    let (codec, description, size) =
+        sample_description(child(stbl, b"stsd").ok_or_else(|| error("no stsd box"))?)?;
+    let samples = sample_table(stbl, timescale, data.len())?;
+
+    let mut video = EncodedVideo {
+        data,
+        codec,
+        description,
+        size,
+        samples,
+        duration: 0.0,
+        frame_times: Vec::new(),
+    };
+    video.finish_samples(None);
+    Ok(video)
+}
+
+fn error(message: &str) -> VideoError {
+    VideoError::Demux(format!("mp4: {message}"))
+}
+
+/// Child boxes of `data`, stops at the first malformed box
+fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut reader = ByteReader::new(data);
+    std::iter::from_fn(move || {
+        let start = reader.pos;
+        let size = reader.u32().ok()? as u64;
+        let fourcc: [u8; 4] = reader.bytes(4).ok()?.try_into().ok()?;
+        let size = match size {
+            0 => (data.len() - start) as u64,
+            1 => reader.u64().ok()?,
+            size => size,
+        };
+        let payload_len = (size as usize).checked_sub(reader.pos - start)?;
+        Some((fourcc, reader.bytes(payload_len).ok()?))
+    })
+}
+
+fn child<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data)
+        .find(|(child, _)| child == fourcc)
+        .map(|(_, payload)| payload)
+}
+
+fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter()
+        .try_fold(data, |data, fourcc| child(data, fourcc))
+}
+
+/// Codec string, description and coded size of the first sample entry
+fn sample_description(stsd: &[u8]) -> Result<(String, Option<Vec<u8>>, UVec2), VideoError> {
+    let (fourcc, entry) = boxes(stsd.get(8..).ok_or_else(|| error("truncated stsd"))?)
+        .next()
+        .ok_or_else(|| error("empty stsd"))?;
+    let mut reader = ByteReader::new(entry);
+    reader.skip(24)?;
+    let size = UVec2::new(reader.u16()? as u32, reader.u16()? as u32);
+    let children = entry
+        .get(78..)
+        .ok_or_else(|| error("truncated sample entry"))?;
+    let config = |name: &[u8; 4]| {
+        child(children, name)
+            .ok_or_else(|| error(&format!("no {} box", String::from_utf8_lossy(name))))
+    };
+
+    let (codec, description) = match &fourcc {
+        b"avc1" | b"avc3" => avc_codec(config(b"avcC")?)?,
+        b"hvc1" | b"hev1" => hevc_codec(
+            std::str::from_utf8(&fourcc).unwrap_or("hvc1"),
+            config(b"hvcC")?,
+        )?,
+        b"vp09" => {
+            let vpcc = config(b"vpcC")?;
+            let mut reader = ByteReader::new(vpcc);
+            reader.skip(4)?;
+            let profile = reader.u8()?;
+            let level = reader.u8()?;
+            let bit_depth = reader.u8()? >> 4;
+            (vp9_codec(profile, level, bit_depth), None)
+        }
+        b"vp08" => ("vp8".into(), None),
+        b"av01" => (av1_codec(config(b"av1C")?)?, None),
+        fourcc => {
+            return Err(error(&format!(
+                "unsupported codec {}",
+                String::from_utf8_lossy(fourcc)
+            )));
+        }
+    };
+    Ok((codec, description, size))
+}
+
+/// Reader at the entries of a full box table, with its entry count and version
+fn table<'a>(
+    stbl: &'a [u8],
+    fourcc: &[u8; 4],
+) -> Result<Option<(ByteReader<'a>, u32, u8)>, VideoError> {
+    let Some(data) = child(stbl, fourcc) else {
+        return Ok(None);
+    };
+    let mut reader = ByteReader::new(data);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    let count = reader.u32()?;
+    Ok(Some((reader, count, version)))
+}
+
+/// Samples in decode order from the sample table boxes of a `file_len` bytes file
+fn sample_table(
+    stbl: &[u8],
+    timescale: i64,
+    file_len: usize,
+) -> Result<Vec<EncodedSample>, VideoError> {
+    // Sizes
+    let stsz = child(stbl, b"stsz").ok_or_else(|| error("no stsz box"))?;
+    let mut reader = ByteReader::new(stsz);
+    reader.skip(4)?;
+    let sample_size = reader.u32()?;
+    let sample_count = reader.u32()? as usize;
+    // The count sizes the tables below, so it must be backed by sizes or file data
+    let max_count = match sample_size {
+        0 => reader.remaining() / 4,
+        sample_size => file_len / sample_size as usize,
+    };
+    if sample_count > max_count {
+        return Err(error("sample count exceeds the sample data"));
+    }
+    let sizes = (0..sample_count)
+        .map(|_| {
+            if sample_size == 0 {
+                reader.u32().map(|size| size as usize)
+            } else {
+                Ok(sample_size as usize)
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Offsets, from chunk offsets and samples per chunk
+    let chunk_offsets = if let Some((mut reader, count, _)) = table(stbl, b"stco")? {
+        (0..count)
+            .map(|_| reader.u32().map(|offset| offset as u64))
+            .collect::<Result<Vec<_>, _>>()?
+    } else if let Some((mut reader, count, _)) = table(stbl, b"co64")? {
+        (0..count)
+            .map(|_| reader.u64())
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        return Err(error("no chunk offsets, fragmented MP4 is not supported"));
+    };
+    let (mut reader, count, _) = table(stbl, b"stsc")?.ok_or_else(|| error("no stsc box"))?;
+    let sample_to_chunk = (0..count)
+        .map(|_| {
+            let first_chunk = reader.u32()? as usize;
+            let samples_per_chunk = reader.u32()? as usize;
+            reader.skip(4)?;
+            Ok((first_chunk, samples_per_chunk))
+        })
+        .collect::<Result<Vec<_>, VideoError>>()?;
+    let mut offsets = Vec::with_capacity(sample_count);
+    for (chunk, chunk_offset) in chunk_offsets.iter().enumerate() {
+        let samples_per_chunk = sample_to_chunk
+            .iter()
+            .rev()
+            .find(|(first_chunk, _)| *first_chunk <= chunk + 1)
+            .map(|(_, samples_per_chunk)| *samples_per_chunk)
+            .unwrap_or_default();
+        let mut offset = usize::try_from(*chunk_offset).unwrap_or(usize::MAX);
+        for _ in 0..samples_per_chunk {
+            let Some(size) = sizes.get(offsets.len()) else {
+                break;
+            };
+            offsets.push(offset);
+            offset = offset.saturating_add(*size);
+        }
+    }
+
+    // Decode times, and composition offsets for presentation times
+    let (mut reader, count, _) = table(stbl, b"stts")?.ok_or_else(|| error("no stts box"))?;
+    let mut decode_times = Vec::with_capacity(sample_count);
+    let mut durations = Vec::with_capacity(sample_count);
+    let mut time = 0i64;
+    for _ in 0..count {
+        let run = reader.u32()? as usize;
+        let delta = reader.u32()? as i64;
+        // Runs past the last sample are ignored
+        for _ in 0..run.min(sample_count - decode_times.len()) {
+            decode_times.push(time);
+            durations.push(delta);
+            time = time.saturating_add(delta);
+        }
+    }
+    let mut composition_offsets = Vec::with_capacity(sample_count);
+    if let Some((mut reader, count, version)) = table(stbl, b"ctts")? {
+        for _ in 0..count {
+            let run = reader.u32()?;
+            let offset = if version == 1 {
+                reader.u32()? as i32 as i64
+            } else {
+                reader.u32()? as i64
+            };
+            let run = (run as usize).min(sample_count - composition_offsets.len());
+            composition_offsets.extend(std::iter::repeat_n(offset, run));
+        }
+    }
+    // Without stss every sample is a sync sample
+    let sync_samples = if let Some((mut reader, count, _)) = table(stbl, b"stss")? {
+        Some(
+            (0..count)
+                .map(|_| {
+                    // Sample numbers start at 1
+                    (reader.u32()? as usize)
+                        .checked_sub(1)
+                        .ok_or_else(|| error("zero sync sample number"))
+                })
+                .collect::<Result<std::collections::HashSet<_>, _>>()?,
+        )
+    } else {
+        None
+    };
+
+    let to_micros = |time: i64| (time as i128 * 1_000_000 / timescale as i128) as i64;
+    Ok(sizes
+        .iter()
+        .zip(&offsets)
+        .enumerate()
+        .map(|(index, (size, offset))| {
+            let decode_time = decode_times.get(index).copied().unwrap_or_default();
+            let composition_offset = composition_offsets.get(index).copied().unwrap_or_default();
+            EncodedSample {
+                timestamp: to_micros(decode_time.saturating_add(composition_offset)),
+                duration: to_micros(durations.get(index).copied().unwrap_or_default()),
+                key: sync_samples
+                    .as_ref()
+                    .is_none_or(|sync_samples| sync_samples.contains(&index)),
+                range: *offset..offset.saturating_add(*size),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VideoContainer;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn mp4_box(fourcc: &[u8; 4], payload: &[&[u8]]) -> Vec<u8> {
+        let payload = payload.concat();
+        [
+            &(payload.len() as u32 + 8).to_be_bytes(),
+            &fourcc[..],
+            &payload,
+        ]
+        .concat()
+    }
+
+    /// Full box whose version, flags and fields are the big endian `values`
+    fn full_box(fourcc: &[u8; 4], values: &[u32]) -> Vec<u8> {
+        let payload = values
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect::<Vec<_>>();
+        mp4_box(fourcc, &[&payload])
+    }
+
+    /// Sample data of the fixture, three samples of 10, 20 and 30 bytes
+    fn mdat() -> Vec<u8> {
+        (0..60).collect()
+    }
+
+    /// A 320x240 AVC track at 1000 ticks per second whose sample table boxes are replaced
+    /// by `tables` with the same type, `chunk_offset` is added to the `stco` offset
+    fn mp4_with(tables: &[Vec<u8>], chunk_offset: u32) -> Vec<u8> {
+        let stbl = |mdat_offset: u32| {
+            let avc1 = [
+                &[0; 24][..],
+                &320u16.to_be_bytes(),
+                &240u16.to_be_bytes(),
+                &[0; 50],
+                &mp4_box(b"avcC", &[&[1, 0x64, 0x00, 0x1f, 0xff]]),
+            ]
+            .concat();
+            let mut boxes = vec![
+                mp4_box(
+                    b"stsd",
+                    &[&[0; 4], &1u32.to_be_bytes(), &mp4_box(b"avc1", &[&avc1])],
+                ),
+                full_box(b"stsz", &[0, 0, 3, 10, 20, 30]),
+                full_box(b"stco", &[0, 1, mdat_offset + chunk_offset]),
+                full_box(b"stsc", &[0, 1, 1, 3, 1]),
+                full_box(b"stts", &[0, 1, 3, 40]),
+                full_box(b"stss", &[0, 1, 1]),
+            ];
+            for table in tables {
+                match boxes
+                    .iter_mut()
+                    .find(|stbl_box| stbl_box[4..8] == table[4..8])
+                {
+                    Some(stbl_box) => *stbl_box = table.clone(),
+                    None => boxes.push(table.clone()),
+                }
+            }
+            let hdlr = [&[0; 8][..], b"vide", &[0; 13]].concat();
+            let mdia = [
+                full_box(b"mdhd", &[0, 0, 0, 1000, 0]),
+                mp4_box(b"hdlr", &[&hdlr]),
+                mp4_box(b"minf", &[&mp4_box(b"stbl", &[&boxes.concat()])]),
+            ]
+            .concat();
+            let trak = mp4_box(b"trak", &[&mp4_box(b"mdia", &[&mdia])]);
+            mp4_box(b"moov", &[&trak])
+        };
+        // Sample data follows the moov box
+        let moov = stbl(0);
+        let moov = stbl(moov.len() as u32 + 8);
+        [moov, mp4_box(b"mdat", &[&mdat()])].concat()
+    }
+
+    fn mp4() -> Vec<u8> {
+        mp4_with(&[], 0)
+    }
+
+    #[wasm_bindgen_test]
+    fn demuxes_avc_track() {
+        let video = EncodedVideo::demux(mp4(), VideoContainer::Mp4).unwrap();
+        assert_eq!(video.codec(), "avc1.64001f");
+        assert_eq!(video.size(), UVec2::new(320, 240));
+        assert_eq!(video.frame_count(), 3);
+        assert_eq!(video.duration(), 0.12);
+        let samples = &video.samples;
+        assert_eq!(
+            samples
+                .iter()
+                .map(|sample| sample.timestamp)
+                .collect::<Vec<_>>(),
+            [0, 40_000, 80_000]
+        );
+        assert_eq!(
+            samples.iter().map(|sample| sample.key).collect::<Vec<_>>(),
+            [true, false, false]
+        );
+        assert_eq!(video.sample_data(&samples[1]), &mdat()[10..30]);
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_truncated_files() {
+        let data = mp4();
+        for len in 0..data.len() {
+            assert!(
+                EncodedVideo::demux(data[..len].to_vec(), VideoContainer::Mp4).is_err(),
+                "truncated to {len} bytes"
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_samples_past_the_end() {
+        let data = mp4_with(&[], 1_000_000);
+        assert!(matches!(
+            EncodedVideo::demux(data, VideoContainer::Mp4),
+            Err(VideoError::Demux(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_zero_sync_sample() {
+        let data = mp4_with(&[full_box(b"stss", &[0, 2, 1, 0])], 0);
+        assert!(EncodedVideo::demux(data, VideoContainer::Mp4).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_sample_count_beyond_data() {
+        let data = mp4_with(&[full_box(b"stsz", &[0, 1, u32::MAX])], 0);
+        assert!(EncodedVideo::demux(data, VideoContainer::Mp4).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn caps_runs_at_sample_count() {
+        let data = mp4_with(
+            &[
+                full_box(b"stts", &[0, 1, u32::MAX, 40]),
+                full_box(b"ctts", &[0, 1, u32::MAX, 0]),
+            ],
+            0,
+        );
+        let video = EncodedVideo::demux(data, VideoContainer::Mp4).unwrap();
+        assert_eq!(video.frame_count(), 3);
+        assert_eq!(video.duration(), 0.12);
+    }
+}
@@ -0,0 +1,359 @@
+use super::EncodedVideo;
+use crate::{
+    MediaPlayer, SeekMode, VideoError, WebTextureSource,
+    player::{PlayerEvent, PlayerInternal, PlayerState},
+};
+use bevy::prelude::*;
+use std::{cell::RefCell, collections::VecDeque, rc::Rc, sync::Arc};
+use wasm_bindgen::prelude::*;
+
+/// Frames decoded ahead of the presented one, decoders stall when too many are held open
+const DECODE_AHEAD: usize = 8;
+
+/// Filled by the decoder callbacks
+#[derive(Default)]
+struct DecoderOutput {
+    frames: VecDeque<web_sys::VideoFrame>,
+    error: Option<VideoError>,
+    // All samples up to the end were output
+    flushed: bool,
+    // Bumped on reset so a flush from before it is ignored
+    generation: u64,
+}
+
+impl DecoderOutput {
+    fn clear(&mut self) {
+        for frame in self.frames.drain(..) {
+            frame.close();
+        }
+        self.flushed = false;
+        self.generation += 1;
+    }
+}
+
+/// A video decoded with WebCodecs and presented on its own clock.
+///
+/// Frames are decoded a few ahead of the clock and each is shown from its timestamp,
+/// so seeks land on the exact frame and [`CodecVideo::step`] moves one frame at a time.
+/// The video track is played without audio. Get it with
+/// [`VideoElementRegistry::codec_video`](crate::VideoElementRegistry::codec_video),
+/// playback is controlled through [`MediaPlayer`].
+pub struct CodecVideo {
+    video: Arc<EncodedVideo>,
+    decoder: web_sys::VideoDecoder,
+    output: Rc<RefCell<DecoderOutput>>,
+    _on_output: Closure<dyn FnMut(web_sys::VideoFrame)>,
+    _on_error: Closure<dyn FnMut(JsValue)>,
+    // Decode order index of the next sample to decode
+    next_sample: usize,
+    flushing: bool,
+    frame: Option<web_sys::VideoFrame>,
+    size: UVec2,
+    // Microseconds, set until the frame at this time is shown after loading or seeking
+    seek_target: Option<i64>,
+    state: PlayerState,
+}
+
+impl CodecVideo {
+    pub(crate) fn new(video: Arc<EncodedVideo>) -> Result<Self, VideoError> {
+        let output = Rc::new(RefCell::new(DecoderOutput::default()));
+        let frames_output = output.clone();
+        let on_output = Closure::<dyn FnMut(web_sys::VideoFrame)>::new(move |frame| {
+            frames_output.borrow_mut().frames.push_back(frame);
+        });
+        let error_output = output.clone();
+        let on_error = Closure::<dyn FnMut(JsValue)>::new(move |err: JsValue| {
+            error_output
+                .borrow_mut()
+                .error
+                .get_or_insert_with(|| VideoError::from(&err));
+        });
+        let decoder = web_sys::VideoDecoder::new(&web_sys::VideoDecoderInit::new(
+            on_error.as_ref().unchecked_ref(),
+            on_output.as_ref().unchecked_ref(),
+        ))
+        .map_err(|err| VideoError::from(&err))?;
+
+        let codec_video = Self {
+            size: video.size(),
+            video,
+            decoder,
+            output,
+            _on_output: on_output,
+            _on_error: on_error,
+            next_sample: 0,
+            flushing: false,
+            frame: None,
+            seek_target: Some(0),
+            state: PlayerState {
+                events: vec![PlayerEvent::LoadedMetadata],
+                ..default()
+            },
+        };
+        codec_video.configure()?;
+        Ok(codec_video)
+    }
+
+    fn configure(&self) -> Result<(), VideoError> {
+        let config = web_sys::VideoDecoderConfig::new(self.video.codec());
+        config.set_coded_width(self.video.size().x);
+        config.set_coded_height(self.video.size().y);
+        if let Some(description) = &self.video.description {
+            config.set_description(&js_sys::Uint8Array::from(description.as_slice()));
+        }
+        self.decoder
+            .configure(&config)
+            .map_err(|err| VideoError::from(&err))
+    }
+
+    pub fn encoded_video(&self) -> &Arc<EncodedVideo> {
+        &self.video
+    }
+
+    /// Timestamp of the shown frame in seconds
+    pub fn frame_time(&self) -> Option<f64> {
+        self.frame.as_ref().map(|frame| micros(frame) as f64 / 1e6)
+    }
+
+    /// Seek `frames` frames forward, or backward if negative, from the shown frame
+    pub fn step(&mut self, frames: i64) {
+        let frame_times = &self.video.frame_times;
+        let current = self
+            .frame
+            .as_ref()
+            .map(micros)
+            .unwrap_or((self.state.current_time * 1e6) as i64);
+        let index = frame_times
+            .partition_point(|time| *time <= current)
+            .saturating_sub(1) as i64;
+        let index = (index + frames).clamp(0, frame_times.len() as i64 - 1) as usize;
+        if let Some(time) = frame_times.get(index) {
+            self.seek(*time as f64 / 1e6, SeekMode::Exact);
+        }
+    }
+
+    fn decoded_all(&self) -> bool {
+        self.next_sample == self.video.samples.len() && self.output.borrow().flushed
+    }
+
+    fn decode_ahead(&mut self) -> Result<(), VideoError> {
+        let samples = &self.video.samples;
+        while self.next_sample < samples.len()
+            && self.output.borrow().frames.len() + (self.decoder.decode_queue_size() as usize)
+                < DECODE_AHEAD
+        {
+            let sample = &samples[self.next_sample];
+            let init = web_sys::EncodedVideoChunkInit::new(
+                &js_sys::Uint8Array::from(self.video.sample_data(sample)),
+                sample.timestamp as f64,
+                if sample.key {
+                    web_sys::EncodedVideoChunkType::Key
+                } else {
+                    web_sys::EncodedVideoChunkType::Delta
+                },
+            );
+            init.set_duration(sample.duration as f64);
+            web_sys::EncodedVideoChunk::new(&init)
+                .and_then(|chunk| self.decoder.decode(&chunk))
+                .map_err(|err| VideoError::from(&err))?;
+            self.next_sample += 1;
+        }
+        // Decoders hold back the last frames until flushed
+        if self.next_sample == samples.len() && !self.flushing {
+            self.flushing = true;
+            let output = self.output.clone();
+            let generation = output.borrow().generation;
+            let flush = wasm_bindgen_futures::JsFuture::from(self.decoder.flush());
+            wasm_bindgen_futures::spawn_local(async move {
+                // Rejected when a seek resets the decoder
+                if flush.await.is_ok() && output.borrow().generation == generation {
+                    output.borrow_mut().flushed = true;
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn restart_decoder(&mut self, sample: usize) -> Result<(), VideoError> {
+        self.output.borrow_mut().clear();
+        self.next_sample = sample;
+        self.flushing = false;
+        self.decoder.reset().map_err(|err| VideoError::from(&err))?;
+        self.configure()
+    }
+
+    /// Next decoded frame if it is due at `time` in microseconds
+    fn pop_frame_until(&self, time: i64) -> Option<web_sys::VideoFrame> {
+        let mut output = self.output.borrow_mut();
+        if output
+            .frames
+            .front()
+            .is_some_and(|frame| micros(frame) <= time)
+        {
+            output.frames.pop_front()
+        } else {
+            None
+        }
+    }
+
+    fn show(&mut self, frame: web_sys::VideoFrame) {
+        let size = UVec2::new(frame.display_width(), frame.display_height());
+        if size != self.size {
+            self.size = size;
+            self.state.events.push(PlayerEvent::Resize);
+        }
+        if let Some(previous) = self.frame.replace(frame) {
+            previous.close();
+        }
+        self.state.presented = true;
+    }
+
+    /// Show the last frame at or before `target` once it is decoded
+    fn finish_seek(&mut self, target: i64) {
+        while let Some(frame) = self.pop_frame_until(target) {
+            self.show(frame);
+        }
+        // Frames come out in presentation order, a later one means the target frame is shown
+        let found = !self.output.borrow().frames.is_empty() || self.decoded_all();
+        if !found {
+            return;
+        }
+        if self.frame.is_none() {
+            let Some(frame) = self.output.borrow_mut().frames.pop_front() else {
+                return;
+            };
+            self.show(frame);
+        }
+        self.seek_target = None;
+        let state = &mut self.state;
+        state.presented = true;
+        state.since_time_update = 0.0;
+        if state.loaded {
+            state
+                .events
+                .extend([PlayerEvent::TimeUpdate, PlayerEvent::Seeked]);
+        } else {
+            state.loaded = true;
+            state
+                .events
+                .extend([PlayerEvent::LoadedData, PlayerEvent::CanPlay]);
+        }
+        if !state.paused {
+            state.events.push(PlayerEvent::Playing);
+        }
+    }
+}
+
+impl MediaPlayer for CodecVideo {
+    /// Size of the shown frame, or of the encoded video before the first frame is decoded
+    fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    fn duration(&self) -> f64 {
+        self.video.duration()
+    }
+
+    /// [`SeekMode::Fast`] stops at the keyframe before `time`
+    fn seek(&mut self, time: f64, mode: SeekMode) {
+        let target = (time.clamp(0.0, self.duration()) * 1e6) as i64;
+        // Decoding has to start from the keyframe before the target
+        let samples = &self.video.samples;
+        let key_sample = samples
+            .iter()
+            .rposition(|sample| sample.key && sample.timestamp <= target)
+            .unwrap_or_default();
+        let target = match mode {
+            SeekMode::Exact => target,
+            SeekMode::Fast => samples[key_sample].timestamp,
+        };
+        if let Err(error) = self.restart_decoder(key_sample) {
+            self.state.fail(error);
+            return;
+        }
+        self.state.current_time = target as f64 / 1e6;
+        self.seek_target = Some(target);
+        self.state.ended = false;
+        if self.state.loaded {
+            self.state.events.push(PlayerEvent::Seeking);
+        }
+    }
+
+    fn seeking(&self) -> bool {
+        self.state.loaded && self.seek_target.is_some()
+    }
+}
+
+impl PlayerInternal for CodecVideo {
+    fn state(&self) -> &PlayerState {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut PlayerState {
+        &mut self.state
+    }
+
+    /// Shares the frame, which stays open until the next one is shown after rendering
+    fn frame(&self) -> Option<WebTextureSource> {
+        self.frame
+            .clone()
+            .filter(|_| self.state.loaded)
+            .map(WebTextureSource::VideoFrame)
+    }
+
+    /// Advance the clock by `delta` seconds, decoding and showing frames up to it
+    fn tick(&mut self, delta: f64) {
+        if self.state.error.is_some() {
+            return;
+        }
+        let error = self.output.borrow_mut().error.take();
+        if let Some(error) = error.or_else(|| self.decode_ahead().err()) {
+            self.state.fail(error);
+            return;
+        }
+        if let Some(target) = self.seek_target {
+            self.finish_seek(target);
+            return;
+        }
+        if self.state.paused {
+            return;
+        }
+        // Wait for the decoder rather than skip past frames
+        if self.output.borrow().frames.is_empty() && !self.decoded_all() {
+            return;
+        }
+
+        self.state.advance(delta, self.duration());
+        while let Some(frame) = self.pop_frame_until((self.state.current_time * 1e6) as i64) {
+            self.show(frame);
+        }
+
+        if self.decoded_all()
+            && self.output.borrow().frames.is_empty()
+            && self.state.current_time >= self.duration()
+        {
+            if self.state.looping {
+                self.seek(0.0, SeekMode::Exact);
+                self.state.events.push(PlayerEvent::Looped);
+            } else {
+                self.state.end();
+            }
+        }
+    }
+}
+
+impl Drop for CodecVideo {
+    fn drop(&mut self) {
+        // Stops the callbacks before their closures are dropped, fails if the decoder errored
+        self.decoder.close().ok();
+        self.output.borrow_mut().clear();
+        if let Some(frame) = self.frame.take() {
+            frame.close();
+        }
+    }
+}
+
+/// Timestamp of `frame` in microseconds
+fn micros(frame: &web_sys::VideoFrame) -> i64 {
+    frame.timestamp().unwrap_or_default() as i64
+}
@@ -0,0 +1,408 @@
+use super::{ByteReader, EncodedSample, EncodedVideo, av1_codec, avc_codec, hevc_codec, vp9_codec};
+use crate::VideoError;
+use bevy::prelude::*;
+
+const SEGMENT: u64 = 0x18538067;
+const INFO: u64 = 0x1549A966;
+const TIMECODE_SCALE: u64 = 0x2AD7B1;
+const TRACKS: u64 = 0x1654AE6B;
+const TRACK_ENTRY: u64 = 0xAE;
+const TRACK_NUMBER: u64 = 0xD7;
+const TRACK_TYPE: u64 = 0x83;
+const CODEC_ID: u64 = 0x86;
+const CODEC_PRIVATE: u64 = 0x63A2;
+const DEFAULT_DURATION: u64 = 0x23E383;
+const VIDEO: u64 = 0xE0;
+const PIXEL_WIDTH: u64 = 0xB0;
+const PIXEL_HEIGHT: u64 = 0xBA;
+const CLUSTER: u64 = 0x1F43B675;
+const TIMECODE: u64 = 0xE7;
+const SIMPLE_BLOCK: u64 = 0xA3;
+const BLOCK_GROUP: u64 = 0xA0;
+const BLOCK: u64 = 0xA1;
+const BLOCK_DURATION: u64 = 0x9B;
+const REFERENCE_BLOCK: u64 = 0xFB;
+
+const VIDEO_TRACK_TYPE: u64 = 1;
+
+#[derive(Default)]
+struct Track {
+    number: u64,
+    codec: String,
+    description: Option<Vec<u8>>,
+    size: UVec2,
+    /// Nanoseconds
+    default_duration: Option<u64>,
+}
+
+/// Demux the first video track of a WebM (or Matroska) file
+pub(super) fn demux(data: Vec<u8>) -> Result<EncodedVideo, VideoError> {
+    let mut track = None::<Track>;
+    let mut timecode_scale = 1_000_000;
+    let mut cluster_timecode = 0;
+    let mut samples = Vec::new();
+
+    let mut reader = ByteReader::new(&data);
+    while reader.remaining() > 0 {
+        let (id, size) = element_header(&mut reader)?;
+        // Masters are walked through so unknown sizes (e.g. live recordings) need no special case
+        if matches!(id, SEGMENT | TRACKS | CLUSTER) {
+            continue;
+        }
+        let size = size.ok_or_else(|| error("unknown element size"))?;
+        let start = reader.pos;
+        // A truncated file still plays up to the last complete element
+        let Ok(body) = reader.bytes(size) else {
+            break;
+        };
+        match id {
+            INFO => {
+                for (id, body) in children(body)? {
+                    if id == TIMECODE_SCALE {
+                        timecode_scale = uint(body)?;
+                    }
+                }
+            }
+            TRACK_ENTRY if track.is_none() => {
+                track = track_entry(body)?;
+            }
+            TIMECODE => cluster_timecode = uint(body)?,
+            SIMPLE_BLOCK => {
+                if let Some(track) = &track
+                    && let Some(block) = block(body, start, track, cluster_timecode, true)?
+                {
+                    samples.push(block.into_sample(timecode_scale));
+                }
+            }
+            BLOCK_GROUP => {
+                if let Some(track) = &track
+                    && let Some(block) = block_group(body, start, track, cluster_timecode)?
+                {
+                    samples.push(block.into_sample(timecode_scale));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let track = track.ok_or_else(|| error("no video track"))?;
+    let mut video = EncodedVideo {
+        data,
+        codec: track.codec,
+        description: track.description,
+        size: track.size,
+        samples,
+        duration: 0.0,
+        frame_times: Vec::new(),
+    };
+    video.finish_samples(
+        track
+            .default_duration
+            .map(|duration| duration as i64 / 1000),
+    );
+    Ok(video)
+}
+
+fn error(message: &str) -> VideoError {
+    VideoError::Demux(format!("webm: {message}"))
+}
+
+/// EBML variable length integer, keeping the length marker for element IDs
+fn vint(reader: &mut ByteReader, keep_marker: bool) -> Result<(u64, usize), VideoError> {
+    let first = reader.u8()?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return Err(error("invalid variable length integer"));
+    }
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        first as u64 & (0xff >> len)
+    };
+    for _ in 1..len {
+        value = (value << 8) | reader.u8()? as u64;
+    }
+    Ok((value, len))
+}
+
+/// Element ID and size, `None` for an unknown size
+fn element_header(reader: &mut ByteReader) -> Result<(u64, Option<usize>), VideoError> {
+    let (id, _) = vint(reader, true)?;
+    let (size, len) = vint(reader, false)?;
+    let unknown = size == (1 << (7 * len)) - 1;
+    Ok((id, (!unknown).then_some(size as usize)))
+}
+
+fn children(data: &[u8]) -> Result<Vec<(u64, &[u8])>, VideoError> {
+    let mut reader = ByteReader::new(data);
+    let mut children = Vec::new();
+    while reader.remaining() > 0 {
+        let (id, size) = element_header(&mut reader)?;
+        let size = size.ok_or_else(|| error("unknown element size"))?;
+        children.push((id, reader.bytes(size)?));
+    }
+    Ok(children)
+}
+
+fn uint(data: &[u8]) -> Result<u64, VideoError> {
+    ByteReader::new(data).uint(data.len().min(8))
+}
+
+fn track_entry(data: &[u8]) -> Result<Option<Track>, VideoError> {
+    let mut track = Track::default();
+    let mut track_type = 0;
+    let mut codec_id = String::new();
+    let mut codec_private = None;
+    for (id, body) in children(data)? {
+        match id {
+            TRACK_NUMBER => track.number = uint(body)?,
+            TRACK_TYPE => track_type = uint(body)?,
+            CODEC_ID => codec_id = String::from_utf8_lossy(body).trim_end_matches('\0').into(),
+            CODEC_PRIVATE => codec_private = Some(body),
+            DEFAULT_DURATION => track.default_duration = Some(uint(body)?),
+            VIDEO => {
+                for (id, body) in children(body)? {
+                    match id {
+                        PIXEL_WIDTH => track.size.x = uint(body)? as u32,
+                        PIXEL_HEIGHT => track.size.y = uint(body)? as u32,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if track_type != VIDEO_TRACK_TYPE {
+        return Ok(None);
+    }
+
+    let required_private = || codec_private.ok_or_else(|| error("no CodecPrivate"));
+    (track.codec, track.description) = match codec_id.as_str() {
+        "V_VP8" => ("vp8".into(), None),
+        "V_VP9" => (vp9_from_private(codec_private), None),
+        "V_AV1" => (av1_codec(required_private()?)?, None),
+        "V_MPEG4/ISO/AVC" => avc_codec(required_private()?)?,
+        "V_MPEGH/ISO/HEVC" => hevc_codec("hvc1", required_private()?)?,
+        codec_id => return Err(error(&format!("unsupported codec {codec_id}"))),
+    };
+    Ok(Some(track))
+}
+
+/// VP9 codec features are optional ID, length, value triples
+fn vp9_from_private(codec_private: Option<&[u8]>) -> String {
+    let (mut profile, mut level, mut bit_depth) = (0, 0, 0);
+    let mut reader = ByteReader::new(codec_private.unwrap_or_default());
+    while let (Ok(id), Ok(len)) = (reader.u8(), reader.u8()) {
+        let Ok(value) = reader.bytes(len as usize) else {
+            break;
+        };
+        let value = value.first().copied().unwrap_or_default();
+        match id {
+            1 => profile = value,
+            2 => level = value,
+            3 => bit_depth = value,
+            _ => {}
+        }
+    }
+    vp9_codec(profile, level, bit_depth)
+}
+
+struct Block {
+    /// In timecode scale units
+    timecode: i64,
+    duration: Option<u64>,
+    key: bool,
+    range: std::ops::Range<usize>,
+}
+
+impl Block {
+    fn into_sample(self, timecode_scale: u64) -> EncodedSample {
+        let to_micros = |timecode: i64| timecode * timecode_scale as i64 / 1000;
+        EncodedSample {
+            timestamp: to_micros(self.timecode),
+            duration: self
+                .duration
+                .map(|duration| to_micros(duration as i64))
+                .unwrap_or_default(),
+            key: self.key,
+            range: self.range,
+        }
+    }
+}
+
+/// Block of `track`, `offset` is the position of `data` in the file
+fn block(
+    data: &[u8],
+    offset: usize,
+    track: &Track,
+    cluster_timecode: u64,
+    simple: bool,
+) -> Result<Option<Block>, VideoError> {
+    let mut reader = ByteReader::new(data);
+    let (number, _) = vint(&mut reader, false)?;
+    if number != track.number {
+        return Ok(None);
+    }
+    let relative_timecode = reader.u16()? as i16;
+    let flags = reader.u8()?;
+    if flags & 0x06 != 0 {
+        return Err(error("laced video blocks are not supported"));
+    }
+    Ok(Some(Block {
+        timecode: cluster_timecode as i64 + relative_timecode as i64,
+        duration: None,
+        // SimpleBlocks flag keyframes, a group's block is one unless it has a ReferenceBlock
+        key: !simple || flags & 0x80 != 0,
+        range: offset + reader.pos..offset + data.len(),
+    }))
+}
+
+fn block_group(
+    data: &[u8],
+    offset: usize,
+    track: &Track,
+    cluster_timecode: u64,
+) -> Result<Option<Block>, VideoError> {
+    let mut reader = ByteReader::new(data);
+    let mut block_entry = None;
+    let mut duration = None;
+    let mut key = true;
+    while reader.remaining() > 0 {
+        let (id, size) = element_header(&mut reader)?;
+        let size = size.ok_or_else(|| error("unknown element size"))?;
+        let start = reader.pos;
+        let body = reader.bytes(size)?;
+        match id {
+            BLOCK => block_entry = block(body, offset + start, track, cluster_timecode, false)?,
+            BLOCK_DURATION => duration = Some(uint(body)?),
+            REFERENCE_BLOCK => key = false,
+            _ => {}
+        }
+    }
+    Ok(block_entry.map(|block| Block {
+        duration,
+        key,
+        ..block
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VideoContainer;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    const EBML: u64 = 0x1A45DFA3;
+
+    /// Element with a two byte size
+    fn element(id: u64, body: &[&[u8]]) -> Vec<u8> {
+        let body = body.concat();
+        let id = id.to_be_bytes();
+        let id = &id[id.iter().position(|byte| *byte != 0).unwrap_or(7)..];
+        let size = [0x40 | (body.len() >> 8) as u8, body.len() as u8];
+        [id, &size, &body].concat()
+    }
+
+    fn uint_element(id: u64, value: u64) -> Vec<u8> {
+        element(id, &[&value.to_be_bytes()])
+    }
+
+    /// Block of track 1 at `timecode` with five bytes of `data`
+    fn block_body(timecode: i16, flags: u8, data: u8) -> Vec<u8> {
+        [&[0x81][..], &timecode.to_be_bytes(), &[flags], &[data; 5]].concat()
+    }
+
+    /// A 64x48 VP9 track with a keyframe, a SimpleBlock and a BlockGroup 33ms apart
+    fn webm() -> Vec<u8> {
+        let track = element(
+            TRACK_ENTRY,
+            &[
+                &uint_element(TRACK_NUMBER, 1),
+                &uint_element(TRACK_TYPE, VIDEO_TRACK_TYPE),
+                &element(CODEC_ID, &[b"V_VP9"]),
+                &uint_element(DEFAULT_DURATION, 33_333_333),
+                &element(
+                    VIDEO,
+                    &[
+                        &uint_element(PIXEL_WIDTH, 64),
+                        &uint_element(PIXEL_HEIGHT, 48),
+                    ],
+                ),
+            ],
+        );
+        let cluster = element(
+            CLUSTER,
+            &[
+                &uint_element(TIMECODE, 0),
+                &element(SIMPLE_BLOCK, &[&block_body(0, 0x80, 1)]),
+                &element(SIMPLE_BLOCK, &[&block_body(33, 0, 2)]),
+                &element(
+                    BLOCK_GROUP,
+                    &[
+                        &element(BLOCK, &[&block_body(66, 0, 3)]),
+                        &uint_element(REFERENCE_BLOCK, 1),
+                    ],
+                ),
+            ],
+        );
+        // Segments of live recordings have an unknown size
+        let segment = [
+            &[
+                0x18, 0x53, 0x80, 0x67, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            ][..],
+            &element(INFO, &[&uint_element(TIMECODE_SCALE, 1_000_000)]),
+            &element(TRACKS, &[&track]),
+            &cluster,
+        ]
+        .concat();
+        [element(EBML, &[&[0; 4]]), segment].concat()
+    }
+
+    #[wasm_bindgen_test]
+    fn demuxes_vp9_track() {
+        let video = EncodedVideo::demux(webm(), VideoContainer::WebM).unwrap();
+        assert_eq!(video.codec(), "vp09.00.10.08");
+        assert_eq!(video.size(), UVec2::new(64, 48));
+        assert_eq!(video.frame_count(), 3);
+        let samples = &video.samples;
+        assert_eq!(
+            samples
+                .iter()
+                .map(|sample| sample.timestamp)
+                .collect::<Vec<_>>(),
+            [0, 33_000, 66_000]
+        );
+        assert_eq!(
+            samples.iter().map(|sample| sample.key).collect::<Vec<_>>(),
+            [true, false, false]
+        );
+        // The last frame lasts the track's default duration
+        assert_eq!(samples[2].duration, 33_333);
+        assert_eq!(video.sample_data(&samples[2]), [3; 5]);
+    }
+
+    #[wasm_bindgen_test]
+    fn plays_truncated_files_up_to_the_last_complete_block() {
+        let data = webm();
+        for len in 0..data.len() {
+            if let Ok(video) = EncodedVideo::demux(data[..len].to_vec(), VideoContainer::WebM) {
+                assert!(video.frame_count() <= 3, "truncated to {len} bytes");
+            }
+        }
+        let video =
+            EncodedVideo::demux(data[..data.len() - 1].to_vec(), VideoContainer::WebM).unwrap();
+        assert_eq!(video.frame_count(), 2);
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_laced_blocks() {
+        let data = webm();
+        let laced = block_body(0, 0x82, 1);
+        let position = data
+            .windows(laced.len())
+            .position(|window| window == block_body(0, 0x80, 1))
+            .unwrap();
+        let data = [&data[..position], &laced, &data[position + laced.len()..]].concat();
+        assert!(EncodedVideo::demux(data, VideoContainer::WebM).is_err());
+    }
+}
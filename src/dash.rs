@@ -0,0 +1,586 @@
+//! MPEG-DASH through Media Source Extensions: manifests are parsed and segments scheduled
+//! in Rust, with a source buffer for each of the video and audio tracks.
+
+use crate::{
+    AbrRendition, MediaSourceVideo, RenditionSelection, SourceBufferId, VideoElementRegistry,
+    VideoError, WebVideo,
+    media_source::update_media_sources,
+    segmented::{
+        Downloader, GAP_TOLERANCE, MAX_BUFFER_AHEAD, SegmentedStream, Throughput,
+        first_decode_time, first_timescale, playing_range,
+    },
+};
+use bevy::{platform::collections::HashSet, prelude::*};
+
+mod mpd;
+
+pub use mpd::{
+    DashAdaptationSet, DashContentType, DashManifest, DashPeriod, DashRepresentation, DashSegment,
+    DashSegmentRef, DashSegments,
+};
+
+pub fn plugin(app: &mut App) {
+    app.register_type::<DashRepresentationSelection>()
+        .add_systems(Update, sync_representation_selection)
+        .add_systems(PostUpdate, update_dash_streams.before(update_media_sources));
+}
+
+/// Segment and period boundaries computed from timescales are off by rounding
+const TIME_EPSILON: f64 = 0.001;
+
+/// Representation selection of a DASH [`WebVideo`] entity.
+///
+/// Changes to `selection` are pushed to the [`DashStream`], `representations` and `current`
+/// are pulled from it.
+#[derive(Component, Reflect, Clone, Debug, Default, PartialEq)]
+#[reflect(Component, Default)]
+pub struct DashRepresentationSelection {
+    /// Index of a fixed selection into `representations`
+    pub selection: RenditionSelection,
+    /// Video representations of the current period, empty until the manifest is loaded
+    pub representations: Vec<DashRepresentation>,
+    /// Index into `representations` of the representation being fetched
+    pub current: Option<usize>,
+}
+
+impl DashRepresentationSelection {
+    pub fn fixed(representation: usize) -> Self {
+        Self {
+            selection: RenditionSelection::Fixed(representation),
+            ..default()
+        }
+    }
+
+    fn from_stream(stream: &DashStream) -> Self {
+        Self {
+            selection: stream.selection,
+            representations: stream.representations().to_vec(),
+            current: stream.current_representation(),
+        }
+    }
+}
+
+fn sync_representation_selection(
+    mut videos: Query<(Entity, &WebVideo, &mut DashRepresentationSelection)>,
+    mut registry: NonSendMut<VideoElementRegistry>,
+    // Changed before their stream existed, pushed once it does
+    mut pending: Local<HashSet<Entity>>,
+) {
+    pending.retain(|entity| videos.contains(*entity));
+    for (entity, web_video, mut selection) in &mut videos {
+        let changed = pending.remove(&entity) || selection.is_changed();
+        let Some(stream) = registry.dash_stream_mut(web_video.asset_id()) else {
+            if changed {
+                pending.insert(entity);
+            }
+            continue;
+        };
+        if changed {
+            stream.set_selection(selection.selection);
+        } else if selection.representations != stream.representations()
+            || selection.current != stream.current_representation()
+        {
+            // Compared first to not clone the segment lists every frame
+            *selection = DashRepresentationSelection::from_stream(stream);
+        }
+    }
+}
+
+/// Runs before the media sources so appended segments are queued the same frame
+fn update_dash_streams(mut registry: NonSendMut<VideoElementRegistry>) {
+    registry.update_dash_streams();
+}
+
+/// A DASH manifest played through the [`MediaSourceVideo`] of the asset's element.
+///
+/// Create one with [`VideoSource::Dash`](crate::VideoSource::Dash) or by loading an `.mpd`
+/// asset, and get it with [`VideoElementRegistry::dash_stream`].
+///
+/// The first video and audio adaptation sets of each period are played, each track runs one
+/// request at a time until 30 seconds are buffered ahead. Video representations are switched
+/// by the [`RenditionSelection`], audio plays the first representation. Segments must be
+/// fragmented MP4, addressed by `SegmentTemplate`, `SegmentList` or `SegmentBase`. Only
+/// static manifests are supported and every period must have the same tracks. Failed
+/// requests are retried, then fired as [`events::Error`](crate::events::Error) and the
+/// stream stops.
+pub struct DashStream {
+    url: String,
+    manifest: Option<DashManifest>,
+    selection: RenditionSelection,
+    // Video representation picked by an AbrController for RenditionSelection::Auto
+    abr_choice: Option<usize>,
+    manifest_download: Downloader<()>,
+    tracks: Vec<Track>,
+    throughput: Throughput,
+    ended: bool,
+    failed: bool,
+}
+
+/// The segments of one adaptation set type appended to one source buffer
+struct Track {
+    content_type: DashContentType,
+    buffer: SourceBufferId,
+    downloader: Downloader<Request>,
+    // Period and representation of the last request
+    current: Option<(usize, usize)>,
+    // Period and representation of the last appended initialization segment
+    init: Option<(usize, usize)>,
+    // Timescale of the last initialization segment's first track
+    init_timescale: Option<u32>,
+    last_appended: Option<SegmentId>,
+    ended: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct SegmentId {
+    period: usize,
+    representation: usize,
+    index: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Request {
+    /// The `sidx` box of a `SegmentBase` representation
+    Index(usize, usize),
+    Init(usize, usize),
+    Segment(SegmentId),
+}
+
+impl DashStream {
+    pub(crate) fn new(url: &str) -> Self {
+        let mut manifest_download = Downloader::default();
+        manifest_download.start((), url, None);
+        Self {
+            url: url.to_string(),
+            manifest: None,
+            selection: RenditionSelection::Auto,
+            abr_choice: None,
+            manifest_download,
+            tracks: Vec::new(),
+            throughput: Throughput::default(),
+            ended: false,
+            failed: false,
+        }
+    }
+
+    /// Whether the browser has MSE, DASH does not play without it
+    pub fn is_supported() -> bool {
+        js_sys::Reflect::has(&js_sys::global(), &"MediaSource".into()).unwrap_or(false)
+    }
+
+    /// URL of the manifest
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// `None` until loaded. `SegmentBase` representations are
+    /// [`DashSegments::Indexed`] until their index is fetched.
+    pub fn manifest(&self) -> Option<&DashManifest> {
+        self.manifest.as_ref()
+    }
+
+    /// Video representations of the period being fetched, empty until the manifest is loaded
+    pub fn representations(&self) -> &[DashRepresentation] {
+        let period = self
+            .video_track()
+            .and_then(|track| track.current)
+            .map(|(period, _)| period)
+            .unwrap_or_default();
+        self.manifest
+            .as_ref()
+            .and_then(|manifest| manifest.periods.get(period))
+            .and_then(|period| period.adaptation_set(DashContentType::Video))
+            .map(|adaptation_set| adaptation_set.representations.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Index into [`representations`](Self::representations) of the video representation
+    /// being fetched
+    pub fn current_representation(&self) -> Option<usize> {
+        self.video_track()?
+            .current
+            .map(|(_, representation)| representation)
+    }
+
+    pub fn selection(&self) -> RenditionSelection {
+        self.selection
+    }
+
+    /// Switch video representations from the next segment, buffered segments keep playing
+    pub fn set_selection(&mut self, selection: RenditionSelection) {
+        self.selection = selection;
+    }
+
+    /// Bits per second measured from video segment downloads
+    pub fn throughput(&self) -> Option<f64> {
+        self.throughput.estimate()
+    }
+
+    /// Complete the running requests and start the next ones. Once an error is returned
+    /// the stream stops.
+    pub(crate) fn update(
+        &mut self,
+        media_source: &mut MediaSourceVideo,
+        element: &web_sys::HtmlVideoElement,
+    ) -> Result<(), VideoError> {
+        if self.failed {
+            return Ok(());
+        }
+        let result = self.poll(media_source, element);
+        self.failed = result.is_err();
+        result
+    }
+
+    fn video_track(&self) -> Option<&Track> {
+        self.tracks
+            .iter()
+            .find(|track| track.content_type == DashContentType::Video)
+    }
+
+    fn poll(
+        &mut self,
+        media_source: &mut MediaSourceVideo,
+        element: &web_sys::HtmlVideoElement,
+    ) -> Result<(), VideoError> {
+        if let Some(((), result)) = self.manifest_download.poll() {
+            let (bytes, _) = result?;
+            let text = String::from_utf8(bytes)
+                .map_err(|_| VideoError::Demux("mpd: invalid UTF-8".into()))?;
+            self.set_manifest(DashManifest::parse(&text, &self.url)?, media_source)?;
+        }
+        let Some(manifest) = &mut self.manifest else {
+            return Ok(());
+        };
+
+        let current_time = element.current_time();
+        for track in &mut self.tracks {
+            if let Some((request, result)) = track.downloader.poll() {
+                let (bytes, seconds) = result?;
+                if matches!(request, Request::Segment(_))
+                    && track.content_type == DashContentType::Video
+                {
+                    self.throughput.measure(bytes.len(), seconds);
+                }
+                track.complete(manifest, request, bytes, media_source)?;
+            }
+            track.drop_stale_segment(manifest, media_source, element, current_time);
+            if track.downloader.is_idle() {
+                let selection = match track.content_type {
+                    DashContentType::Video => self.selection.with_choice(self.abr_choice),
+                    _ => RenditionSelection::Fixed(0),
+                };
+                track.schedule(
+                    manifest,
+                    selection,
+                    self.throughput.estimate(),
+                    media_source,
+                    current_time,
+                );
+            }
+        }
+
+        // Segments starting late leave a gap before the first frame
+        if let Some(track) = self.tracks.first()
+            && let Some(first) = media_source.buffered(track.buffer).first()
+            && current_time < first.start
+            && first.start - current_time < GAP_TOLERANCE
+            && !element.seeking()
+        {
+            element.set_current_time(first.start);
+        }
+
+        let ended = self.tracks.iter().all(|track| track.ended);
+        if ended && !self.ended {
+            media_source.end_of_stream();
+        }
+        self.ended = ended;
+        Ok(())
+    }
+
+    /// Add a source buffer for each track, all before the first append as MSE requires
+    fn set_manifest(
+        &mut self,
+        manifest: DashManifest,
+        media_source: &mut MediaSourceVideo,
+    ) -> Result<(), VideoError> {
+        media_source.set_duration(manifest.duration);
+        let first_period = &manifest.periods[0];
+        for content_type in [DashContentType::Video, DashContentType::Audio] {
+            let Some(representation) = first_period
+                .adaptation_set(content_type)
+                .and_then(|adaptation_set| adaptation_set.representations.first())
+            else {
+                continue;
+            };
+            self.tracks.push(Track {
+                content_type,
+                buffer: media_source.add_source_buffer(&representation.full_mime_type())?,
+                downloader: Downloader::default(),
+                current: None,
+                init: None,
+                init_timescale: None,
+                last_appended: None,
+                ended: false,
+            });
+        }
+        if self.tracks.is_empty() {
+            return Err(VideoError::Demux(
+                "mpd: no video or audio AdaptationSet".into(),
+            ));
+        }
+        self.manifest = Some(manifest);
+        Ok(())
+    }
+}
+
+impl Track {
+    fn representation<'a>(
+        &self,
+        manifest: &'a mut DashManifest,
+        period: usize,
+        representation: usize,
+    ) -> Option<&'a mut DashRepresentation> {
+        manifest
+            .periods
+            .get_mut(period)?
+            .adaptation_sets
+            .iter_mut()
+            .find(|adaptation_set| adaptation_set.content_type == self.content_type)?
+            .representations
+            .get_mut(representation)
+    }
+
+    /// Start and end of a segment in seconds from the start of the presentation
+    fn segment_time(&self, manifest: &mut DashManifest, id: SegmentId) -> Option<(f64, f64)> {
+        let period_start = manifest.periods.get(id.period)?.start;
+        let segment = self
+            .representation(manifest, id.period, id.representation)?
+            .segments
+            .segments()
+            .get(id.index)?;
+        let start = period_start + segment.start;
+        Some((start, start + segment.duration))
+    }
+
+    fn complete(
+        &mut self,
+        manifest: &mut DashManifest,
+        request: Request,
+        bytes: Vec<u8>,
+        media_source: &mut MediaSourceVideo,
+    ) -> Result<(), VideoError> {
+        match request {
+            Request::Index(period, representation) => {
+                if let Some(representation) = self.representation(manifest, period, representation)
+                {
+                    let segments = std::mem::take(&mut representation.segments);
+                    representation.segments = segments.resolve_index(&bytes)?;
+                }
+            }
+            Request::Init(period, representation) => {
+                let Some(representation_data) =
+                    self.representation(manifest, period, representation)
+                else {
+                    return Ok(());
+                };
+                let mime_type = representation_data.full_mime_type();
+                if media_source.mime_type(self.buffer) != Some(mime_type.as_str()) {
+                    media_source.change_type(self.buffer, &mime_type);
+                }
+                self.init_timescale = first_timescale(&bytes);
+                media_source.append(self.buffer, bytes);
+                self.init = Some((period, representation));
+            }
+            Request::Segment(id) => {
+                let continuous = self.last_appended.is_some_and(|last| {
+                    last.period == id.period
+                        && last.representation == id.representation
+                        && last.index + 1 == id.index
+                });
+                // Map the segment's media time to its presentation time
+                if !continuous
+                    && let Some((start, _)) = self.segment_time(manifest, id)
+                    && let Some(timescale) = self.init_timescale
+                    && let Some(decode_time) = first_decode_time(&bytes)
+                {
+                    let offset = start - decode_time as f64 / timescale as f64;
+                    media_source.set_timestamp_offset(self.buffer, offset);
+                }
+                media_source.append(self.buffer, bytes);
+                self.last_appended = Some(id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Abort fetching a segment the element seeked away from
+    fn drop_stale_segment(
+        &mut self,
+        manifest: &mut DashManifest,
+        media_source: &MediaSourceVideo,
+        element: &web_sys::HtmlVideoElement,
+        current_time: f64,
+    ) {
+        if !element.seeking()
+            || playing_range(&media_source.buffered(self.buffer), current_time).is_some()
+        {
+            return;
+        }
+        if let Some(Request::Segment(id)) = self.downloader.request().copied()
+            && self
+                .segment_time(manifest, id)
+                .is_some_and(|(start, end)| current_time < start || end <= current_time)
+        {
+            self.downloader.abort();
+        }
+    }
+
+    fn schedule(
+        &mut self,
+        manifest: &mut DashManifest,
+        selection: RenditionSelection,
+        throughput: Option<f64>,
+        media_source: &mut MediaSourceVideo,
+        current_time: f64,
+    ) {
+        // Wait for appends so the buffered ranges include them
+        if media_source.is_busy(self.buffer) {
+            return;
+        }
+        let buffered = media_source.buffered(self.buffer);
+        let playing_range = playing_range(&buffered, current_time);
+        if playing_range
+            .as_ref()
+            .is_some_and(|range| range.end - current_time >= MAX_BUFFER_AHEAD)
+        {
+            return;
+        }
+
+        // Continue after the last appended segment if it extends the playing range,
+        // otherwise fetch the segment at the end of the range or the seeked time
+        let last = self
+            .last_appended
+            .and_then(|id| Some((id, self.segment_time(manifest, id)?)));
+        let time = match (&playing_range, last) {
+            (Some(range), Some((id, (start, end))))
+                if start < range.end + GAP_TOLERANCE && range.start < end + GAP_TOLERANCE =>
+            {
+                let segment_count = self
+                    .representation(manifest, id.period, id.representation)
+                    .map(|representation| representation.segments.segments().len())
+                    .unwrap_or_default();
+                if id.index + 1 < segment_count {
+                    end
+                } else if let Some(next_period) = manifest.periods.get(id.period + 1) {
+                    // Segments may end before their period does
+                    next_period.start.max(end)
+                } else {
+                    self.ended = true;
+                    return;
+                }
+            }
+            (Some(range), _) => range.end + GAP_TOLERANCE,
+            (None, _) => current_time,
+        };
+        self.ended = time >= manifest.duration - TIME_EPSILON;
+        if self.ended {
+            return;
+        }
+        let Some(period) = manifest.period_at(time + TIME_EPSILON) else {
+            return;
+        };
+
+        let Some(adaptation_set) = manifest.periods[period].adaptation_set(self.content_type)
+        else {
+            return;
+        };
+        let bandwidths: Vec<_> = adaptation_set
+            .representations
+            .iter()
+            .map(|representation| representation.bandwidth)
+            .collect();
+        let current = self
+            .current
+            .map(|(_, representation)| representation.min(bandwidths.len().saturating_sub(1)));
+        let Some(representation) = selection.select(&bandwidths, current, throughput) else {
+            return;
+        };
+        self.current = Some((period, representation));
+
+        let period_start = manifest.periods[period].start;
+        let Some(representation_data) = self.representation(manifest, period, representation)
+        else {
+            return;
+        };
+        let (init, segments) = match &representation_data.segments {
+            DashSegments::Indexed { url, index, .. } => {
+                let (url, index) = (url.clone(), index.clone());
+                self.downloader
+                    .start(Request::Index(period, representation), &url, Some(index));
+                return;
+            }
+            DashSegments::List { init, segments } => (init, segments),
+        };
+        let Some(mut index) = representation_data.segments.segment_at(time - period_start) else {
+            return;
+        };
+        // The segment ending a rounding error after the last appended one
+        if self.last_appended
+            == Some(SegmentId {
+                period,
+                representation,
+                index,
+            })
+        {
+            index += 1;
+        }
+        let Some(segment) = segments.get(index) else {
+            return;
+        };
+
+        if let Some(init) = init
+            && self.init != Some((period, representation))
+        {
+            let (url, byte_range) = (init.url.clone(), init.byte_range.clone());
+            self.downloader
+                .start(Request::Init(period, representation), &url, byte_range);
+        } else {
+            let id = SegmentId {
+                period,
+                representation,
+                index,
+            };
+            let (url, byte_range) = (segment.url.clone(), segment.byte_range.clone());
+            self.downloader
+                .start(Request::Segment(id), &url, byte_range);
+        }
+    }
+}
+
+impl SegmentedStream for DashStream {
+    fn renditions(&self) -> Vec<AbrRendition> {
+        self.representations()
+            .iter()
+            .map(|representation| AbrRendition {
+                bandwidth: representation.bandwidth,
+                size: representation.size,
+            })
+            .collect()
+    }
+
+    fn current_rendition(&self) -> Option<usize> {
+        self.current_representation()
+    }
+
+    fn measured_throughput(&self) -> Option<f64> {
+        self.throughput.estimate()
+    }
+
+    fn video_buffer(&self) -> Option<SourceBufferId> {
+        self.video_track().map(|track| track.buffer)
+    }
+
+    fn set_abr_choice(&mut self, rendition: Option<usize>) {
+        self.abr_choice = rendition;
+    }
+}
@@ -0,0 +1,989 @@
+//! DASH manifests, parsed in pure Rust independently of the element and MSE
+
+use crate::VideoError;
+use bevy::prelude::*;
+use roxmltree::Node;
+use std::ops::Range;
+use url::Url;
+
+/// A parsed static `.mpd` manifest
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DashManifest {
+    /// `mediaPresentationDuration` in seconds, otherwise the end of the last period
+    pub duration: f64,
+    /// In presentation order
+    pub periods: Vec<DashPeriod>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DashPeriod {
+    pub id: Option<String>,
+    /// Seconds from the start of the presentation
+    pub start: f64,
+    /// Seconds
+    pub duration: f64,
+    pub adaptation_sets: Vec<DashAdaptationSet>,
+}
+
+#[derive(Reflect, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DashContentType {
+    Video,
+    Audio,
+    Text,
+    #[default]
+    Other,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DashAdaptationSet {
+    pub id: Option<String>,
+    pub content_type: DashContentType,
+    /// RFC 5646 language tag
+    pub language: Option<String>,
+    /// In manifest order
+    pub representations: Vec<DashRepresentation>,
+}
+
+#[derive(Reflect, Clone, Debug, Default, PartialEq)]
+pub struct DashRepresentation {
+    pub id: String,
+    /// Bits per second
+    pub bandwidth: u64,
+    /// e.g. `video/mp4`
+    pub mime_type: String,
+    /// RFC 6381 codecs, e.g. `avc1.64001f`
+    pub codecs: Option<String>,
+    pub size: Option<UVec2>,
+    pub frame_rate: Option<f64>,
+    #[reflect(ignore)]
+    pub segments: DashSegments,
+}
+
+/// How the segments of a [`DashRepresentation`] are addressed
+#[derive(Clone, Debug, PartialEq)]
+pub enum DashSegments {
+    /// Resolved from a `SegmentTemplate` or `SegmentList`
+    List {
+        init: Option<DashSegmentRef>,
+        segments: Vec<DashSegment>,
+    },
+    /// A `SegmentBase` file whose segments are listed by the `sidx` box at `index`,
+    /// see [`DashSegments::resolve_index`]
+    Indexed {
+        /// Absolute URL
+        url: String,
+        init: Option<Range<u64>>,
+        index: Range<u64>,
+        /// Seconds subtracted from the media time to get the period time
+        presentation_time_offset: f64,
+    },
+}
+
+/// A byte range of a URL, e.g. the initialization segment
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DashSegmentRef {
+    /// Absolute URL
+    pub url: String,
+    pub byte_range: Option<Range<u64>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DashSegment {
+    /// Absolute URL
+    pub url: String,
+    pub byte_range: Option<Range<u64>>,
+    /// Seconds from the start of the period
+    pub start: f64,
+    /// Seconds
+    pub duration: f64,
+}
+
+impl Default for DashSegments {
+    fn default() -> Self {
+        DashSegments::List {
+            init: None,
+            segments: Vec::new(),
+        }
+    }
+}
+
+impl DashManifest {
+    /// Parse the manifest `text` fetched from `url`, relative URLs are resolved against it.
+    ///
+    /// Dynamic (live) manifests are [`VideoError::NotSupported`].
+    pub fn parse(text: &str, url: &str) -> Result<Self, VideoError> {
+        let base = Url::parse(url).map_err(|err| error(&format!("invalid URL {url}: {err}")))?;
+        let document = roxmltree::Document::parse(text.trim_start_matches('\u{feff}'))
+            .map_err(|err| error(&err.to_string()))?;
+        let mpd = document.root_element();
+        if mpd.tag_name().name() != "MPD" {
+            return Err(error("missing MPD element"));
+        }
+        if mpd.attribute("type") == Some("dynamic") {
+            return Err(VideoError::NotSupported);
+        }
+        let base = base_url(mpd, &base)?;
+        let presentation_duration = mpd
+            .attribute("mediaPresentationDuration")
+            .map(parse_duration)
+            .transpose()?;
+
+        let period_nodes: Vec<_> = children(mpd, "Period").collect();
+        let mut periods = Vec::with_capacity(period_nodes.len());
+        for (index, period) in period_nodes.iter().enumerate() {
+            let start = match period.attribute("start") {
+                Some(start) => parse_duration(start)?,
+                None => periods
+                    .last()
+                    .map(|previous: &DashPeriod| previous.start + previous.duration)
+                    .unwrap_or_default(),
+            };
+            let next_start = period_nodes
+                .get(index + 1)
+                .and_then(|next| next.attribute("start"))
+                .map(parse_duration)
+                .transpose()?;
+            let duration = match period.attribute("duration") {
+                Some(duration) => parse_duration(duration)?,
+                None => next_start
+                    .or(presentation_duration)
+                    .map(|end| end - start)
+                    .ok_or_else(|| error("Period without duration"))?,
+            };
+            periods.push(parse_period(*period, &base, start, duration)?);
+        }
+        if periods.is_empty() {
+            return Err(error("MPD without Period"));
+        }
+        let duration = presentation_duration.unwrap_or_else(|| {
+            periods
+                .last()
+                .map(|period| period.start + period.duration)
+                .unwrap_or_default()
+        });
+        Ok(DashManifest { duration, periods })
+    }
+
+    /// Index of the period playing at `time` in seconds, the last one past the end
+    pub fn period_at(&self, time: f64) -> Option<usize> {
+        let index = self
+            .periods
+            .partition_point(|period| period.start + period.duration <= time);
+        Some(index.min(self.periods.len().checked_sub(1)?))
+    }
+}
+
+impl DashPeriod {
+    /// First adaptation set with `content_type`
+    pub fn adaptation_set(&self, content_type: DashContentType) -> Option<&DashAdaptationSet> {
+        self.adaptation_sets
+            .iter()
+            .find(|adaptation_set| adaptation_set.content_type == content_type)
+    }
+}
+
+impl DashRepresentation {
+    /// Type for `MediaSource`, e.g. `video/mp4; codecs="avc1.64001f"`
+    pub fn full_mime_type(&self) -> String {
+        match &self.codecs {
+            Some(codecs) => format!("{}; codecs=\"{codecs}\"", self.mime_type),
+            None => self.mime_type.clone(),
+        }
+    }
+}
+
+impl DashSegments {
+    /// Resolve [`DashSegments::Indexed`] with the bytes of its index range, other addressing
+    /// is returned unchanged
+    pub fn resolve_index(self, index_data: &[u8]) -> Result<Self, VideoError> {
+        let DashSegments::Indexed {
+            url,
+            init,
+            index,
+            presentation_time_offset,
+        } = self
+        else {
+            return Ok(self);
+        };
+        let sidx_error = || error("invalid sidx");
+        let mut offset = 0;
+        let sidx = crate::segmented::mp4_boxes(index_data)
+            .find_map(|(fourcc, body)| {
+                offset += body.len() + 8;
+                (&fourcc == b"sidx").then_some(body)
+            })
+            .ok_or_else(sidx_error)?;
+        // Offsets count from the first byte after the sidx box
+        let anchor = index.start + offset as u64;
+
+        let read_u32 = |at: usize| -> Result<u64, VideoError> {
+            Ok(u32::from_be_bytes(
+                sidx.get(at..at + 4)
+                    .ok_or_else(sidx_error)?
+                    .try_into()
+                    .map_err(|_| sidx_error())?,
+            ) as u64)
+        };
+        let read_u64 =
+            |at: usize| -> Result<u64, VideoError> { Ok(read_u32(at)? << 32 | read_u32(at + 4)?) };
+        let version = *sidx.first().ok_or_else(sidx_error)?;
+        let timescale = read_u32(8)?.max(1) as f64;
+        let (earliest_time, first_offset, mut at) = if version == 0 {
+            (read_u32(12)?, read_u32(16)?, 20)
+        } else {
+            (read_u64(12)?, read_u64(20)?, 28)
+        };
+        let reference_count = read_u32(at)? & 0xffff;
+        at += 4;
+
+        let mut start = earliest_time as f64 / timescale - presentation_time_offset;
+        let mut byte = anchor + first_offset;
+        let mut segments = Vec::with_capacity(reference_count as usize);
+        for _ in 0..reference_count {
+            let reference = read_u32(at)?;
+            if reference >> 31 == 1 {
+                // References to further sidx boxes are used by few packagers
+                return Err(VideoError::NotSupported);
+            }
+            let size = reference & 0x7fff_ffff;
+            let duration = read_u32(at + 4)? as f64 / timescale;
+            segments.push(DashSegment {
+                url: url.clone(),
+                byte_range: Some(byte..byte + size),
+                start,
+                duration,
+            });
+            start += duration;
+            byte += size;
+            at += 12;
+        }
+        Ok(DashSegments::List {
+            init: init.map(|init| DashSegmentRef {
+                url,
+                byte_range: Some(init),
+            }),
+            segments,
+        })
+    }
+
+    /// Listed segments, empty for [`DashSegments::Indexed`]
+    pub fn segments(&self) -> &[DashSegment] {
+        match self {
+            DashSegments::List { segments, .. } => segments,
+            DashSegments::Indexed { .. } => &[],
+        }
+    }
+
+    /// Index of the segment playing at `time` in seconds from the period start, the last
+    /// one past the end
+    pub fn segment_at(&self, time: f64) -> Option<usize> {
+        let segments = self.segments();
+        let index = segments
+            .partition_point(|segment| segment.start + segment.duration <= time + TIME_EPSILON);
+        Some(index.min(segments.len().checked_sub(1)?))
+    }
+}
+
+/// Segment boundaries computed from timescales are off by rounding
+const TIME_EPSILON: f64 = 0.001;
+
+fn error(message: &str) -> VideoError {
+    VideoError::Demux(format!("mpd: {message}"))
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// `node`'s `BaseURL` resolved against `base`
+fn base_url(node: Node, base: &Url) -> Result<Url, VideoError> {
+    match child(node, "BaseURL").and_then(|base_url| base_url.text()) {
+        Some(url) => base
+            .join(url.trim())
+            .map_err(|err| error(&format!("invalid BaseURL {url}: {err}"))),
+        None => Ok(base.clone()),
+    }
+}
+
+fn resolve(base: &Url, url: &str) -> Result<String, VideoError> {
+    base.join(url)
+        .map(String::from)
+        .map_err(|err| error(&format!("invalid URL {url}: {err}")))
+}
+
+fn parse_period(
+    period: Node,
+    base: &Url,
+    start: f64,
+    duration: f64,
+) -> Result<DashPeriod, VideoError> {
+    let base = base_url(period, base)?;
+    let mut adaptation_sets = Vec::new();
+    for adaptation_set in children(period, "AdaptationSet") {
+        let set_base = base_url(adaptation_set, &base)?;
+        let mut representations = Vec::new();
+        for representation in children(adaptation_set, "Representation") {
+            let context = Context {
+                nodes: [Some(representation), Some(adaptation_set), Some(period)],
+                base: base_url(representation, &set_base)?,
+                period_duration: duration,
+            };
+            representations.push(context.representation()?);
+        }
+        let content_type = adaptation_set
+            .attribute("contentType")
+            .or(adaptation_set.attribute("mimeType"))
+            .or_else(|| {
+                representations
+                    .first()
+                    .map(|representation| representation.mime_type.as_str())
+            })
+            .map(content_type)
+            .unwrap_or_default();
+        adaptation_sets.push(DashAdaptationSet {
+            id: adaptation_set.attribute("id").map(str::to_string),
+            content_type,
+            language: adaptation_set.attribute("lang").map(str::to_string),
+            representations,
+        });
+    }
+    Ok(DashPeriod {
+        id: period.attribute("id").map(str::to_string),
+        start,
+        duration,
+        adaptation_sets,
+    })
+}
+
+/// `contentType` or the type of a MIME type
+fn content_type(content_type: &str) -> DashContentType {
+    match content_type.split('/').next() {
+        Some("video") => DashContentType::Video,
+        Some("audio") => DashContentType::Audio,
+        Some("text") => DashContentType::Text,
+        _ => DashContentType::Other,
+    }
+}
+
+/// A representation with its adaptation set and period, attributes and segment addressing
+/// are inherited from the most specific of them
+struct Context<'a, 'input> {
+    /// Representation, adaptation set and period
+    nodes: [Option<Node<'a, 'input>>; 3],
+    base: Url,
+    period_duration: f64,
+}
+
+impl<'a, 'input> Context<'a, 'input> {
+    fn representation(&self) -> Result<DashRepresentation, VideoError> {
+        let id = self.nodes[0]
+            .and_then(|representation| representation.attribute("id"))
+            .unwrap_or_default();
+        let size = self
+            .number(&[], "width")
+            .zip(self.number(&[], "height"))
+            .map(|(width, height)| UVec2::new(width as u32, height as u32));
+        Ok(DashRepresentation {
+            id: id.to_string(),
+            bandwidth: self
+                .number(&[], "bandwidth")
+                .ok_or_else(|| error("Representation without bandwidth"))?,
+            mime_type: self
+                .attribute(&[], "mimeType")
+                .ok_or_else(|| error("Representation without mimeType"))?
+                .to_string(),
+            codecs: self.attribute(&[], "codecs").map(str::to_string),
+            size,
+            frame_rate: self.attribute(&[], "frameRate").and_then(parse_frame_rate),
+            segments: self.segments(id)?,
+        })
+    }
+
+    /// Most specific `name` attribute of the element at `path` below the nodes
+    fn attribute(&self, path: &[&str], name: &str) -> Option<&'a str> {
+        self.elements(path)
+            .find_map(|element| element.attribute(name))
+    }
+
+    fn number(&self, path: &[&str], name: &str) -> Option<u64> {
+        self.attribute(path, name)?.parse().ok()
+    }
+
+    /// Elements at `path` below the nodes, most specific first
+    fn elements<'b>(&'b self, path: &'b [&'b str]) -> impl Iterator<Item = Node<'a, 'input>> + 'b {
+        self.nodes.iter().flatten().filter_map(move |node| {
+            path.iter()
+                .try_fold(*node, |element, name| child(element, name))
+        })
+    }
+
+    /// Most specific element at `path`
+    fn element(&self, path: &[&str]) -> Option<Node<'a, 'input>> {
+        self.elements(path).next()
+    }
+
+    fn segments(&self, representation_id: &str) -> Result<DashSegments, VideoError> {
+        if self.element(&["SegmentTemplate"]).is_some() {
+            self.template_segments(representation_id)
+        } else if self.element(&["SegmentList"]).is_some() {
+            self.list_segments()
+        } else if let Some(index) = self.attribute(&["SegmentBase"], "indexRange") {
+            Ok(DashSegments::Indexed {
+                url: self.base.to_string(),
+                init: self
+                    .attribute(&["SegmentBase", "Initialization"], "range")
+                    .map(parse_range)
+                    .transpose()?,
+                index: parse_range(index)?,
+                presentation_time_offset: self.presentation_time_offset(&["SegmentBase"]),
+            })
+        } else if self.nodes[0]
+            .is_some_and(|representation| child(representation, "BaseURL").is_some())
+        {
+            // A single self-initializing segment
+            Ok(DashSegments::List {
+                init: None,
+                segments: vec![DashSegment {
+                    url: self.base.to_string(),
+                    byte_range: None,
+                    start: 0.0,
+                    duration: self.period_duration,
+                }],
+            })
+        } else {
+            Err(error("Representation without segments"))
+        }
+    }
+
+    fn presentation_time_offset(&self, path: &[&str]) -> f64 {
+        let timescale = self.number(path, "timescale").unwrap_or(1).max(1) as f64;
+        self.number(path, "presentationTimeOffset").unwrap_or(0) as f64 / timescale
+    }
+
+    fn template_segments(&self, representation_id: &str) -> Result<DashSegments, VideoError> {
+        const TEMPLATE: &[&str] = &["SegmentTemplate"];
+        let bandwidth = self.number(&[], "bandwidth").unwrap_or_default();
+        let fill = |template: &str, number: u64, time: u64| {
+            let url = fill_template(template, representation_id, number, bandwidth, time)?;
+            resolve(&self.base, &url)
+        };
+        let init = self
+            .attribute(TEMPLATE, "initialization")
+            .map(|template| {
+                Ok::<_, VideoError>(DashSegmentRef {
+                    url: fill(template, 0, 0)?,
+                    byte_range: None,
+                })
+            })
+            .transpose()?;
+        let media = self
+            .attribute(TEMPLATE, "media")
+            .ok_or_else(|| error("SegmentTemplate without media"))?;
+        let segments = self
+            .timeline(TEMPLATE)?
+            .into_iter()
+            .map(|(number, time, start, duration)| {
+                Ok(DashSegment {
+                    url: fill(media, number, time)?,
+                    byte_range: None,
+                    start,
+                    duration,
+                })
+            })
+            .collect::<Result<_, VideoError>>()?;
+        Ok(DashSegments::List { init, segments })
+    }
+
+    fn list_segments(&self) -> Result<DashSegments, VideoError> {
+        const LIST: &[&str] = &["SegmentList"];
+        let init = match self.element(&["SegmentList", "Initialization"]) {
+            Some(initialization) => Some(DashSegmentRef {
+                url: match initialization.attribute("sourceURL") {
+                    Some(url) => resolve(&self.base, url)?,
+                    None => self.base.to_string(),
+                },
+                byte_range: initialization
+                    .attribute("range")
+                    .map(parse_range)
+                    .transpose()?,
+            }),
+            None => None,
+        };
+        let segment_urls: Vec<_> = self
+            .element(LIST)
+            .map(|list| children(list, "SegmentURL").collect())
+            .unwrap_or_default();
+        let timeline = self.timeline(LIST)?;
+        let segments = segment_urls
+            .iter()
+            .zip(timeline)
+            .map(|(segment_url, (_, _, start, duration))| {
+                Ok(DashSegment {
+                    url: match segment_url.attribute("media") {
+                        Some(url) => resolve(&self.base, url)?,
+                        None => self.base.to_string(),
+                    },
+                    byte_range: segment_url
+                        .attribute("mediaRange")
+                        .map(parse_range)
+                        .transpose()?,
+                    start,
+                    duration,
+                })
+            })
+            .collect::<Result<_, VideoError>>()?;
+        Ok(DashSegments::List { init, segments })
+    }
+
+    /// Number, media time, start and duration in seconds of the segments of a
+    /// `SegmentTimeline` or a fixed `duration`
+    fn timeline(&self, path: &[&str]) -> Result<Vec<(u64, u64, f64, f64)>, VideoError> {
+        let timescale = self.number(path, "timescale").unwrap_or(1).max(1);
+        let start_number = self.number(path, "startNumber").unwrap_or(1);
+        let offset = self.number(path, "presentationTimeOffset").unwrap_or(0);
+        let seconds = |time: u64| time as f64 / timescale as f64;
+        let period_end = offset + (self.period_duration * timescale as f64).round() as u64;
+
+        let mut segments = Vec::new();
+        let timeline_path: Vec<_> = path.iter().copied().chain(["SegmentTimeline"]).collect();
+        if let Some(timeline) = self.element(&timeline_path) {
+            let entries: Vec<_> = children(timeline, "S").collect();
+            let mut time = offset;
+            for (index, entry) in entries.iter().enumerate() {
+                let number = |attribute| -> Result<Option<i64>, VideoError> {
+                    entry
+                        .attribute(attribute)
+                        .map(|value: &str| {
+                            value
+                                .parse()
+                                .map_err(|_| error(&format!("invalid S@{attribute} {value}")))
+                        })
+                        .transpose()
+                };
+                if let Some(start) = number("t")? {
+                    time = start.max(0) as u64;
+                }
+                let duration = number("d")?
+                    .filter(|duration| *duration > 0)
+                    .ok_or_else(|| error("S without d"))? as u64;
+                let end = match number("r")?.unwrap_or(0) {
+                    // Repeat until the next entry or the end of the period
+                    repeat if repeat < 0 => entries
+                        .get(index + 1)
+                        .and_then(|next| next.attribute("t")?.parse().ok())
+                        .unwrap_or(period_end),
+                    repeat => time + duration * (repeat as u64 + 1),
+                };
+                while time < end {
+                    let number = start_number + segments.len() as u64;
+                    segments.push((
+                        number,
+                        time,
+                        seconds(time - offset.min(time)),
+                        seconds(duration),
+                    ));
+                    time += duration;
+                }
+            }
+        } else if let Some(duration) = self.number(path, "duration").filter(|d| *d > 0) {
+            let count = (self.period_duration * timescale as f64 / duration as f64).ceil() as u64;
+            for index in 0..count {
+                let time = offset + index * duration;
+                let start = seconds(index * duration);
+                let duration = seconds(duration).min(self.period_duration - start);
+                segments.push((start_number + index, time, start, duration));
+            }
+        } else if path == ["SegmentList"] {
+            // A list of one segment may omit its duration
+            segments.push((start_number, offset, 0.0, self.period_duration));
+        } else {
+            return Err(error("SegmentTemplate without duration or SegmentTimeline"));
+        }
+        Ok(segments)
+    }
+}
+
+/// Substitute the `$identifier$`s of a `SegmentTemplate` URL, with `%0Nd` widths
+fn fill_template(
+    template: &str,
+    representation_id: &str,
+    number: u64,
+    bandwidth: u64,
+    time: u64,
+) -> Result<String, VideoError> {
+    let mut url = String::with_capacity(template.len());
+    let mut parts = template.split('$');
+    url.push_str(parts.next().unwrap_or_default());
+    let mut in_identifier = true;
+    for part in parts {
+        if !in_identifier {
+            url.push_str(part);
+            in_identifier = true;
+            continue;
+        }
+        in_identifier = false;
+        let (identifier, format) = part.split_once('%').unwrap_or((part, ""));
+        let width = match format {
+            "" => 0,
+            format => format
+                .strip_prefix('0')
+                .and_then(|format| format.strip_suffix('d'))
+                .and_then(|width| width.parse().ok())
+                .ok_or_else(|| error(&format!("invalid template format {format}")))?,
+        };
+        match identifier {
+            "" => url.push('$'),
+            "RepresentationID" => url.push_str(representation_id),
+            "Number" => url.push_str(&format!("{number:0width$}")),
+            "Bandwidth" => url.push_str(&format!("{bandwidth:0width$}")),
+            "Time" => url.push_str(&format!("{time:0width$}")),
+            identifier => return Err(error(&format!("unknown template identifier {identifier}"))),
+        }
+    }
+    if in_identifier {
+        Ok(url)
+    } else {
+        Err(error(&format!("unterminated identifier in {template}")))
+    }
+}
+
+/// `first-last` byte range, inclusive
+fn parse_range(range: &str) -> Result<Range<u64>, VideoError> {
+    let (first, last) = range
+        .split_once('-')
+        .and_then(|(first, last)| {
+            Some((
+                first.trim().parse::<u64>().ok()?,
+                last.trim().parse::<u64>().ok()?,
+            ))
+        })
+        .ok_or_else(|| error(&format!("invalid byte range {range}")))?;
+    Ok(first..last + 1)
+}
+
+/// `30000/1001` or `25`
+fn parse_frame_rate(frame_rate: &str) -> Option<f64> {
+    match frame_rate.split_once('/') {
+        Some((numerator, denominator)) => {
+            Some(numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?)
+        }
+        None => frame_rate.parse().ok(),
+    }
+}
+
+/// ISO 8601 duration in seconds, e.g. `PT1H2M3.5S`. Years and months have no fixed
+/// length and are rejected.
+fn parse_duration(duration: &str) -> Result<f64, VideoError> {
+    let invalid = || error(&format!("invalid duration {duration}"));
+    let rest = duration.trim().strip_prefix('P').ok_or_else(invalid)?;
+    let (date, time) = rest.split_once('T').unwrap_or((rest, ""));
+    let mut seconds = 0.0;
+    for (components, units) in [
+        (date, &[('D', 86400.0)][..]),
+        (time, &[('H', 3600.0), ('M', 60.0), ('S', 1.0)][..]),
+    ] {
+        let mut components = components;
+        for (unit, scale) in units {
+            if let Some((value, rest)) = components.split_once(*unit) {
+                seconds += value.parse::<f64>().map_err(|_| invalid())? * scale;
+                components = rest;
+            }
+        }
+        if !components.is_empty() {
+            return Err(invalid());
+        }
+    }
+    Ok(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    const STATIC: &str = include_str!("../../tests/fixtures/dash/static.mpd");
+    const PERIODS: &str = include_str!("../../tests/fixtures/dash/periods.mpd");
+
+    fn segment(url: &str, start: f64, duration: f64) -> DashSegment {
+        DashSegment {
+            url: url.into(),
+            byte_range: None,
+            start,
+            duration,
+        }
+    }
+
+    /// `sidx` box of version 0 with `(size, duration)` references
+    fn sidx(timescale: u32, references: &[(u32, u32)]) -> Vec<u8> {
+        let mut body = vec![0, 0, 0, 0];
+        for value in [1, timescale, 0, 0, references.len() as u32] {
+            body.extend_from_slice(&value.to_be_bytes());
+        }
+        for (size, duration) in references {
+            body.extend_from_slice(&size.to_be_bytes());
+            body.extend_from_slice(&duration.to_be_bytes());
+            // Starts with a SAP
+            body.extend_from_slice(&0x9000_0000u32.to_be_bytes());
+        }
+        [&(body.len() as u32 + 8).to_be_bytes()[..], b"sidx", &body].concat()
+    }
+
+    #[wasm_bindgen_test]
+    fn parses_representations() {
+        let manifest =
+            DashManifest::parse(STATIC, "https://cdn.example/show/manifest.mpd").unwrap();
+        assert_eq!(manifest.duration, 60.5);
+        let [period] = &manifest.periods[..] else {
+            panic!("expected one period");
+        };
+        assert_eq!(period.id.as_deref(), Some("main"));
+        assert_eq!((period.start, period.duration), (0.0, 60.5));
+
+        let video = period.adaptation_set(DashContentType::Video).unwrap();
+        assert_eq!(video.language.as_deref(), Some("und"));
+        let v720 = &video.representations[0];
+        assert_eq!(v720.id, "v720");
+        assert_eq!(v720.bandwidth, 3_000_000);
+        assert_eq!(v720.full_mime_type(), "video/mp4; codecs=\"avc1.64001f\"");
+        assert_eq!(v720.size, Some(UVec2::new(1280, 720)));
+        assert!((v720.frame_rate.unwrap() - 29.97).abs() < 0.001);
+
+        // Inherited from the adaptation set
+        let audio = period.adaptation_set(DashContentType::Audio).unwrap();
+        assert_eq!(
+            audio.representations[0].full_mime_type(),
+            "audio/mp4; codecs=\"mp4a.40.2\""
+        );
+        assert!(period.adaptation_set(DashContentType::Text).is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn fills_segment_template() {
+        let manifest =
+            DashManifest::parse(STATIC, "https://cdn.example/show/manifest.mpd").unwrap();
+        let DashSegments::List { init, segments } =
+            &manifest.periods[0].adaptation_sets[0].representations[0].segments
+        else {
+            panic!("expected listed segments");
+        };
+        assert_eq!(
+            init.as_ref().unwrap().url,
+            "https://cdn.example/show/media/v720/init.mp4"
+        );
+        assert_eq!(segments.len(), 16);
+        assert_eq!(
+            segments[..2],
+            [
+                segment(
+                    "https://cdn.example/show/media/v720/seg-00001-0.m4s",
+                    0.0,
+                    4.0
+                ),
+                segment(
+                    "https://cdn.example/show/media/v720/seg-00002-4000.m4s",
+                    4.0,
+                    4.0
+                ),
+            ]
+        );
+        // Cut off at the end of the period
+        assert_eq!(
+            segments[15],
+            segment(
+                "https://cdn.example/show/media/v720/seg-00016-60000.m4s",
+                60.0,
+                0.5
+            )
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn expands_segment_timeline() {
+        let manifest =
+            DashManifest::parse(STATIC, "https://cdn.example/show/manifest.mpd").unwrap();
+        let representation = &manifest.periods[0].adaptation_sets[0].representations[1];
+        let DashSegments::List { init, segments } = &representation.segments else {
+            panic!("expected listed segments");
+        };
+        // The initialization template is inherited from the adaptation set
+        assert_eq!(
+            init.as_ref().unwrap().url,
+            "https://cdn.example/show/media/v360/init.mp4"
+        );
+        // Three 2 second segments, then 1 second segments repeated to the end of the period
+        assert_eq!(segments.len(), 58);
+        assert_eq!(
+            segments[..2],
+            [
+                segment("https://cdn.example/show/media/v360/9000.m4s", 0.0, 2.0),
+                segment("https://cdn.example/show/media/v360/189000.m4s", 2.0, 2.0),
+            ]
+        );
+        let last = segments.last().unwrap();
+        assert_eq!((last.start, last.duration), (60.0, 1.0));
+        assert_eq!(representation.segments.segment_at(6.5), Some(3));
+        assert_eq!(representation.segments.segment_at(1000.0), Some(57));
+    }
+
+    #[wasm_bindgen_test]
+    fn resolves_segment_base_index() {
+        let manifest =
+            DashManifest::parse(STATIC, "https://cdn.example/show/manifest.mpd").unwrap();
+        let indexed = manifest.periods[0].adaptation_sets[1].representations[0]
+            .segments
+            .clone();
+        let url = "https://cdn.example/show/media/audio.mp4";
+        assert_eq!(
+            indexed,
+            DashSegments::Indexed {
+                url: url.into(),
+                init: Some(0..800),
+                index: 800..1000,
+                presentation_time_offset: 0.0,
+            }
+        );
+        assert!(indexed.segments().is_empty());
+
+        let index = sidx(48000, &[(1000, 96000), (1500, 48000)]);
+        // Offsets count from the end of the 56 byte sidx box
+        assert_eq!(
+            indexed.clone().resolve_index(&index).unwrap(),
+            DashSegments::List {
+                init: Some(DashSegmentRef {
+                    url: url.into(),
+                    byte_range: Some(0..800),
+                }),
+                segments: vec![
+                    DashSegment {
+                        byte_range: Some(856..1856),
+                        ..segment(url, 0.0, 2.0)
+                    },
+                    DashSegment {
+                        byte_range: Some(1856..3356),
+                        ..segment(url, 2.0, 1.0)
+                    },
+                ],
+            }
+        );
+        assert!(matches!(
+            indexed.clone().resolve_index(&index[..30]),
+            Err(VideoError::Demux(_))
+        ));
+        // References to other sidx boxes
+        let mut nested = index;
+        nested[32] |= 0x80;
+        assert!(matches!(
+            indexed.resolve_index(&nested),
+            Err(VideoError::NotSupported)
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn parses_segment_list_and_periods() {
+        let manifest = DashManifest::parse(PERIODS, "http://h/x/m.mpd").unwrap();
+        let [first, second] = &manifest.periods[..] else {
+            panic!("expected two periods");
+        };
+        assert_eq!((first.start, first.duration), (10.0, 6.0));
+        // Starts where the first ends
+        assert_eq!((second.start, second.duration), (16.0, 86400.5));
+        assert_eq!(manifest.duration, 86416.5);
+        assert_eq!(manifest.period_at(0.0), Some(0));
+        assert_eq!(manifest.period_at(16.0), Some(1));
+        assert_eq!(manifest.period_at(1e9), Some(1));
+
+        let representation = &first.adaptation_sets[0].representations[0];
+        assert_eq!(
+            first.adaptation_sets[0].content_type,
+            DashContentType::Video
+        );
+        assert_eq!(
+            representation.segments,
+            DashSegments::List {
+                init: Some(DashSegmentRef {
+                    url: "http://h/x/i.webm".into(),
+                    byte_range: None,
+                }),
+                segments: vec![
+                    DashSegment {
+                        byte_range: Some(0..10),
+                        ..segment("http://h/x/a.webm", 0.0, 3.0)
+                    },
+                    segment("http://h/x/b.webm", 3.0, 3.0),
+                ],
+            }
+        );
+        assert_eq!(representation.segments.segment_at(3.0), Some(1));
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_invalid_manifests() {
+        let url = "http://h/m.mpd";
+        assert!(matches!(
+            DashManifest::parse(r#"<MPD type="dynamic"><Period/></MPD>"#, url),
+            Err(VideoError::NotSupported)
+        ));
+        for text in [
+            "<NotMPD/>",
+            "<MPD",
+            r#"<MPD type="static"/>"#,
+            // Nothing gives the last period's end
+            r#"<MPD><Period start="PT0S"/></MPD>"#,
+            r#"<MPD mediaPresentationDuration="PT1S"><Period><AdaptationSet>
+                <Representation id="r" mimeType="video/mp4"/></AdaptationSet></Period></MPD>"#,
+        ] {
+            assert!(
+                matches!(DashManifest::parse(text, url), Err(VideoError::Demux(_))),
+                "{text}"
+            );
+        }
+        let Err(VideoError::Demux(message)) = DashManifest::parse(
+            &PERIODS.replace(r#"duration="P1DT0.5S""#, ""),
+            "http://h/x/m.mpd",
+        ) else {
+            panic!("expected an error");
+        };
+        assert_eq!(message, "mpd: Period without duration");
+    }
+
+    #[wasm_bindgen_test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("PT1H2M3.5S").unwrap(), 3723.5);
+        assert_eq!(parse_duration("P1DT0.5S").unwrap(), 86400.5);
+        assert_eq!(parse_duration("PT0S").unwrap(), 0.0);
+        for duration in ["1S", "P1Y", "P1M", "PT1X", "PTxS"] {
+            assert!(parse_duration(duration).is_err(), "{duration}");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn fills_templates() {
+        assert_eq!(
+            fill_template(
+                "$RepresentationID$/$Bandwidth$-$Number%03d$-$$.m4s",
+                "v",
+                7,
+                800,
+                0
+            )
+            .unwrap(),
+            "v/800-007-$.m4s"
+        );
+        assert_eq!(
+            fill_template("$Time%010d$", "v", 1, 1, 42).unwrap(),
+            "0000000042"
+        );
+        for template in ["$Number", "$Unknown$", "$Number%3d$", "$Number%0xd$"] {
+            assert!(fill_template(template, "v", 1, 1, 1).is_err(), "{template}");
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use bevy::prelude::*;
+use wasm_bindgen::prelude::*;
+
+/// Typed media failure, from a `MediaError` code or a `DOMException` name
+#[derive(Reflect, Clone, Debug, PartialEq, Eq)]
+pub enum VideoError {
+    /// `MEDIA_ERR_ABORTED`, fetching was aborted by the user agent
+    Aborted,
+    /// `MEDIA_ERR_NETWORK`, a network error occurred while fetching
+    Network,
+    /// `MEDIA_ERR_DECODE`, the media could not be decoded
+    Decode,
+    /// `MEDIA_ERR_SRC_NOT_SUPPORTED`, the source is unsupported or could not be loaded
+    SrcNotSupported,
+    /// `NotAllowedError`, e.g. playback was blocked by the autoplay policy
+    NotAllowed,
+    /// `NotSupportedError`
+    NotSupported,
+    /// `SecurityError`, e.g. a cross-origin source without CORS
+    Security,
+    /// `QuotaExceededError`, e.g. a `SourceBuffer` is full
+    QuotaExceeded,
+    /// The media could not be parsed in Rust, i.e. demuxed for WebCodecs playback,
+    /// decoded as an animated image, an HLS playlist or segment, or a DASH manifest
+    Demux(String),
+    Other(String),
+}
+
+impl From<&web_sys::MediaError> for VideoError {
+    fn from(error: &web_sys::MediaError) -> Self {
+        match error.code() {
+            web_sys::MediaError::MEDIA_ERR_ABORTED => VideoError::Aborted,
+            web_sys::MediaError::MEDIA_ERR_NETWORK => VideoError::Network,
+            web_sys::MediaError::MEDIA_ERR_DECODE => VideoError::Decode,
+            web_sys::MediaError::MEDIA_ERR_SRC_NOT_SUPPORTED => VideoError::SrcNotSupported,
+            code => VideoError::Other(format!("MediaError {code}: {}", error.message())),
+        }
+    }
+}
+
+impl From<&JsValue> for VideoError {
+    fn from(value: &JsValue) -> Self {
+        if let Some(exception) = value.dyn_ref::<web_sys::DomException>() {
+            match exception.name().as_str() {
+                "NotAllowedError" => VideoError::NotAllowed,
+                "NotSupportedError" => VideoError::NotSupported,
+                "SecurityError" => VideoError::Security,
+                "QuotaExceededError" => VideoError::QuotaExceeded,
+                "AbortError" => VideoError::Aborted,
+                // Fired by WebCodecs decoders
+                "EncodingError" => VideoError::Decode,
+                name => VideoError::Other(format!("{name}: {}", exception.message())),
+            }
+        } else if let Some(error) = value.dyn_ref::<web_sys::MediaError>() {
+            VideoError::from(error)
+        } else {
+            VideoError::Other(format!("{value:?}"))
+        }
+    }
+}
+
+impl VideoError {
+    /// Error of the media element that dispatched `event`
+    pub(crate) fn from_event(event: &web_sys::Event) -> Self {
+        event
+            .target()
+            .and_then(|target| target.dyn_into::<web_sys::HtmlMediaElement>().ok())
+            .and_then(|element| element.error())
+            .map(|error| VideoError::from(&error))
+            .unwrap_or_else(|| VideoError::Other("unknown media error".into()))
+    }
+}
+
+impl std::error::Error for VideoError {}
+
+impl std::fmt::Display for VideoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoError::Aborted => write!(f, "media fetch aborted"),
+            VideoError::Network => write!(f, "media network error"),
+            VideoError::Decode => write!(f, "media decode error"),
+            VideoError::SrcNotSupported => write!(f, "media source not supported"),
+            VideoError::NotAllowed => write!(f, "not allowed"),
+            VideoError::NotSupported => write!(f, "not supported"),
+            VideoError::Security => write!(f, "security error"),
+            VideoError::QuotaExceeded => write!(f, "quota exceeded"),
+            VideoError::Demux(message) => write!(f, "demux error: {message}"),
+            VideoError::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WebVideoError {
+    kind: VideoError,
+}
+
+impl WebVideoError {
+    pub fn kind(&self) -> &VideoError {
+        &self.kind
+    }
+}
+
+impl std::error::Error for WebVideoError {}
+
+impl std::fmt::Display for WebVideoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl From<VideoError> for WebVideoError {
+    fn from(kind: VideoError) -> Self {
+        Self { kind }
+    }
+}
+
+impl From<JsValue> for WebVideoError {
+    fn from(value: JsValue) -> Self {
+        Self {
+            kind: VideoError::from(&value),
+        }
+    }
+}
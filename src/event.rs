@@ -1,16 +1,43 @@
-use crate::{VideoElement, VideoElementRegistry};
-use bevy::prelude::*;
+use crate::{VideoElement, VideoElementRegistry, registry::EventSubscription};
+use bevy::{
+    ecs::{lifecycle::HookContext, world::DeferredWorld},
+    platform::collections::HashMap,
+    prelude::*,
+};
 use crossbeam_channel::unbounded;
 use gloo_events::EventListener;
-use std::marker::PhantomData;
+use std::{any::TypeId, marker::PhantomData};
 
 pub fn plugin(app: &mut App) {
-    app.add_listener_event::<events::LoadedMetadata>()
+    app.init_resource::<VideoEntities>()
+        .add_systems(Update, track_event_targets)
+        .add_listener_event::<events::LoadedMetadata>()
         .add_listener_event::<events::CanPlay>()
         .add_listener_event::<events::Resize>()
         .add_listener_event::<events::Playing>()
         .add_listener_event::<events::Ended>()
-        .add_listener_event::<events::Error>();
+        .add_listener_event::<events::Error>()
+        .add_listener_event::<events::LoadedData>()
+        .add_listener_event::<events::Seeked>()
+        .add_listener_event::<events::TimeUpdate>()
+        .add_listener_event::<events::Progress>()
+        .add_listener_event::<events::Pause>()
+        .add_listener_event::<events::Play>()
+        .add_listener_event::<events::Seeking>()
+        .add_listener_event::<events::Waiting>()
+        .add_listener_event::<events::Stalled>()
+        .add_listener_event::<events::RateChange>()
+        .add_listener_event::<events::VolumeChange>()
+        .add_listener_event::<events::DurationChange>()
+        .add_listener_event::<events::CanPlayThrough>()
+        .add_listener_event::<events::Emptied>()
+        .add_listener_event::<events::Suspend>()
+        .add_listener_event::<events::Abort>()
+        .add_listener_event::<events::Looped>()
+        .add_listener_event::<events::StreamPermission>()
+        .add_listener_event::<events::StreamEnded>()
+        .add_listener_event::<events::UpdateEnd>()
+        .add_listener_event::<events::SourceBufferError>();
 }
 
 pub trait EventListenerAppExt {
@@ -24,37 +51,110 @@ impl EventListenerAppExt for App {
             return self;
         }
         let (tx, rx) = unbounded();
+        // Initialized here when called before WebVideoPlugin is added
+        self.init_non_send_resource::<VideoElementRegistry>();
+        self.world_mut()
+            .non_send_resource_mut::<VideoElementRegistry>()
+            .add_event_type::<E>(tx.clone());
         self.insert_resource(EventSender::<E>(tx))
             .insert_resource(EventReceiver::<E>(rx))
             .add_systems(Update, listen_for_events::<E>)
     }
 }
 
+pub(crate) fn new_listener<E: EventType>(
+    tx: crossbeam_channel::Sender<ListenerEventInternal<E>>,
+    asset_id: AssetId<VideoElement>,
+    element: &web_sys::EventTarget,
+    target: Option<Entity>,
+) -> EventListener {
+    EventListener::new(element, E::EVENT_NAME, move |event: &web_sys::Event| {
+        let payload = E::payload(event);
+        if let Err(err) = tx.send(ListenerEventInternal::<E>::new(asset_id, target, payload)) {
+            warn!("Failed to fire video event {}: {err:?}", E::EVENT_NAME);
+        };
+    })
+}
+
+/// Removes the event listeners targeting this entity when it is despawned
+#[derive(Component)]
+#[component(on_despawn = on_despawn_event_target)]
+struct EventObserverTarget;
+
+fn on_despawn_event_target(mut world: DeferredWorld, HookContext { entity, .. }: HookContext) {
+    world
+        .non_send_resource_mut::<VideoElementRegistry>()
+        .disable_entity_event_observers(entity);
+}
+
+fn track_event_targets(mut registry: NonSendMut<VideoElementRegistry>, mut commands: Commands) {
+    for target in registry.take_new_targets() {
+        // Targets despawned before the marker is inserted never run its hook
+        commands.queue(move |world: &mut World| {
+            if let Ok(mut entity) = world.get_entity_mut(target) {
+                entity.insert(EventObserverTarget);
+            } else {
+                world
+                    .non_send_resource_mut::<VideoElementRegistry>()
+                    .disable_entity_event_observers(target);
+            }
+        });
+    }
+}
+
+/// Entities of each video asset, maintained by the [`WebVideo`](crate::WebVideo) hooks
+#[derive(Resource, Default)]
+pub(crate) struct VideoEntities(HashMap<AssetId<VideoElement>, Vec<Entity>>);
+
+impl VideoEntities {
+    pub(crate) fn insert(&mut self, asset_id: AssetId<VideoElement>, entity: Entity) {
+        self.0.entry(asset_id).or_default().push(entity);
+    }
+
+    pub(crate) fn remove(&mut self, asset_id: AssetId<VideoElement>, entity: Entity) {
+        if let Some(entities) = self.0.get_mut(&asset_id) {
+            entities.retain(|e| *e != entity);
+            if entities.is_empty() {
+                self.0.remove(&asset_id);
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, asset_id: AssetId<VideoElement>) -> &[Entity] {
+        self.0.get(&asset_id).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
 #[derive(Resource)]
 pub struct EventSender<E: EventType>(crossbeam_channel::Sender<ListenerEventInternal<E>>);
 
 impl<E: EventType> EventSender<E> {
+    /// Fire [`ListenerEvent<E>`] at `target` when `element` dispatches `E`.
+    ///
+    /// Events of video elements already reach every entity with a matching
+    /// [`WebVideo`](crate::WebVideo), this is for other elements such as a `<track>` or for
+    /// entities without a `WebVideo`. Listening on the video element of `target`'s own
+    /// `WebVideo` replaces that routing, so `E` is not fired twice.
+    ///
+    /// The listener is removed when the returned subscription is passed to
+    /// [`VideoElementRegistry::disable_element_event_observers`], when `target` is despawned,
+    /// or when the video asset is removed.
+    /// Returns `None` if the video asset has no registered element.
     pub fn enable_element_event_observers(
         &self,
         asset_id: impl Into<AssetId<VideoElement>>,
         element: &web_sys::EventTarget,
         registry: &mut VideoElementRegistry,
         target: Entity,
-    ) -> &Self {
-        let tx = self.0.clone();
+    ) -> Option<EventSubscription> {
         let asset_id = asset_id.into();
-        let listener =
-            EventListener::new(element, E::EVENT_NAME, move |_event: &web_sys::Event| {
-                if let Err(err) = tx.send(ListenerEventInternal::<E>::new(asset_id, Some(target))) {
-                    warn!("Failed to fire video event {}: {err:?}", E::EVENT_NAME);
-                };
-            });
-        registry.add_event_listener(asset_id, listener);
-        self
-    }
-
-    pub(crate) fn tx(&self) -> crossbeam_channel::Sender<ListenerEventInternal<E>> {
-        self.0.clone()
+        let listener = new_listener(self.0.clone(), asset_id, element, Some(target));
+        // Listening on the video element replaces routing its events to target
+        let routed = registry
+            .element(asset_id)
+            .filter(|video| AsRef::<web_sys::EventTarget>::as_ref(video) == element)
+            .map(|_| TypeId::of::<E>());
+        registry.subscribe(asset_id, target, vec![listener], routed)
     }
 }
 
@@ -65,12 +165,14 @@ struct EventReceiver<E: EventType>(crossbeam_channel::Receiver<ListenerEventInte
 pub(crate) struct ListenerEventInternal<E: EventType> {
     asset_id: AssetId<VideoElement>,
     entity: Option<Entity>,
+    payload: E::Payload,
     _phantom: PhantomData<E>,
 }
 
 #[derive(Event, Clone, Debug)]
 pub struct ListenerAssetEvent<E: EventType> {
     asset_id: AssetId<VideoElement>,
+    payload: E::Payload,
     _phantom: PhantomData<E>,
 }
 
@@ -78,23 +180,30 @@ pub struct ListenerAssetEvent<E: EventType> {
 pub struct ListenerEvent<E: EventType> {
     asset_id: AssetId<VideoElement>,
     pub entity: Entity,
+    payload: E::Payload,
     _phantom: PhantomData<E>,
 }
 
 impl<E: EventType> ListenerEventInternal<E> {
-    pub(crate) fn new(asset_id: AssetId<VideoElement>, entity: Option<Entity>) -> Self {
+    pub(crate) fn new(
+        asset_id: AssetId<VideoElement>,
+        entity: Option<Entity>,
+        payload: E::Payload,
+    ) -> Self {
         Self {
             asset_id,
             entity,
+            payload,
             _phantom: PhantomData,
         }
     }
 }
 
 impl<E: EventType> ListenerAssetEvent<E> {
-    fn new(asset_id: AssetId<VideoElement>) -> Self {
+    fn new(asset_id: AssetId<VideoElement>, payload: E::Payload) -> Self {
         Self {
             asset_id,
+            payload,
             _phantom: PhantomData,
         }
     }
@@ -102,13 +211,18 @@ impl<E: EventType> ListenerAssetEvent<E> {
     pub fn asset_id(&self) -> AssetId<VideoElement> {
         self.asset_id
     }
+
+    pub fn payload(&self) -> &E::Payload {
+        &self.payload
+    }
 }
 
 impl<E: EventType> ListenerEvent<E> {
-    fn new(asset_id: AssetId<VideoElement>, entity: Entity) -> Self {
+    fn new(asset_id: AssetId<VideoElement>, entity: Entity, payload: E::Payload) -> Self {
         Self {
             asset_id,
             entity,
+            payload,
             _phantom: PhantomData,
         }
     }
@@ -116,41 +230,214 @@ impl<E: EventType> ListenerEvent<E> {
     pub fn asset_id(&self) -> AssetId<VideoElement> {
         self.asset_id
     }
+
+    pub fn payload(&self) -> &E::Payload {
+        &self.payload
+    }
 }
 
 pub trait EventType: Copy + Clone + Send + Sync + 'static {
     const EVENT_NAME: &'static str;
+    /// Data extracted from the DOM event when it is dispatched
+    type Payload: Clone + std::fmt::Debug + Send + Sync + 'static;
+
+    fn payload(event: &web_sys::Event) -> Self::Payload;
 }
 
 pub mod events {
     use super::*;
+    use crate::{MediaStreamPermission, SourceBufferId, VideoError};
+    use std::ops::Range;
+    use wasm_bindgen::JsCast;
 
     #[macro_export]
+    /// Defines a new [`EventType`](crate::EventType) for the DOM event named `$event_name`.
+    ///
+    /// The optional `$payload` type is extracted from the DOM event by `$extract`,
+    /// a `fn(&web_sys::Event) -> $payload`, when the event is dispatched.
     macro_rules! new_event_type {
         ($name:ident, $event_name:literal) => {
+            $crate::new_event_type!($name, $event_name, (), |_event| {});
+        };
+        ($name:ident, $event_name:literal, $payload:ty, $extract:expr) => {
             #[derive(Event, Copy, Clone, Debug)]
             pub struct $name;
 
             impl $crate::EventType for $name {
                 const EVENT_NAME: &'static str = $event_name;
+                type Payload = $payload;
+
+                fn payload(event: &$crate::web_sys::Event) -> Self::Payload {
+                    let extract: fn(&$crate::web_sys::Event) -> $payload = $extract;
+                    extract(event)
+                }
             }
         };
     }
 
-    new_event_type!(LoadedMetadata, "loadedmetadata");
+    /// Video element that dispatched `event`
+    pub fn video_element(event: &web_sys::Event) -> Option<web_sys::HtmlVideoElement> {
+        event
+            .target()
+            .and_then(|target| target.dyn_into::<web_sys::HtmlVideoElement>().ok())
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct VideoMetadata {
+        pub size: UVec2,
+        pub duration: f64,
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct Volume {
+        pub volume: f64,
+        pub muted: bool,
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct PlaybackTime {
+        pub current_time: f64,
+        pub duration: f64,
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct SourceBufferUpdate {
+        pub buffer: SourceBufferId,
+        /// Buffered ranges in seconds after the update
+        pub buffered: Vec<Range<f64>>,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct SourceBufferFailure {
+        pub buffer: SourceBufferId,
+        pub error: VideoError,
+    }
+
+    fn video_size(element: &web_sys::HtmlVideoElement) -> UVec2 {
+        UVec2::new(element.video_width(), element.video_height())
+    }
+
+    new_event_type!(LoadedMetadata, "loadedmetadata", VideoMetadata, |event| {
+        video_element(event)
+            .map(|element| VideoMetadata {
+                size: video_size(&element),
+                duration: element.duration(),
+            })
+            .unwrap_or_default()
+    });
     new_event_type!(CanPlay, "canplay");
-    new_event_type!(Resize, "resize");
+    new_event_type!(Resize, "resize", UVec2, |event| {
+        video_element(event)
+            .map(|element| video_size(&element))
+            .unwrap_or_default()
+    });
     new_event_type!(Playing, "playing");
     new_event_type!(Ended, "ended");
-    new_event_type!(Error, "error");
+    new_event_type!(Error, "error", VideoError, VideoError::from_event);
+    new_event_type!(LoadedData, "loadeddata");
+    new_event_type!(Seeked, "seeked");
+    new_event_type!(TimeUpdate, "timeupdate", PlaybackTime, |event| {
+        video_element(event)
+            .map(|element| PlaybackTime {
+                current_time: element.current_time(),
+                duration: element.duration(),
+            })
+            .unwrap_or_default()
+    });
+    new_event_type!(Progress, "progress", Vec<Range<f64>>, |event| {
+        video_element(event)
+            .map(|element| {
+                let buffered = element.buffered();
+                (0..buffered.length())
+                    .filter_map(|i| Some(buffered.start(i).ok()?..buffered.end(i).ok()?))
+                    .collect()
+            })
+            .unwrap_or_default()
+    });
+    new_event_type!(Pause, "pause");
+    new_event_type!(Play, "play");
+    new_event_type!(Seeking, "seeking");
+    new_event_type!(Waiting, "waiting");
+    new_event_type!(Stalled, "stalled");
+    new_event_type!(RateChange, "ratechange", f64, |event| {
+        video_element(event)
+            .map(|element| element.playback_rate())
+            .unwrap_or(1.0)
+    });
+    new_event_type!(VolumeChange, "volumechange", Volume, |event| {
+        video_element(event)
+            .map(|element| Volume {
+                volume: element.volume(),
+                muted: element.muted(),
+            })
+            .unwrap_or_default()
+    });
+    new_event_type!(DurationChange, "durationchange", f64, |event| {
+        video_element(event)
+            .map(|element| element.duration())
+            .unwrap_or(f64::NAN)
+    });
+    new_event_type!(CanPlayThrough, "canplaythrough");
+    new_event_type!(Emptied, "emptied");
+    new_event_type!(Suspend, "suspend");
+    new_event_type!(Abort, "abort");
+    // Synthesized when a looping video reaches its end and wraps around to the start,
+    // the browser dispatches no event for this. Seeks through VideoSeek are not loops.
+    new_event_type!(Looped, "bevywebvideo:looped");
+    // Synthesized when the stream of a MediaStreamSource is granted or rejected
+    new_event_type!(
+        StreamPermission,
+        "bevywebvideo:streampermission",
+        MediaStreamPermission,
+        MediaStreamPermission::from_event
+    );
+    // Synthesized when a video track of the stream ends, e.g. screen sharing was stopped
+    new_event_type!(StreamEnded, "bevywebvideo:streamended");
+    // Fired by a MediaSourceVideo when a SourceBuffer operation completes,
+    // the payload is not read from DOM events
+    new_event_type!(
+        UpdateEnd,
+        "bevywebvideo:updateend",
+        SourceBufferUpdate,
+        |_event| SourceBufferUpdate::default()
+    );
+    // Fired by a MediaSourceVideo when a SourceBuffer operation fails
+    new_event_type!(
+        SourceBufferError,
+        "bevywebvideo:sourcebuffererror",
+        SourceBufferFailure,
+        |_event| SourceBufferFailure {
+            buffer: SourceBufferId::default(),
+            error: VideoError::Other("unknown source buffer error".into()),
+        }
+    );
 }
 
-fn listen_for_events<E: EventType>(receiver: Res<EventReceiver<E>>, mut commands: Commands) {
+/// Asset events are also fired at every entity displaying the video
+fn listen_for_events<E: EventType>(
+    receiver: Res<EventReceiver<E>>,
+    video_entities: Res<VideoEntities>,
+    registry: NonSend<VideoElementRegistry>,
+    mut commands: Commands,
+) {
     while let Ok(event) = receiver.0.try_recv() {
         if let Some(entity) = event.entity {
-            commands.trigger(ListenerEvent::<E>::new(event.asset_id, entity));
+            commands.trigger(ListenerEvent::<E>::new(
+                event.asset_id,
+                entity,
+                event.payload,
+            ));
         } else {
-            commands.trigger(ListenerAssetEvent::<E>::new(event.asset_id));
+            for entity in video_entities.get(event.asset_id) {
+                if !registry.is_routed(event.asset_id, *entity, TypeId::of::<E>()) {
+                    commands.trigger(ListenerEvent::<E>::new(
+                        event.asset_id,
+                        *entity,
+                        event.payload.clone(),
+                    ));
+                }
+            }
+            commands.trigger(ListenerAssetEvent::<E>::new(event.asset_id, event.payload));
         }
     }
 }
@@ -0,0 +1,117 @@
+//! Byte requests of streaming playback, polled from systems like the other async results
+
+use crate::VideoError;
+use std::{cell::RefCell, ops::Range, rc::Rc};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+type FetchResult = Result<Vec<u8>, VideoError>;
+
+/// A running `fetch`, aborted when dropped
+pub(crate) struct Fetch {
+    // Set by the fetch task
+    result: Rc<RefCell<Option<FetchResult>>>,
+    controller: Option<web_sys::AbortController>,
+    // Milliseconds
+    started: f64,
+}
+
+impl Fetch {
+    /// Request `byte_range` of `url`, or all of it
+    pub(crate) fn start(url: &str, byte_range: Option<Range<u64>>) -> Self {
+        let result = Rc::new(RefCell::new(None));
+        let controller = web_sys::AbortController::new().ok();
+        let request = request(url, byte_range, controller.as_ref());
+        let task_result = result.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let bytes = match request {
+                Ok(promise) => response_bytes(promise).await,
+                Err(err) => Err(VideoError::from(&err)),
+            };
+            *task_result.borrow_mut() = Some(bytes);
+        });
+        Self {
+            result,
+            controller,
+            started: js_sys::Date::now(),
+        }
+    }
+
+    /// The response body once complete, with the seconds it took
+    pub(crate) fn take(&self) -> Option<(FetchResult, f64)> {
+        let result = self.result.borrow_mut().take()?;
+        Some((result, (js_sys::Date::now() - self.started) / 1000.0))
+    }
+}
+
+impl Drop for Fetch {
+    fn drop(&mut self) {
+        // No-op once the response was read
+        if let Some(controller) = &self.controller {
+            controller.abort();
+        }
+    }
+}
+
+/// Request all of `url` and wait for the response body, e.g. from an asset loader
+pub(crate) async fn fetch_bytes(url: &str) -> FetchResult {
+    response_bytes(request(url, None, None).map_err(|err| VideoError::from(&err))?).await
+}
+
+/// `fetch` rejects with a `TypeError` when the request fails, or an `AbortError` when aborted
+fn rejected(err: JsValue) -> VideoError {
+    match VideoError::from(&err) {
+        VideoError::Aborted => VideoError::Aborted,
+        _ => VideoError::Network,
+    }
+}
+
+/// Like an element failing to fetch its source, only server errors and timeouts are retried
+fn status_error(status: u16) -> VideoError {
+    match status {
+        408 | 429 => VideoError::Network,
+        400..=499 => VideoError::SrcNotSupported,
+        _ => VideoError::Network,
+    }
+}
+
+fn request(
+    url: &str,
+    byte_range: Option<Range<u64>>,
+    controller: Option<&web_sys::AbortController>,
+) -> Result<js_sys::Promise, JsValue> {
+    let window = web_sys::window().ok_or_else(|| JsValue::from_str("no window"))?;
+    let init = web_sys::RequestInit::new();
+    if let Some(byte_range) = byte_range {
+        let headers = web_sys::Headers::new()?;
+        // HTTP ranges are inclusive
+        headers.set(
+            "Range",
+            &format!(
+                "bytes={}-{}",
+                byte_range.start,
+                byte_range.end.saturating_sub(1)
+            ),
+        )?;
+        init.set_headers(&headers);
+    }
+    if let Some(controller) = controller {
+        init.set_signal(Some(&controller.signal()));
+    }
+    Ok(window.fetch_with_str_and_init(url, &init))
+}
+
+async fn response_bytes(promise: js_sys::Promise) -> FetchResult {
+    let error = |err: JsValue| VideoError::from(&err);
+    let response = JsFuture::from(promise)
+        .await
+        .map_err(rejected)?
+        .unchecked_into::<web_sys::Response>();
+    if !response.ok() {
+        return Err(status_error(response.status()));
+    }
+    let buffer = JsFuture::from(response.array_buffer().map_err(error)?)
+        .await
+        .map_err(error)?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
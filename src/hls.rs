@@ -0,0 +1,638 @@
+//! HTTP Live Streaming through Media Source Extensions: playlists are parsed and segments
+//! scheduled in Rust, so HLS plays in every browser with MSE, not only Safari.
+
+use crate::{
+    AbrRendition, MediaSourceVideo, RenditionSelection, SourceBufferId, VideoElementRegistry,
+    VideoError, WebVideo,
+    media_source::update_media_sources,
+    segmented::{
+        Downloader, GAP_TOLERANCE, MAX_BUFFER_AHEAD, SegmentedStream, Throughput,
+        first_decode_time, first_timescale, playing_range,
+    },
+};
+use bevy::{platform::collections::HashSet, prelude::*};
+use transmux::Transmuxer;
+
+mod playlist;
+mod transmux;
+
+pub use playlist::{
+    HlsInitSegment, HlsMasterPlaylist, HlsMediaPlaylist, HlsPlaylist, HlsSegment, HlsVariant,
+};
+
+pub fn plugin(app: &mut App) {
+    app.register_type::<HlsVariantSelection>()
+        .add_systems(Update, sync_variant_selection)
+        .add_systems(PostUpdate, update_hls_streams.before(update_media_sources));
+}
+
+/// Live playback starts this many segments before the end of the playlist
+const LIVE_START_SEGMENTS: usize = 3;
+/// Type of fragmented MP4 segments whose variant lists no codecs
+const DEFAULT_MP4_TYPE: &str = "video/mp4; codecs=\"avc1.42e01e,mp4a.40.2\"";
+
+/// Variant selection of an HLS [`WebVideo`] entity.
+///
+/// Changes to `selection` are pushed to the [`HlsStream`], `variants` and `current`
+/// are pulled from it.
+#[derive(Component, Reflect, Clone, Debug, Default, PartialEq)]
+#[reflect(Component, Default)]
+pub struct HlsVariantSelection {
+    /// Index of a fixed selection into `variants`
+    pub selection: RenditionSelection,
+    /// Variants of the master playlist, empty until loaded and for media playlists
+    pub variants: Vec<HlsVariant>,
+    /// Index into `variants` of the variant being fetched
+    pub current: Option<usize>,
+}
+
+impl HlsVariantSelection {
+    pub fn fixed(variant: usize) -> Self {
+        Self {
+            selection: RenditionSelection::Fixed(variant),
+            ..default()
+        }
+    }
+
+    fn from_stream(stream: &HlsStream) -> Self {
+        Self {
+            selection: stream.selection,
+            variants: stream.variants.clone(),
+            current: stream.current,
+        }
+    }
+}
+
+fn sync_variant_selection(
+    mut videos: Query<(Entity, &WebVideo, &mut HlsVariantSelection)>,
+    mut registry: NonSendMut<VideoElementRegistry>,
+    // Changed before their stream existed, pushed once it does
+    mut pending: Local<HashSet<Entity>>,
+) {
+    pending.retain(|entity| videos.contains(*entity));
+    for (entity, web_video, mut selection) in &mut videos {
+        let changed = pending.remove(&entity) || selection.is_changed();
+        let Some(stream) = registry.hls_stream_mut(web_video.asset_id()) else {
+            if changed {
+                pending.insert(entity);
+            }
+            continue;
+        };
+        if changed {
+            stream.set_selection(selection.selection);
+        } else {
+            selection.set_if_neq(HlsVariantSelection::from_stream(stream));
+        }
+    }
+}
+
+/// Runs before the media sources so appended segments are queued the same frame
+fn update_hls_streams(mut registry: NonSendMut<VideoElementRegistry>) {
+    registry.update_hls_streams();
+}
+
+/// An HLS playlist played through the [`MediaSourceVideo`] of the asset's element.
+///
+/// Create one with [`VideoSource::Hls`](crate::VideoSource::Hls) or by loading an `.m3u8`
+/// asset, and get it with [`VideoElementRegistry::hls_stream`]. Browsers without MSE that play
+/// HLS natively, i.e. iOS Safari, get the playlist URL as the element's source instead.
+///
+/// One request runs at a time: the playlist, then initialization and media segments until
+/// 30 seconds are buffered ahead. Live playlists are reloaded every target duration. Segments
+/// are fragmented MP4 or MPEG-TS with H.264 and AAC, which is remuxed to fragmented MP4.
+/// Variants must include their audio, alternative renditions and encrypted segments
+/// are unsupported. Failed requests are retried, then fired as
+/// [`events::Error`](crate::events::Error) and the stream stops.
+pub struct HlsStream {
+    url: String,
+    variants: Vec<HlsVariant>,
+    selection: RenditionSelection,
+    // Variant picked by an AbrController for RenditionSelection::Auto
+    abr_choice: Option<usize>,
+    current: Option<usize>,
+    playlist: Option<HlsMediaPlaylist>,
+    // Milliseconds
+    playlist_loaded_at: f64,
+    buffer: Option<SourceBufferId>,
+    // Last appended initialization segment of fragmented MP4
+    init: Option<HlsInitSegment>,
+    // Timescale of the last initialization segment's first track
+    init_timescale: Option<u32>,
+    transmuxer: Transmuxer,
+    // Sequence number of the last appended segment
+    last_appended: Option<u64>,
+    downloader: Downloader<Request>,
+    throughput: Throughput,
+    duration_set: bool,
+    ended: bool,
+    failed: bool,
+}
+
+#[derive(Clone)]
+enum Request {
+    /// The playlist of the source URL, a master or media playlist
+    Source,
+    Playlist(usize),
+    Init(HlsInitSegment),
+    Segment(HlsSegment),
+}
+
+impl HlsStream {
+    pub(crate) fn new(url: &str) -> Self {
+        let mut stream = Self {
+            url: url.to_string(),
+            variants: Vec::new(),
+            selection: RenditionSelection::Auto,
+            abr_choice: None,
+            current: None,
+            playlist: None,
+            playlist_loaded_at: 0.0,
+            buffer: None,
+            init: None,
+            init_timescale: None,
+            transmuxer: Transmuxer::default(),
+            last_appended: None,
+            downloader: Downloader::default(),
+            throughput: Throughput::default(),
+            duration_set: false,
+            ended: false,
+            failed: false,
+        };
+        stream.start(Request::Source);
+        stream
+    }
+
+    /// Whether the browser has MSE, otherwise HLS only plays natively
+    pub fn is_supported() -> bool {
+        js_sys::Reflect::has(&js_sys::global(), &"MediaSource".into()).unwrap_or(false)
+    }
+
+    /// Whether `element` plays HLS without MSE
+    pub fn is_natively_supported(element: &web_sys::HtmlVideoElement) -> bool {
+        !element
+            .can_play_type("application/vnd.apple.mpegurl")
+            .is_empty()
+    }
+
+    /// URL of the master or media playlist
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Variants of the master playlist, empty until loaded and for media playlists
+    pub fn variants(&self) -> &[HlsVariant] {
+        &self.variants
+    }
+
+    /// Index into [`variants`](Self::variants) of the variant being fetched
+    pub fn current_variant(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn selection(&self) -> RenditionSelection {
+        self.selection
+    }
+
+    /// Switch variants from the next segment, buffered segments keep playing
+    pub fn set_selection(&mut self, selection: RenditionSelection) {
+        self.selection = selection;
+    }
+
+    /// Media playlist of the current variant, `None` until loaded
+    pub fn playlist(&self) -> Option<&HlsMediaPlaylist> {
+        self.playlist.as_ref()
+    }
+
+    /// Bits per second measured from segment downloads
+    pub fn throughput(&self) -> Option<f64> {
+        self.throughput.estimate()
+    }
+
+    /// Complete the running request and start the next one. Once an error is returned
+    /// the stream stops.
+    pub(crate) fn update(
+        &mut self,
+        media_source: &mut MediaSourceVideo,
+        element: &web_sys::HtmlVideoElement,
+    ) -> Result<(), VideoError> {
+        if self.failed {
+            return Ok(());
+        }
+        let result = self.poll(media_source, element);
+        self.failed = result.is_err();
+        result
+    }
+
+    fn poll(
+        &mut self,
+        media_source: &mut MediaSourceVideo,
+        element: &web_sys::HtmlVideoElement,
+    ) -> Result<(), VideoError> {
+        if let Some((request, result)) = self.downloader.poll() {
+            let (bytes, seconds) = result?;
+            if matches!(request, Request::Segment(_)) {
+                self.throughput.measure(bytes.len(), seconds);
+            }
+            self.complete(request, bytes, media_source)?;
+        }
+
+        let current_time = element.current_time();
+        self.drop_stale_segment(media_source, element, current_time);
+        if !self.downloader.is_idle() {
+            return Ok(());
+        }
+        self.schedule(media_source, element, current_time);
+        Ok(())
+    }
+
+    fn start(&mut self, request: Request) {
+        let (url, byte_range) = match &request {
+            Request::Source => (self.url.clone(), None),
+            Request::Playlist(variant) => (self.variants[*variant].uri.clone(), None),
+            Request::Init(init) => (init.uri.clone(), init.byte_range.clone()),
+            Request::Segment(segment) => (segment.uri.clone(), segment.byte_range.clone()),
+        };
+        self.downloader.start(request, &url, byte_range);
+    }
+
+    fn complete(
+        &mut self,
+        request: Request,
+        bytes: Vec<u8>,
+        media_source: &mut MediaSourceVideo,
+    ) -> Result<(), VideoError> {
+        match request {
+            Request::Source | Request::Playlist(_) => {
+                let text = String::from_utf8(bytes)
+                    .map_err(|_| VideoError::Demux("m3u8: invalid UTF-8".into()))?;
+                let url = match request {
+                    Request::Playlist(variant) => &self.variants[variant].uri,
+                    _ => &self.url,
+                };
+                match HlsPlaylist::parse(&text, url)? {
+                    HlsPlaylist::Master(master) => {
+                        self.variants = master.variants;
+                        let variant = self.wanted_variant().unwrap_or_default();
+                        self.start(Request::Playlist(variant));
+                    }
+                    HlsPlaylist::Media(playlist) => {
+                        if let Request::Playlist(variant) = request {
+                            self.current = Some(variant);
+                        }
+                        self.set_playlist(playlist, media_source);
+                    }
+                }
+            }
+            Request::Init(init) => {
+                let mime_type = self.mp4_type();
+                let buffer = self.source_buffer(media_source, &mime_type)?;
+                self.init_timescale = first_timescale(&bytes);
+                media_source.append(buffer, bytes);
+                self.init = Some(init);
+            }
+            Request::Segment(segment) => {
+                let continuous =
+                    segment.sequence.checked_sub(1) == self.last_appended && !segment.discontinuity;
+                if segment.init.is_some() {
+                    let mime_type = self.mp4_type();
+                    let buffer = self.source_buffer(media_source, &mime_type)?;
+                    // Map the segment's media time to its playlist time
+                    if !continuous
+                        && let Some(timescale) = self.init_timescale
+                        && let Some(decode_time) = first_decode_time(&bytes)
+                    {
+                        let offset = segment.start - decode_time as f64 / timescale as f64;
+                        media_source.set_timestamp_offset(buffer, offset);
+                    }
+                    media_source.append(buffer, bytes);
+                } else {
+                    if !continuous {
+                        self.transmuxer.discontinuity();
+                    }
+                    let remuxed = self.transmuxer.remux(&bytes, segment.start)?;
+                    let buffer = self.source_buffer(media_source, &remuxed.mime_type)?;
+                    if let Some(init) = remuxed.init {
+                        media_source.append(buffer, init);
+                    }
+                    media_source.append(buffer, remuxed.fragment);
+                }
+                self.last_appended = Some(segment.sequence);
+            }
+        }
+        Ok(())
+    }
+
+    /// Source buffer for `mime_type`, added on the first segment and switched to the type
+    /// of later variants
+    fn source_buffer(
+        &mut self,
+        media_source: &mut MediaSourceVideo,
+        mime_type: &str,
+    ) -> Result<SourceBufferId, VideoError> {
+        match self.buffer {
+            Some(buffer) => {
+                if media_source.mime_type(buffer) != Some(mime_type) {
+                    media_source.change_type(buffer, mime_type);
+                }
+                Ok(buffer)
+            }
+            None => {
+                let buffer = media_source.add_source_buffer(mime_type)?;
+                self.buffer = Some(buffer);
+                Ok(buffer)
+            }
+        }
+    }
+
+    fn mp4_type(&self) -> String {
+        self.current
+            .and_then(|variant| self.variants[variant].codecs.as_ref())
+            .map(|codecs| format!("video/mp4; codecs=\"{codecs}\""))
+            .unwrap_or_else(|| DEFAULT_MP4_TYPE.to_string())
+    }
+
+    fn set_playlist(
+        &mut self,
+        mut playlist: HlsMediaPlaylist,
+        media_source: &mut MediaSourceVideo,
+    ) {
+        // Reloaded live playlists and other variants start at 0, continue the timeline
+        // from a segment in both
+        if let Some(previous) = &self.playlist {
+            let offset = playlist
+                .segments
+                .iter()
+                .find_map(|segment| {
+                    let shared = previous.segment(segment.sequence)?;
+                    Some(shared.start - segment.start)
+                })
+                .unwrap_or_else(|| previous.duration());
+            playlist.shift(offset);
+        }
+        if !self.duration_set {
+            self.duration_set = true;
+            media_source.set_duration(if playlist.end_list {
+                playlist.duration()
+            } else {
+                f64::INFINITY
+            });
+        }
+        if self.last_appended.is_none() && !playlist.end_list {
+            // Start live playback near the live edge
+            let start = playlist.segments.len().saturating_sub(LIVE_START_SEGMENTS);
+            self.last_appended = playlist
+                .segments
+                .get(start)
+                .and_then(|segment| segment.sequence.checked_sub(1));
+        }
+        self.playlist = Some(playlist);
+        self.playlist_loaded_at = js_sys::Date::now();
+    }
+
+    /// Variant for the selection and measured throughput
+    fn wanted_variant(&self) -> Option<usize> {
+        let bandwidths: Vec<_> = self
+            .variants
+            .iter()
+            .map(|variant| variant.bandwidth)
+            .collect();
+        self.selection.with_choice(self.abr_choice).select(
+            &bandwidths,
+            self.current,
+            self.throughput.estimate(),
+        )
+    }
+
+    /// Abort fetching a segment the element seeked away from
+    fn drop_stale_segment(
+        &mut self,
+        media_source: &MediaSourceVideo,
+        element: &web_sys::HtmlVideoElement,
+        current_time: f64,
+    ) {
+        let Some(playlist) = &self.playlist else {
+            return;
+        };
+        if !element.seeking() || self.is_buffered(media_source, current_time) {
+            return;
+        }
+        if let Some(Request::Segment(segment)) = self.downloader.request()
+            && playlist
+                .segment_at(current_time)
+                .is_some_and(|seeked| seeked.sequence != segment.sequence)
+        {
+            self.downloader.abort();
+        }
+    }
+
+    fn schedule(
+        &mut self,
+        media_source: &mut MediaSourceVideo,
+        element: &web_sys::HtmlVideoElement,
+        current_time: f64,
+    ) {
+        let Some(playlist) = &self.playlist else {
+            return;
+        };
+        if !playlist.end_list
+            && js_sys::Date::now() - self.playlist_loaded_at >= playlist.target_duration * 1000.0
+        {
+            self.reload_playlist();
+            return;
+        }
+        if let Some(variant) = self.wanted_variant()
+            && Some(variant) != self.current
+        {
+            self.start(Request::Playlist(variant));
+            return;
+        }
+        // Wait for appends so the buffered ranges include them
+        if self
+            .buffer
+            .is_some_and(|buffer| media_source.is_busy(buffer))
+        {
+            return;
+        }
+
+        let buffered = self
+            .buffer
+            .map(|buffer| media_source.buffered(buffer))
+            .unwrap_or_default();
+        // Live playback and segments starting late leave a gap before the first frame
+        if let Some(first) = buffered.first()
+            && current_time < first.start
+            && first.start - current_time < playlist.target_duration.max(GAP_TOLERANCE)
+            && !element.seeking()
+        {
+            element.set_current_time(first.start);
+        }
+        let playing_range = playing_range(&buffered, current_time);
+        if playing_range
+            .as_ref()
+            .is_some_and(|range| range.end - current_time >= MAX_BUFFER_AHEAD)
+        {
+            return;
+        }
+
+        // Continue after the last appended segment if it extends the playing range,
+        // otherwise fetch the segment at the end of the range or the seeked time
+        let last = self.last_appended.and_then(|sequence| {
+            playlist
+                .segment(sequence)
+                .map(|segment| (sequence, segment))
+        });
+        let next = match (playing_range, last) {
+            (Some(range), Some((sequence, segment)))
+                if segment.start < range.end + GAP_TOLERANCE
+                    && range.start < segment.start + segment.duration + GAP_TOLERANCE =>
+            {
+                sequence + 1
+            }
+            // Live playback starts after the segment set by set_playlist
+            (None, Some((sequence, _))) if buffered.is_empty() && !playlist.end_list => {
+                sequence + 1
+            }
+            (Some(range), _) => match playlist.segment_at(range.end + GAP_TOLERANCE) {
+                Some(segment) => segment.sequence,
+                None => return,
+            },
+            (None, _) => match playlist.segment_at(current_time) {
+                Some(segment) => segment.sequence,
+                None => return,
+            },
+        };
+        // Live playback fell behind the playlist window
+        let next = next.max(playlist.media_sequence);
+
+        let Some(segment) = playlist.segment(next) else {
+            if playlist.end_list && !self.ended {
+                self.ended = true;
+                media_source.end_of_stream();
+            }
+            return;
+        };
+        self.ended = false;
+        if let Some(init) = &segment.init
+            && self.init.as_ref() != Some(init)
+        {
+            self.start(Request::Init(init.clone()));
+        } else {
+            self.start(Request::Segment(segment.clone()));
+        }
+    }
+
+    fn is_buffered(&self, media_source: &MediaSourceVideo, time: f64) -> bool {
+        self.buffer
+            .is_some_and(|buffer| playing_range(&media_source.buffered(buffer), time).is_some())
+    }
+
+    fn reload_playlist(&mut self) {
+        match self.current {
+            Some(variant) => self.start(Request::Playlist(variant)),
+            None => self.start(Request::Source),
+        }
+    }
+}
+
+impl SegmentedStream for HlsStream {
+    fn renditions(&self) -> Vec<AbrRendition> {
+        self.variants
+            .iter()
+            .map(|variant| AbrRendition {
+                bandwidth: variant.bandwidth,
+                size: variant.resolution,
+            })
+            .collect()
+    }
+
+    fn current_rendition(&self) -> Option<usize> {
+        self.current
+    }
+
+    fn measured_throughput(&self) -> Option<f64> {
+        self.throughput.estimate()
+    }
+
+    fn video_buffer(&self) -> Option<SourceBufferId> {
+        self.buffer
+    }
+
+    fn set_abr_choice(&mut self, rendition: Option<usize>) {
+        self.abr_choice = rendition;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::Fetch;
+    use std::ops::Range;
+    use wasm_bindgen_futures::JsFuture;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    /// `tests/fixtures`, served by `python3 tests/fixtures/serve.py`
+    const FIXTURES: &str = "http://127.0.0.1:8787/";
+
+    /// Resolves after the next macrotask, so fetches can complete
+    async fn yield_to_browser() {
+        JsFuture::from(js_sys::Promise::new(&mut |resolve, _| {
+            web_sys::window()
+                .unwrap()
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, 10)
+                .unwrap();
+        }))
+        .await
+        .unwrap();
+    }
+
+    async fn fetch(path: &str, byte_range: Option<Range<u64>>) -> Result<Vec<u8>, VideoError> {
+        let fetch = Fetch::start(&format!("{FIXTURES}{path}"), byte_range);
+        loop {
+            if let Some((result, _)) = fetch.take() {
+                return result;
+            }
+            yield_to_browser().await;
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn remuxes_fixture_playlist() {
+        let url = format!("{FIXTURES}hls/index.m3u8");
+        let text = String::from_utf8(fetch("hls/index.m3u8", None).await.unwrap()).unwrap();
+        let Ok(HlsPlaylist::Media(playlist)) = HlsPlaylist::parse(&text, &url) else {
+            panic!("expected a media playlist");
+        };
+        assert!(playlist.end_list);
+        assert_eq!(playlist.segments.len(), 2);
+
+        let mut transmuxer = Transmuxer::default();
+        for segment in &playlist.segments {
+            let bytes = fetch(&segment.uri[FIXTURES.len()..], segment.byte_range.clone())
+                .await
+                .unwrap();
+            // Range requests return only the segment, which starts with a TS packet
+            assert_eq!(
+                bytes.len() as u64,
+                segment.byte_range.as_ref().unwrap().end
+                    - segment.byte_range.as_ref().unwrap().start
+            );
+            assert_eq!(bytes[0], 0x47);
+
+            let remuxed = transmuxer.remux(&bytes, segment.start).unwrap();
+            assert_eq!(remuxed.init.is_some(), segment.sequence == 0);
+            assert!(
+                web_sys::MediaSource::is_type_supported(&remuxed.mime_type),
+                "{}",
+                remuxed.mime_type
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn fails_missing_fixture() {
+        assert!(matches!(
+            fetch("hls/missing.ts", None).await,
+            Err(VideoError::SrcNotSupported)
+        ));
+    }
+}
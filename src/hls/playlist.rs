@@ -0,0 +1,528 @@
+//! HLS playlists, parsed in pure Rust independently of the element and MSE
+
+use crate::VideoError;
+use bevy::prelude::*;
+use std::ops::Range;
+use url::Url;
+
+/// A parsed `.m3u8` playlist
+#[derive(Clone, Debug, PartialEq)]
+pub enum HlsPlaylist {
+    /// Lists the variant streams
+    Master(HlsMasterPlaylist),
+    /// Lists the segments of one variant
+    Media(HlsMediaPlaylist),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HlsMasterPlaylist {
+    /// In playlist order, the first is the default
+    pub variants: Vec<HlsVariant>,
+}
+
+/// An `EXT-X-STREAM-INF` variant stream
+#[derive(Reflect, Clone, Debug, Default, PartialEq)]
+pub struct HlsVariant {
+    /// Absolute URL of the media playlist
+    pub uri: String,
+    /// Peak bits per second
+    pub bandwidth: u64,
+    pub average_bandwidth: Option<u64>,
+    pub resolution: Option<UVec2>,
+    /// RFC 6381 codecs, e.g. `avc1.64001f,mp4a.40.2`
+    pub codecs: Option<String>,
+    pub frame_rate: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HlsMediaPlaylist {
+    /// Seconds
+    pub target_duration: f64,
+    /// Sequence number of the first segment
+    pub media_sequence: u64,
+    pub segments: Vec<HlsSegment>,
+    /// `EXT-X-ENDLIST`, no segments are added, otherwise the playlist is live and reloaded
+    pub end_list: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HlsSegment {
+    /// Absolute URL
+    pub uri: String,
+    pub sequence: u64,
+    /// Seconds from the start of the playlist
+    pub start: f64,
+    /// Seconds
+    pub duration: f64,
+    pub byte_range: Option<Range<u64>>,
+    /// `EXT-X-MAP` initialization segment of fragmented MP4 segments
+    pub init: Option<HlsInitSegment>,
+    /// `EXT-X-DISCONTINUITY`, timestamps and encoding may change from this segment
+    pub discontinuity: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HlsInitSegment {
+    /// Absolute URL
+    pub uri: String,
+    pub byte_range: Option<Range<u64>>,
+}
+
+impl HlsPlaylist {
+    /// Parse the playlist `text` fetched from `url`, relative URIs are resolved against it.
+    ///
+    /// Encrypted segments are [`VideoError::NotSupported`].
+    pub fn parse(text: &str, url: &str) -> Result<Self, VideoError> {
+        let base = Url::parse(url).map_err(|err| error(&format!("invalid URL {url}: {err}")))?;
+        let mut lines = text
+            .trim_start_matches('\u{feff}')
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+        if lines.next() != Some("#EXTM3U") {
+            return Err(error("missing #EXTM3U"));
+        }
+        if text.contains("#EXT-X-STREAM-INF") {
+            parse_master(lines, &base).map(HlsPlaylist::Master)
+        } else {
+            parse_media(lines, &base).map(HlsPlaylist::Media)
+        }
+    }
+}
+
+impl HlsMediaPlaylist {
+    /// Seconds
+    pub fn duration(&self) -> f64 {
+        self.segments
+            .last()
+            .map(|segment| segment.start + segment.duration)
+            .unwrap_or_default()
+    }
+
+    /// Segment playing at `time` in seconds, the last one past the end
+    pub fn segment_at(&self, time: f64) -> Option<&HlsSegment> {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start + segment.duration <= time);
+        self.segments.get(index).or(self.segments.last())
+    }
+
+    pub fn segment(&self, sequence: u64) -> Option<&HlsSegment> {
+        let index = sequence.checked_sub(self.media_sequence)?;
+        self.segments.get(index as usize)
+    }
+
+    /// Shift segment start times by `offset` seconds, e.g. to continue the timeline of a
+    /// reloaded live playlist
+    pub fn shift(&mut self, offset: f64) {
+        for segment in &mut self.segments {
+            segment.start += offset;
+        }
+    }
+}
+
+fn error(message: &str) -> VideoError {
+    VideoError::Demux(format!("m3u8: {message}"))
+}
+
+fn parse_master<'a>(
+    mut lines: impl Iterator<Item = &'a str>,
+    base: &Url,
+) -> Result<HlsMasterPlaylist, VideoError> {
+    let mut playlist = HlsMasterPlaylist::default();
+    while let Some(line) = lines.next() {
+        let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") else {
+            continue;
+        };
+        let attributes = Attributes::parse(attributes);
+        let uri = lines
+            .find(|line| !line.starts_with('#'))
+            .ok_or_else(|| error("EXT-X-STREAM-INF without URI"))?;
+        playlist.variants.push(HlsVariant {
+            uri: resolve(base, uri)?,
+            bandwidth: attributes
+                .number("BANDWIDTH")
+                .ok_or_else(|| error("EXT-X-STREAM-INF without BANDWIDTH"))?,
+            average_bandwidth: attributes.number("AVERAGE-BANDWIDTH"),
+            resolution: attributes.get("RESOLUTION").and_then(|resolution| {
+                let (width, height) = resolution.split_once(['x', 'X'])?;
+                Some(UVec2::new(width.parse().ok()?, height.parse().ok()?))
+            }),
+            codecs: attributes.get("CODECS").map(str::to_string),
+            frame_rate: attributes.number("FRAME-RATE"),
+        });
+    }
+    if playlist.variants.is_empty() {
+        return Err(error("no variants"));
+    }
+    Ok(playlist)
+}
+
+fn parse_media<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    base: &Url,
+) -> Result<HlsMediaPlaylist, VideoError> {
+    let mut playlist = HlsMediaPlaylist::default();
+    // Tags apply to the next segment URI
+    let mut duration = None;
+    let mut byte_range = None;
+    let mut discontinuity = false;
+    let mut init = None;
+    // Byte ranges without an offset follow the previous range of the same URI
+    let mut previous_range: Option<(String, u64)> = None;
+    let mut start = 0.0;
+
+    for line in lines {
+        if let Some(tag) = line.strip_prefix('#') {
+            let (name, value) = tag.split_once(':').unwrap_or((tag, ""));
+            match name {
+                "EXT-X-TARGETDURATION" => {
+                    playlist.target_duration = value
+                        .parse()
+                        .map_err(|_| error("invalid EXT-X-TARGETDURATION"))?;
+                }
+                "EXT-X-MEDIA-SEQUENCE" => {
+                    playlist.media_sequence = value
+                        .parse()
+                        .map_err(|_| error("invalid EXT-X-MEDIA-SEQUENCE"))?;
+                }
+                "EXTINF" => {
+                    let seconds = value.split(',').next().unwrap_or_default();
+                    duration = Some(seconds.parse().map_err(|_| error("invalid EXTINF"))?);
+                }
+                "EXT-X-BYTERANGE" => byte_range = Some(value.to_string()),
+                "EXT-X-DISCONTINUITY" => discontinuity = true,
+                "EXT-X-ENDLIST" => playlist.end_list = true,
+                "EXT-X-MAP" => {
+                    let attributes = Attributes::parse(value);
+                    let uri = resolve(
+                        base,
+                        attributes
+                            .get("URI")
+                            .ok_or_else(|| error("EXT-X-MAP without URI"))?,
+                    )?;
+                    let byte_range = attributes
+                        .get("BYTERANGE")
+                        .map(|range| parse_byte_range(range, None))
+                        .transpose()?;
+                    init = Some(HlsInitSegment { uri, byte_range });
+                }
+                "EXT-X-KEY" if Attributes::parse(value).get("METHOD") != Some("NONE") => {
+                    return Err(VideoError::NotSupported);
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let uri = resolve(base, line)?;
+        let duration = duration
+            .take()
+            .ok_or_else(|| error("segment without EXTINF"))?;
+        let byte_range = byte_range
+            .take()
+            .map(|range| {
+                let follows = previous_range
+                    .as_ref()
+                    .filter(|(previous_uri, _)| *previous_uri == uri)
+                    .map(|(_, end)| *end);
+                parse_byte_range(&range, follows)
+            })
+            .transpose()?;
+        previous_range = byte_range.as_ref().map(|range| (uri.clone(), range.end));
+        playlist.segments.push(HlsSegment {
+            uri,
+            sequence: playlist.media_sequence + playlist.segments.len() as u64,
+            start,
+            duration,
+            byte_range,
+            init: init.clone(),
+            discontinuity: std::mem::take(&mut discontinuity),
+        });
+        start += duration;
+    }
+    Ok(playlist)
+}
+
+fn resolve(base: &Url, uri: &str) -> Result<String, VideoError> {
+    base.join(uri)
+        .map(String::from)
+        .map_err(|err| error(&format!("invalid URI {uri}: {err}")))
+}
+
+/// `<length>[@<offset>]`, the offset defaults to `follows`
+fn parse_byte_range(range: &str, follows: Option<u64>) -> Result<Range<u64>, VideoError> {
+    let invalid = || error(&format!("invalid byte range {range}"));
+    let (length, offset) = match range.split_once('@') {
+        Some((length, offset)) => (length, Some(offset.parse().map_err(|_| invalid())?)),
+        None => (range, follows),
+    };
+    let length: u64 = length.parse().map_err(|_| invalid())?;
+    let offset = offset.ok_or_else(invalid)?;
+    Ok(offset..offset + length)
+}
+
+/// `NAME=value` pairs of a tag, values may be quoted and contain commas
+struct Attributes<'a>(Vec<(&'a str, &'a str)>);
+
+impl<'a> Attributes<'a> {
+    fn parse(mut text: &'a str) -> Self {
+        let mut attributes = Vec::new();
+        while let Some((name, rest)) = text.split_once('=') {
+            let (value, rest) = match rest.strip_prefix('"') {
+                Some(quoted) => {
+                    let (value, rest) = quoted.split_once('"').unwrap_or((quoted, ""));
+                    (value, rest.trim_start_matches(','))
+                }
+                None => rest.split_once(',').unwrap_or((rest, "")),
+            };
+            attributes.push((name.trim(), value));
+            text = rest;
+        }
+        Self(attributes)
+    }
+
+    fn get(&self, name: &str) -> Option<&'a str> {
+        self.0
+            .iter()
+            .find(|(attribute, _)| *attribute == name)
+            .map(|(_, value)| *value)
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name)?.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    const URL: &str = "https://cdn.example/show/master.m3u8";
+
+    fn media(text: &str) -> HlsMediaPlaylist {
+        match HlsPlaylist::parse(text, URL).unwrap() {
+            HlsPlaylist::Media(playlist) => playlist,
+            playlist => panic!("expected a media playlist, got {playlist:?}"),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn parses_master_playlist() {
+        let text = "\u{feff}#EXTM3U
+#EXT-X-VERSION:6
+#EXT-X-STREAM-INF:BANDWIDTH=1280000,AVERAGE-BANDWIDTH=1000000,RESOLUTION=1280x720,CODECS=\"avc1.64001f,mp4a.40.2\",FRAME-RATE=29.97
+720/index.m3u8
+
+#EXT-X-STREAM-INF:BANDWIDTH=640000
+https://other.example/360.m3u8
+";
+        let HlsPlaylist::Master(playlist) = HlsPlaylist::parse(text, URL).unwrap() else {
+            panic!("expected a master playlist");
+        };
+        assert_eq!(
+            playlist.variants,
+            [
+                HlsVariant {
+                    uri: "https://cdn.example/show/720/index.m3u8".into(),
+                    bandwidth: 1_280_000,
+                    average_bandwidth: Some(1_000_000),
+                    resolution: Some(UVec2::new(1280, 720)),
+                    codecs: Some("avc1.64001f,mp4a.40.2".into()),
+                    frame_rate: Some(29.97),
+                },
+                HlsVariant {
+                    uri: "https://other.example/360.m3u8".into(),
+                    bandwidth: 640_000,
+                    ..default()
+                },
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_invalid_master_playlists() {
+        for text in [
+            "#EXT-X-STREAM-INF:BANDWIDTH=1\na.m3u8",
+            "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=1\n",
+            "#EXTM3U\n#EXT-X-STREAM-INF:RESOLUTION=1x1\na.m3u8",
+        ] {
+            assert!(
+                matches!(HlsPlaylist::parse(text, URL), Err(VideoError::Demux(_))),
+                "{text}"
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn parses_media_playlist() {
+        let playlist = media(
+            "#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXT-X-MEDIA-SEQUENCE:7
+#EXTINF:6.0,
+seg7.ts
+#EXTINF:5.5,title
+seg8.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:2,
+/ad/seg0.ts
+#EXT-X-ENDLIST
+",
+        );
+        assert_eq!(playlist.target_duration, 6.0);
+        assert!(playlist.end_list);
+        assert_eq!(playlist.duration(), 13.5);
+        let segments = &playlist.segments;
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].uri, "https://cdn.example/show/seg7.ts");
+        assert_eq!(segments[2].uri, "https://cdn.example/ad/seg0.ts");
+        assert_eq!(
+            segments
+                .iter()
+                .map(|segment| (segment.sequence, segment.start, segment.discontinuity))
+                .collect::<Vec<_>>(),
+            [(7, 0.0, false), (8, 6.0, false), (9, 11.5, true)]
+        );
+
+        assert_eq!(playlist.segment_at(6.0).unwrap().sequence, 8);
+        assert_eq!(playlist.segment_at(100.0).unwrap().sequence, 9);
+        assert_eq!(playlist.segment(9).unwrap().start, 11.5);
+        assert!(playlist.segment(6).is_none());
+        assert!(playlist.segment(10).is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn continues_byte_ranges() {
+        let playlist = media(
+            "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXTINF:4,
+#EXT-X-BYTERANGE:1000@200
+all.ts
+#EXTINF:4,
+#EXT-X-BYTERANGE:500
+all.ts
+#EXTINF:4,
+#EXT-X-BYTERANGE:300
+all.ts
+",
+        );
+        assert_eq!(
+            playlist
+                .segments
+                .iter()
+                .map(|segment| segment.byte_range.clone().unwrap())
+                .collect::<Vec<_>>(),
+            [200..1200, 1200..1700, 1700..2000]
+        );
+        // Nothing to continue from in another URI
+        let text = "#EXTM3U
+#EXTINF:4,
+#EXT-X-BYTERANGE:1000@0
+a.ts
+#EXTINF:4,
+#EXT-X-BYTERANGE:500
+b.ts
+";
+        assert!(matches!(
+            HlsPlaylist::parse(text, URL),
+            Err(VideoError::Demux(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn applies_init_segments() {
+        let playlist = media(
+            "#EXTM3U
+#EXTINF:4,
+plain.mp4
+#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"
+#EXTINF:4,
+a.m4s
+#EXTINF:4,
+b.m4s
+",
+        );
+        let init = HlsInitSegment {
+            uri: "https://cdn.example/show/init.mp4".into(),
+            byte_range: Some(0..720),
+        };
+        let inits = playlist
+            .segments
+            .iter()
+            .map(|segment| segment.init.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(inits, [None, Some(init.clone()), Some(init)]);
+        assert!(matches!(
+            HlsPlaylist::parse("#EXTM3U\n#EXT-X-MAP:BYTERANGE=\"1@0\"\n", URL),
+            Err(VideoError::Demux(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_encrypted_segments() {
+        let encrypted = "#EXTM3U
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x0
+#EXTINF:4,
+a.ts
+";
+        assert!(matches!(
+            HlsPlaylist::parse(encrypted, URL),
+            Err(VideoError::NotSupported)
+        ));
+        let unencrypted = "#EXTM3U\n#EXT-X-KEY:METHOD=NONE\n#EXTINF:4,\na.ts\n";
+        assert_eq!(media(unencrypted).segments.len(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn shifts_reloaded_live_playlist() {
+        let previous = media(
+            "#EXTM3U
+#EXT-X-MEDIA-SEQUENCE:10
+#EXTINF:4,
+10.ts
+#EXTINF:4,
+11.ts
+#EXTINF:4,
+12.ts
+",
+        );
+        assert!(!previous.end_list);
+        let mut reloaded = media(
+            "#EXTM3U
+#EXT-X-MEDIA-SEQUENCE:12
+#EXTINF:4,
+12.ts
+#EXTINF:4,
+13.ts
+#EXTINF:4,
+14.ts
+",
+        );
+        // Segment 12 is in both, the reload continues from its start
+        let shared = reloaded.segment(12).unwrap();
+        assert_eq!(shared.start, 0.0);
+        reloaded.shift(previous.segment(12).unwrap().start - shared.start);
+        assert_eq!(reloaded.segment(12).unwrap().start, 8.0);
+        assert_eq!(reloaded.segment(14).unwrap().start, 16.0);
+        assert_eq!(reloaded.segment_at(17.0).unwrap().sequence, 14);
+        assert_eq!(reloaded.duration(), 20.0);
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_invalid_media_playlists() {
+        for text in [
+            "#EXTM3U\nsegment.ts\n",
+            "#EXTM3U\n#EXTINF:four,\nsegment.ts\n",
+            "#EXTM3U\n#EXT-X-TARGETDURATION:x\n",
+            "#EXTM3U\n#EXTINF:4,\n#EXT-X-BYTERANGE:x@0\nsegment.ts\n",
+        ] {
+            assert!(
+                matches!(HlsPlaylist::parse(text, URL), Err(VideoError::Demux(_))),
+                "{text}"
+            );
+        }
+    }
+}
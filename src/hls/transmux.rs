@@ -0,0 +1,1213 @@
+//! MPEG-TS segments remuxed to fragmented MP4, browsers only accept the latter through MSE.
+//! Supports H.264 video and ADTS AAC audio, the codecs of TS based HLS.
+
+use crate::VideoError;
+use bevy::prelude::*;
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+/// Timestamps of TS are 33 bit, 90kHz
+const TIMESCALE: u64 = 90_000;
+const TIMESTAMP_MASK: u64 = (1 << 33) - 1;
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_AAC: u8 = 0x0f;
+const VIDEO_TRACK: u32 = 1;
+const AUDIO_TRACK: u32 = 2;
+/// Samples per AAC frame
+const AAC_FRAME_LENGTH: u32 = 1024;
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Remuxes the segments of one stream, keeping their timeline across segments and variants
+#[derive(Default)]
+pub(super) struct Transmuxer {
+    // TS timestamp shown at the playlist time in seconds
+    base: Option<(u64, f64)>,
+    // Codecs of the last initialization segment
+    config: Option<StreamConfig>,
+    sequence: u32,
+}
+
+pub(super) struct Remuxed {
+    /// For the first segment and when the codecs change, e.g. on a variant switch
+    pub init: Option<Vec<u8>>,
+    /// MSE type of the remuxed segments
+    pub mime_type: String,
+    pub fragment: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq)]
+struct StreamConfig {
+    video: Option<VideoConfig>,
+    audio: Option<AudioConfig>,
+}
+
+#[derive(Clone, PartialEq)]
+struct VideoConfig {
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+    size: UVec2,
+}
+
+#[derive(Clone, PartialEq)]
+struct AudioConfig {
+    object_type: u8,
+    sample_rate_index: u8,
+    channels: u8,
+}
+
+impl AudioConfig {
+    fn sample_rate(&self) -> u32 {
+        AAC_SAMPLE_RATES[self.sample_rate_index as usize]
+    }
+}
+
+struct Sample {
+    /// Timescale units
+    duration: u32,
+    data: Vec<u8>,
+    key: bool,
+    composition_offset: i32,
+}
+
+struct Track {
+    id: u32,
+    /// Timescale units
+    decode_time: u64,
+    samples: Vec<Sample>,
+}
+
+impl Transmuxer {
+    /// Timestamps restart at `start` seconds with the next segment, i.e. `EXT-X-DISCONTINUITY`
+    pub(super) fn discontinuity(&mut self) {
+        self.base = None;
+    }
+
+    /// Remux the TS `data` of a segment starting at `start` seconds in the playlist
+    pub(super) fn remux(&mut self, data: &[u8], start: f64) -> Result<Remuxed, VideoError> {
+        let streams = demux(data)?;
+        let (video_config, video_samples) = video_samples(&streams.video)?;
+        let (audio_config, audio_frames) = audio_frames(&streams.audio)?;
+        if video_samples.is_empty() && audio_frames.is_empty() {
+            return Err(error("no H.264 or AAC samples"));
+        }
+
+        let first_timestamp = video_samples
+            .first()
+            .map(|sample| sample.dts)
+            .or(audio_frames.first().map(|(pts, _)| *pts))
+            .unwrap_or_default();
+        let (base_timestamp, base_time) = *self.base.get_or_insert((first_timestamp, start));
+        // 90kHz ticks on the playlist timeline, timestamps wrap around after about 26 hours
+        let ticks = |timestamp: u64| {
+            let elapsed = timestamp.wrapping_sub(base_timestamp) & TIMESTAMP_MASK;
+            let elapsed = if elapsed > TIMESTAMP_MASK / 2 {
+                elapsed as i64 - (TIMESTAMP_MASK as i64 + 1)
+            } else {
+                elapsed as i64
+            };
+            (elapsed + (base_time * TIMESCALE as f64) as i64).max(0) as u64
+        };
+
+        let mut tracks = Vec::new();
+        if let Some(first) = video_samples.first() {
+            let mut samples = Vec::with_capacity(video_samples.len());
+            let mut previous_duration = (TIMESCALE / 30) as u32;
+            for (index, sample) in video_samples.iter().enumerate() {
+                let duration = video_samples
+                    .get(index + 1)
+                    .map(|next| ticks(next.dts).saturating_sub(ticks(sample.dts)) as u32)
+                    .unwrap_or(previous_duration);
+                previous_duration = duration;
+                samples.push(Sample {
+                    duration,
+                    data: sample.data.clone(),
+                    key: sample.key,
+                    composition_offset: (ticks(sample.pts) as i64 - ticks(sample.dts) as i64)
+                        as i32,
+                });
+            }
+            tracks.push(Track {
+                id: VIDEO_TRACK,
+                decode_time: ticks(first.dts),
+                samples,
+            });
+        }
+        if let (Some(config), Some((pts, _))) = (&audio_config, audio_frames.first()) {
+            tracks.push(Track {
+                id: AUDIO_TRACK,
+                decode_time: ticks(*pts) * config.sample_rate() as u64 / TIMESCALE,
+                samples: audio_frames
+                    .into_iter()
+                    .map(|(_, data)| Sample {
+                        duration: AAC_FRAME_LENGTH,
+                        data,
+                        key: true,
+                        composition_offset: 0,
+                    })
+                    .collect(),
+            });
+        }
+
+        let config = StreamConfig {
+            video: video_config,
+            audio: audio_config,
+        };
+        let mime_type = config.mime_type();
+        let init = (self.config.as_ref() != Some(&config)).then(|| init_segment(&config));
+        self.config = Some(config);
+        self.sequence += 1;
+        Ok(Remuxed {
+            init,
+            mime_type,
+            fragment: fragment(self.sequence, &tracks),
+        })
+    }
+}
+
+impl StreamConfig {
+    fn mime_type(&self) -> String {
+        let codecs = self
+            .video
+            .iter()
+            .map(|video| {
+                format!(
+                    "avc1.{:02x}{:02x}{:02x}",
+                    video.sps[1], video.sps[2], video.sps[3]
+                )
+            })
+            .chain(
+                self.audio
+                    .iter()
+                    .map(|audio| format!("mp4a.40.{}", audio.object_type)),
+            )
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("video/mp4; codecs=\"{codecs}\"")
+    }
+}
+
+fn error(message: &str) -> VideoError {
+    VideoError::Demux(format!("ts: {message}"))
+}
+
+/// PES packets of the first H.264 and AAC streams
+#[derive(Default)]
+struct Streams {
+    video: Vec<Pes>,
+    audio: Vec<Pes>,
+}
+
+struct Pes {
+    pts: Option<u64>,
+    dts: Option<u64>,
+    payload: Vec<u8>,
+}
+
+fn demux(data: &[u8]) -> Result<Streams, VideoError> {
+    let mut pmt_pid = None;
+    let mut video_pid = None;
+    let mut audio_pid = None;
+    let mut video = PesAssembler::default();
+    let mut audio = PesAssembler::default();
+
+    for packet in data.as_chunks::<PACKET_SIZE>().0 {
+        if packet[0] != SYNC_BYTE {
+            return Err(error("lost sync"));
+        }
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+        let adaptation_field = packet[3] & 0x20 != 0;
+        let has_payload = packet[3] & 0x10 != 0;
+        let offset = if adaptation_field {
+            5 + packet[4] as usize
+        } else {
+            4
+        };
+        let Some(payload) = packet.get(offset..).filter(|_| has_payload) else {
+            continue;
+        };
+
+        if pid == 0 {
+            if unit_start {
+                pmt_pid = parse_pat(payload).or(pmt_pid);
+            }
+        } else if Some(pid) == pmt_pid {
+            if unit_start {
+                for (stream_type, pid) in parse_pmt(payload) {
+                    match stream_type {
+                        STREAM_TYPE_H264 => {
+                            video_pid.get_or_insert(pid);
+                        }
+                        STREAM_TYPE_AAC => {
+                            audio_pid.get_or_insert(pid);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        } else if Some(pid) == video_pid {
+            video.push(unit_start, payload);
+        } else if Some(pid) == audio_pid {
+            audio.push(unit_start, payload);
+        }
+    }
+    if pmt_pid.is_none() {
+        return Err(error("no program map table"));
+    }
+    Ok(Streams {
+        video: video.finish(),
+        audio: audio.finish(),
+    })
+}
+
+/// Section of a PSI table, after the pointer field
+fn section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let table = payload.get(1 + pointer..)?;
+    let length = (u16::from_be_bytes([*table.get(1)?, *table.get(2)?]) & 0x0fff) as usize;
+    // Without the CRC
+    table.get(3..(3 + length).checked_sub(4)?)
+}
+
+/// PID of the first program's map table
+fn parse_pat(payload: &[u8]) -> Option<u16> {
+    section(payload)?
+        .get(5..)?
+        .as_chunks::<4>()
+        .0
+        .iter()
+        .find(|[number_high, number_low, ..]| u16::from_be_bytes([*number_high, *number_low]) != 0)
+        .map(|[_, _, pid_high, pid_low]| u16::from_be_bytes([pid_high & 0x1f, *pid_low]))
+}
+
+/// Stream type and PID of each elementary stream
+fn parse_pmt(payload: &[u8]) -> Vec<(u8, u16)> {
+    let mut streams = Vec::new();
+    let Some(section) = section(payload) else {
+        return streams;
+    };
+    let Some(program_info_length) = section
+        .get(7..9)
+        .map(|length| (u16::from_be_bytes([length[0], length[1]]) & 0x0fff) as usize)
+    else {
+        return streams;
+    };
+    let mut entries = section.get(9 + program_info_length..).unwrap_or_default();
+    while let [
+        stream_type,
+        pid_high,
+        pid_low,
+        info_high,
+        info_low,
+        rest @ ..,
+    ] = entries
+    {
+        streams.push((
+            *stream_type,
+            u16::from_be_bytes([pid_high & 0x1f, *pid_low]),
+        ));
+        let info_length = (u16::from_be_bytes([*info_high, *info_low]) & 0x0fff) as usize;
+        entries = rest.get(info_length..).unwrap_or_default();
+    }
+    streams
+}
+
+#[derive(Default)]
+struct PesAssembler {
+    packets: Vec<Pes>,
+    current: Vec<u8>,
+}
+
+impl PesAssembler {
+    fn push(&mut self, unit_start: bool, payload: &[u8]) {
+        if unit_start {
+            self.flush();
+        }
+        self.current.extend_from_slice(payload);
+    }
+
+    fn flush(&mut self) {
+        let data = std::mem::take(&mut self.current);
+        if let Some(pes) = parse_pes(&data) {
+            self.packets.push(pes);
+        }
+    }
+
+    fn finish(mut self) -> Vec<Pes> {
+        self.flush();
+        self.packets
+    }
+}
+
+fn parse_pes(data: &[u8]) -> Option<Pes> {
+    if !data.starts_with(&[0, 0, 1]) {
+        return None;
+    }
+    let flags = data.get(7)? >> 6;
+    let header_length = *data.get(8)? as usize;
+    let timestamp = |offset: usize| -> Option<u64> {
+        let bytes = data.get(offset..offset + 5)?;
+        Some(
+            ((bytes[0] as u64 >> 1) & 0x07) << 30
+                | (bytes[1] as u64) << 22
+                | (bytes[2] as u64 >> 1) << 15
+                | (bytes[3] as u64) << 7
+                | bytes[4] as u64 >> 1,
+        )
+    };
+    let pts = if flags & 0x2 != 0 { timestamp(9) } else { None };
+    let dts = if flags == 0x3 { timestamp(14) } else { pts };
+    Some(Pes {
+        pts,
+        dts,
+        payload: data.get(9 + header_length..)?.to_vec(),
+    })
+}
+
+struct VideoSample {
+    pts: u64,
+    dts: u64,
+    /// Length prefixed NAL units
+    data: Vec<u8>,
+    key: bool,
+}
+
+const NAL_IDR: u8 = 5;
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+const NAL_AUD: u8 = 9;
+
+/// One access unit per PES, as HLS segments are packaged
+fn video_samples(packets: &[Pes]) -> Result<(Option<VideoConfig>, Vec<VideoSample>), VideoError> {
+    let mut sps = None;
+    let mut pps = None;
+    let mut samples = Vec::new();
+    for pes in packets {
+        let (Some(pts), Some(dts)) = (pes.pts, pes.dts) else {
+            continue;
+        };
+        let mut data = Vec::new();
+        let mut key = false;
+        for nal in nal_units(&pes.payload) {
+            match nal[0] & 0x1f {
+                NAL_SPS => {
+                    sps.get_or_insert_with(|| nal.to_vec());
+                }
+                NAL_PPS => {
+                    pps.get_or_insert_with(|| nal.to_vec());
+                }
+                NAL_AUD => {}
+                nal_type => {
+                    key |= nal_type == NAL_IDR;
+                    data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    data.extend_from_slice(nal);
+                }
+            }
+        }
+        if !data.is_empty() {
+            samples.push(VideoSample {
+                pts,
+                dts,
+                data,
+                key,
+            });
+        }
+    }
+
+    let config = match (sps, pps) {
+        (Some(sps), Some(pps)) if sps.len() >= 4 => Some(VideoConfig {
+            size: sps_size(&sps).ok_or_else(|| error("invalid SPS"))?,
+            sps,
+            pps,
+        }),
+        _ if samples.is_empty() => None,
+        _ => return Err(error("H.264 stream without SPS and PPS")),
+    };
+    Ok((config, samples))
+}
+
+/// NAL units of an Annex B byte stream
+fn nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut starts = Vec::new();
+    let mut index = 0;
+    while index + 3 <= data.len() {
+        if data[index..index + 3] == [0, 0, 1] {
+            starts.push(index + 3);
+            index += 3;
+        } else {
+            index += 1;
+        }
+    }
+    let ends = starts
+        .iter()
+        .skip(1)
+        .map(|start| start - 3)
+        .chain([data.len()])
+        .collect::<Vec<_>>();
+    starts
+        .into_iter()
+        .zip(ends)
+        .filter_map(move |(start, end)| {
+            // Zero bytes before a start code belong to it, e.g. 4 byte start codes
+            let mut end = end;
+            while end > start && data[end - 1] == 0 {
+                end -= 1;
+            }
+            (end > start).then(|| &data[start..end])
+        })
+}
+
+/// Display size from a sequence parameter set
+fn sps_size(sps: &[u8]) -> Option<UVec2> {
+    // Emulation prevention bytes are removed before parsing
+    let mut rbsp = Vec::with_capacity(sps.len());
+    for (index, byte) in sps.iter().enumerate() {
+        if *byte == 3 && index >= 2 && sps[index - 2..index] == [0, 0] {
+            continue;
+        }
+        rbsp.push(*byte);
+    }
+    let mut bits = BitReader::new(rbsp.get(1..)?);
+    let profile = bits.bits(8)?;
+    bits.bits(16)?; // constraint flags and level
+    bits.exp_golomb()?; // seq_parameter_set_id
+    let mut chroma_format = 1;
+    if matches!(
+        profile,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format = bits.exp_golomb()?;
+        if chroma_format == 3 {
+            bits.bits(1)?; // separate_colour_plane_flag
+        }
+        bits.exp_golomb()?; // bit_depth_luma_minus8
+        bits.exp_golomb()?; // bit_depth_chroma_minus8
+        bits.bits(1)?; // qpprime_y_zero_transform_bypass_flag
+        if bits.bits(1)? == 1 {
+            for list in 0..if chroma_format == 3 { 12 } else { 8 } {
+                if bits.bits(1)? == 1 {
+                    skip_scaling_list(&mut bits, if list < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+    bits.exp_golomb()?; // log2_max_frame_num_minus4
+    match bits.exp_golomb()? {
+        0 => {
+            bits.exp_golomb()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            bits.bits(1)?; // delta_pic_order_always_zero_flag
+            bits.signed_exp_golomb()?; // offset_for_non_ref_pic
+            bits.signed_exp_golomb()?; // offset_for_top_to_bottom_field
+            for _ in 0..bits.exp_golomb()? {
+                bits.signed_exp_golomb()?; // offset_for_ref_frame
+            }
+        }
+        _ => {}
+    }
+    bits.exp_golomb()?; // max_num_ref_frames
+    bits.bits(1)?; // gaps_in_frame_num_value_allowed_flag
+    let width_in_macroblocks = bits.exp_golomb()?.checked_add(1)?;
+    let height_in_map_units = bits.exp_golomb()?.checked_add(1)?;
+    let frame_mbs_only = bits.bits(1)?;
+    if frame_mbs_only == 0 {
+        bits.bits(1)?; // mb_adaptive_frame_field_flag
+    }
+    bits.bits(1)?; // direct_8x8_inference_flag
+    let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+    if bits.bits(1)? == 1 {
+        crop_left = bits.exp_golomb()?;
+        crop_right = bits.exp_golomb()?;
+        crop_top = bits.exp_golomb()?;
+        crop_bottom = bits.exp_golomb()?;
+    }
+
+    let (crop_unit_x, crop_unit_y) = match chroma_format {
+        0 | 3 => (1, 2 - frame_mbs_only),
+        1 => (2, 2 * (2 - frame_mbs_only)),
+        _ => (2, 2 - frame_mbs_only),
+    };
+    // Sizes come from the stream, a corrupt SPS may overflow them
+    let crop = |start: u32, end: u32, unit: u32| start.checked_add(end)?.checked_mul(unit);
+    let width = width_in_macroblocks.checked_mul(16)?.checked_sub(crop(
+        crop_left,
+        crop_right,
+        crop_unit_x,
+    )?)?;
+    let height = height_in_map_units
+        .checked_mul(16 * (2 - frame_mbs_only))?
+        .checked_sub(crop(crop_top, crop_bottom, crop_unit_y)?)?;
+    Some(UVec2::new(width, height))
+}
+
+fn skip_scaling_list(bits: &mut BitReader, size: usize) -> Option<()> {
+    let mut last = 8;
+    let mut next = 8;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + bits.signed_exp_golomb()? as i64).rem_euclid(256);
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = self.data.get(self.position / 8)?;
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+        Some(value)
+    }
+
+    fn exp_golomb(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while self.bits(1)? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+        Some((1 << leading_zeros) - 1 + self.bits(leading_zeros)?)
+    }
+
+    fn signed_exp_golomb(&mut self) -> Option<i32> {
+        let value = self.exp_golomb()? as i64;
+        Some(if value % 2 == 1 {
+            ((value + 1) / 2) as i32
+        } else {
+            -(value / 2) as i32
+        })
+    }
+}
+
+/// Raw AAC frames and their timestamps
+type AudioFrames = (Option<AudioConfig>, Vec<(u64, Vec<u8>)>);
+
+/// Frames follow the timestamp of the first contiguously
+fn audio_frames(packets: &[Pes]) -> Result<AudioFrames, VideoError> {
+    let mut config = None;
+    let mut frames = Vec::new();
+    let mut first_pts = None;
+    // Frames may continue in the next PES
+    let mut pending = Vec::new();
+    for pes in packets {
+        if first_pts.is_none() && pending.is_empty() {
+            first_pts = pes.pts;
+        }
+        pending.extend_from_slice(&pes.payload);
+        let mut offset = 0;
+        while let Some(header) = pending.get(offset..offset + 7) {
+            if header[0] != 0xff || header[1] & 0xf0 != 0xf0 {
+                return Err(error("lost ADTS sync"));
+            }
+            let header_length = if header[1] & 0x01 == 0 { 9 } else { 7 };
+            let frame_length = ((header[3] as usize & 0x03) << 11)
+                | (header[4] as usize) << 3
+                | header[5] as usize >> 5;
+            if frame_length < header_length {
+                return Err(error("invalid ADTS frame"));
+            }
+            let Some(frame) = pending.get(offset + header_length..offset + frame_length) else {
+                break;
+            };
+            let frame_config = AudioConfig {
+                object_type: (header[2] >> 6) + 1,
+                sample_rate_index: (header[2] >> 2) & 0x0f,
+                channels: ((header[2] & 0x01) << 2) | header[3] >> 6,
+            };
+            if frame_config.sample_rate_index as usize >= AAC_SAMPLE_RATES.len() {
+                return Err(error("invalid AAC sample rate"));
+            }
+            config.get_or_insert(frame_config);
+            frames.push(frame.to_vec());
+            offset += frame_length;
+        }
+        pending.drain(..offset);
+    }
+
+    let Some(config) = config else {
+        return Ok((None, Vec::new()));
+    };
+    let first_pts = first_pts.unwrap_or_default();
+    let frame_ticks = AAC_FRAME_LENGTH as u64 * TIMESCALE / config.sample_rate() as u64;
+    let frames = frames
+        .into_iter()
+        .enumerate()
+        .map(|(index, frame)| (first_pts + index as u64 * frame_ticks, frame))
+        .collect();
+    Ok((Some(config), frames))
+}
+
+fn mp4_box(out: &mut Vec<u8>, fourcc: &[u8; 4], body: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(fourcc);
+    body(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn full_box(
+    out: &mut Vec<u8>,
+    fourcc: &[u8; 4],
+    version: u8,
+    flags: u32,
+    body: impl FnOnce(&mut Vec<u8>),
+) {
+    mp4_box(out, fourcc, |out| {
+        out.push(version);
+        out.extend_from_slice(&flags.to_be_bytes()[1..]);
+        body(out);
+    });
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+fn put_matrix(out: &mut Vec<u8>) {
+    for value in MATRIX {
+        put_u32(out, value);
+    }
+}
+
+fn init_segment(config: &StreamConfig) -> Vec<u8> {
+    let mut out = Vec::new();
+    mp4_box(&mut out, b"ftyp", |out| {
+        out.extend_from_slice(b"isom");
+        put_u32(out, 0x200);
+        out.extend_from_slice(b"isomiso6mp41");
+    });
+    mp4_box(&mut out, b"moov", |out| {
+        full_box(out, b"mvhd", 0, 0, |out| {
+            out.extend_from_slice(&[0; 8]); // creation and modification time
+            put_u32(out, 1000); // timescale
+            put_u32(out, 0); // duration
+            put_u32(out, 0x0001_0000); // rate
+            put_u16(out, 0x0100); // volume
+            out.extend_from_slice(&[0; 10]);
+            put_matrix(out);
+            out.extend_from_slice(&[0; 24]);
+            put_u32(out, AUDIO_TRACK + 1); // next track ID
+        });
+        if let Some(video) = &config.video {
+            video_track(out, video);
+        }
+        if let Some(audio) = &config.audio {
+            audio_track(out, audio);
+        }
+        mp4_box(out, b"mvex", |out| {
+            let tracks = [
+                config.video.as_ref().map(|_| VIDEO_TRACK),
+                config.audio.as_ref().map(|_| AUDIO_TRACK),
+            ];
+            for track in tracks.into_iter().flatten() {
+                full_box(out, b"trex", 0, 0, |out| {
+                    put_u32(out, track);
+                    put_u32(out, 1); // sample description index
+                    out.extend_from_slice(&[0; 12]); // default duration, size and flags
+                });
+            }
+        });
+    });
+    out
+}
+
+fn track(
+    out: &mut Vec<u8>,
+    id: u32,
+    size: UVec2,
+    timescale: u32,
+    handler: &[u8; 4],
+    media_header: impl FnOnce(&mut Vec<u8>),
+    sample_entry: impl FnOnce(&mut Vec<u8>),
+) {
+    mp4_box(out, b"trak", |out| {
+        // Enabled and in movie
+        full_box(out, b"tkhd", 0, 0x03, |out| {
+            out.extend_from_slice(&[0; 8]); // creation and modification time
+            put_u32(out, id);
+            put_u32(out, 0);
+            put_u32(out, 0); // duration
+            out.extend_from_slice(&[0; 8]);
+            put_u16(out, 0); // layer
+            put_u16(out, 0); // alternate group
+            put_u16(out, if id == AUDIO_TRACK { 0x0100 } else { 0 }); // volume
+            put_u16(out, 0);
+            put_matrix(out);
+            put_u32(out, size.x << 16);
+            put_u32(out, size.y << 16);
+        });
+        mp4_box(out, b"mdia", |out| {
+            full_box(out, b"mdhd", 0, 0, |out| {
+                out.extend_from_slice(&[0; 8]); // creation and modification time
+                put_u32(out, timescale);
+                put_u32(out, 0); // duration
+                put_u16(out, 0x55c4); // und
+                put_u16(out, 0);
+            });
+            full_box(out, b"hdlr", 0, 0, |out| {
+                put_u32(out, 0);
+                out.extend_from_slice(handler);
+                out.extend_from_slice(&[0; 12]);
+                out.extend_from_slice(b"bevy_web_video\0");
+            });
+            mp4_box(out, b"minf", |out| {
+                media_header(out);
+                mp4_box(out, b"dinf", |out| {
+                    full_box(out, b"dref", 0, 0, |out| {
+                        put_u32(out, 1);
+                        // Samples are in this file
+                        full_box(out, b"url ", 0, 0x01, |_| {});
+                    });
+                });
+                mp4_box(out, b"stbl", |out| {
+                    full_box(out, b"stsd", 0, 0, |out| {
+                        put_u32(out, 1);
+                        sample_entry(out);
+                    });
+                    // Samples are in the fragments
+                    for fourcc in [b"stts", b"stsc", b"stco"] {
+                        full_box(out, fourcc, 0, 0, |out| put_u32(out, 0));
+                    }
+                    full_box(out, b"stsz", 0, 0, |out| out.extend_from_slice(&[0; 8]));
+                });
+            });
+        });
+    });
+}
+
+fn video_track(out: &mut Vec<u8>, video: &VideoConfig) {
+    track(
+        out,
+        VIDEO_TRACK,
+        video.size,
+        TIMESCALE as u32,
+        b"vide",
+        |out| full_box(out, b"vmhd", 0, 0x01, |out| out.extend_from_slice(&[0; 8])),
+        |out| {
+            mp4_box(out, b"avc1", |out| {
+                out.extend_from_slice(&[0; 6]);
+                put_u16(out, 1); // data reference index
+                out.extend_from_slice(&[0; 16]);
+                put_u16(out, video.size.x as u16);
+                put_u16(out, video.size.y as u16);
+                put_u32(out, 0x0048_0000); // 72 dpi
+                put_u32(out, 0x0048_0000);
+                put_u32(out, 0);
+                put_u16(out, 1); // frame count
+                out.extend_from_slice(&[0; 32]); // compressor name
+                put_u16(out, 0x0018); // depth
+                put_u16(out, 0xffff);
+                mp4_box(out, b"avcC", |out| {
+                    out.extend_from_slice(&[1, video.sps[1], video.sps[2], video.sps[3]]);
+                    // 4 byte NAL unit lengths, one SPS
+                    out.extend_from_slice(&[0xff, 0xe1]);
+                    put_u16(out, video.sps.len() as u16);
+                    out.extend_from_slice(&video.sps);
+                    out.push(1);
+                    put_u16(out, video.pps.len() as u16);
+                    out.extend_from_slice(&video.pps);
+                });
+            });
+        },
+    );
+}
+
+fn audio_track(out: &mut Vec<u8>, audio: &AudioConfig) {
+    let sample_rate = audio.sample_rate();
+    track(
+        out,
+        AUDIO_TRACK,
+        UVec2::ZERO,
+        sample_rate,
+        b"soun",
+        |out| full_box(out, b"smhd", 0, 0, |out| put_u32(out, 0)),
+        |out| {
+            mp4_box(out, b"mp4a", |out| {
+                out.extend_from_slice(&[0; 6]);
+                put_u16(out, 1); // data reference index
+                out.extend_from_slice(&[0; 8]);
+                put_u16(out, audio.channels as u16);
+                put_u16(out, 16); // sample size
+                put_u32(out, 0);
+                put_u32(out, sample_rate.min(u16::MAX as u32) << 16);
+                full_box(out, b"esds", 0, 0, |out| {
+                    let audio_specific_config = [
+                        (audio.object_type << 3) | (audio.sample_rate_index >> 1),
+                        ((audio.sample_rate_index & 0x01) << 7) | (audio.channels << 3),
+                    ];
+                    // ES, decoder config, decoder specific info and SL config descriptors
+                    out.extend_from_slice(&[0x03, 25]);
+                    put_u16(out, AUDIO_TRACK as u16);
+                    out.push(0);
+                    out.extend_from_slice(&[0x04, 17, 0x40, 0x15]);
+                    out.extend_from_slice(&[0; 11]); // buffer size and bitrates
+                    out.extend_from_slice(&[0x05, 2]);
+                    out.extend_from_slice(&audio_specific_config);
+                    out.extend_from_slice(&[0x06, 1, 0x02]);
+                });
+            });
+        },
+    );
+}
+
+/// Sample depends on others and is no sync sample
+const SAMPLE_FLAGS_DELTA: u32 = 0x0101_0000;
+/// Sample depends on no others
+const SAMPLE_FLAGS_KEY: u32 = 0x0200_0000;
+
+fn fragment(sequence: u32, tracks: &[Track]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut data_offsets = Vec::new();
+    mp4_box(&mut out, b"moof", |out| {
+        full_box(out, b"mfhd", 0, 0, |out| put_u32(out, sequence));
+        for track in tracks {
+            mp4_box(out, b"traf", |out| {
+                // Data offsets are relative to the moof
+                full_box(out, b"tfhd", 0, 0x02_0000, |out| put_u32(out, track.id));
+                full_box(out, b"tfdt", 1, 0, |out| {
+                    out.extend_from_slice(&track.decode_time.to_be_bytes());
+                });
+                // Data offset, sample durations, sizes, flags and composition offsets
+                full_box(out, b"trun", 1, 0x00_0f01, |out| {
+                    put_u32(out, track.samples.len() as u32);
+                    data_offsets.push(out.len());
+                    put_u32(out, 0);
+                    for sample in &track.samples {
+                        put_u32(out, sample.duration);
+                        put_u32(out, sample.data.len() as u32);
+                        put_u32(
+                            out,
+                            if sample.key {
+                                SAMPLE_FLAGS_KEY
+                            } else {
+                                SAMPLE_FLAGS_DELTA
+                            },
+                        );
+                        out.extend_from_slice(&sample.composition_offset.to_be_bytes());
+                    }
+                });
+            });
+        }
+    });
+
+    // Track data follows the mdat header in track order
+    let mut data_offset = out.len() + 8;
+    for (position, track) in data_offsets.into_iter().zip(tracks) {
+        out[position..position + 4].copy_from_slice(&(data_offset as u32).to_be_bytes());
+        data_offset += track
+            .samples
+            .iter()
+            .map(|sample| sample.data.len())
+            .sum::<usize>();
+    }
+    mp4_box(&mut out, b"mdat", |out| {
+        for sample in tracks.iter().flat_map(|track| &track.samples) {
+            out.extend_from_slice(&sample.data);
+        }
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    const VIDEO_PID: u16 = 0x100;
+    const AUDIO_PID: u16 = 0x101;
+    const PMT_PID: u16 = 0x1000;
+
+    /// Bytes of a string of `0` and `1`, zero padded
+    fn pack(bits: &str) -> Vec<u8> {
+        let bits = bits.bytes().filter(|bit| *bit != b' ').collect::<Vec<_>>();
+        bits.chunks(8)
+            .map(|byte| {
+                (0..8).fold(0, |value, index| {
+                    (value << 1) | (byte.get(index) == Some(&b'1')) as u8
+                })
+            })
+            .collect()
+    }
+
+    /// Exp-Golomb code of `value`
+    fn ue(value: u32) -> String {
+        let code = format!("{:b}", value as u64 + 1);
+        format!("{}{code}", "0".repeat(code.len() - 1))
+    }
+
+    /// Baseline SPS with `width_in_macroblocks_minus1`, 15 map units high and no cropping
+    fn sps(width_in_macroblocks_minus1: u32) -> Vec<u8> {
+        let bits = [
+            ue(0), // seq_parameter_set_id
+            ue(0), // log2_max_frame_num_minus4
+            ue(0), // pic_order_cnt_type
+            ue(0), // log2_max_pic_order_cnt_lsb_minus4
+            ue(1), // max_num_ref_frames
+            "0".into(),
+            ue(width_in_macroblocks_minus1),
+            ue(14),
+            // frame_mbs_only, direct_8x8_inference, frame_cropping, vui_parameters_present
+            // and the stop bit
+            "11001".into(),
+        ]
+        .concat();
+        [&[0x67, 0x42, 0xc0, 0x1e][..], &pack(&bits)].concat()
+    }
+
+    fn timestamp(prefix: u8, timestamp: u64) -> [u8; 5] {
+        [
+            (prefix << 4) | (((timestamp >> 30) & 0x07) << 1) as u8 | 1,
+            (timestamp >> 22) as u8,
+            (((timestamp >> 15) & 0x7f) << 1) as u8 | 1,
+            (timestamp >> 7) as u8,
+            ((timestamp & 0x7f) << 1) as u8 | 1,
+        ]
+    }
+
+    fn pes(stream_id: u8, pts: u64, dts: Option<u64>, payload: &[u8]) -> Vec<u8> {
+        let header = match dts {
+            Some(dts) => [&[0xc0, 10][..], &timestamp(3, pts), &timestamp(1, dts)].concat(),
+            None => [&[0x80, 5][..], &timestamp(2, pts)].concat(),
+        };
+        [&[0, 0, 1, stream_id, 0, 0, 0x80][..], &header, payload].concat()
+    }
+
+    /// TS packets carrying `payload`, the last one padded with an adaptation field
+    fn packets(pid: u16, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (index, chunk) in payload.chunks(184).enumerate() {
+            let unit_start = if index == 0 { 0x40 } else { 0 };
+            out.extend_from_slice(&[SYNC_BYTE, unit_start | (pid >> 8) as u8, pid as u8]);
+            if chunk.len() == 184 {
+                out.push(0x10);
+            } else {
+                let stuffing = 183 - chunk.len();
+                out.extend_from_slice(&[0x30, stuffing as u8]);
+                if stuffing > 0 {
+                    out.push(0);
+                    out.extend(std::iter::repeat_n(0xff, stuffing - 1));
+                }
+            }
+            out.extend_from_slice(chunk);
+        }
+        out
+    }
+
+    fn psi(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let length = body.len() as u16 + 4;
+        [
+            &[0, table_id, 0xb0 | (length >> 8) as u8, length as u8][..],
+            body,
+            &[0; 4], // CRC
+        ]
+        .concat()
+    }
+
+    /// ADTS frame of AAC LC, 48kHz stereo
+    fn adts(payload: &[u8]) -> Vec<u8> {
+        let length = payload.len() + 7;
+        [
+            &[
+                0xff,
+                0xf1,
+                0x4c,
+                0x80 | (length >> 11) as u8,
+                (length >> 3) as u8,
+                ((length & 0x07) << 5) as u8 | 0x1f,
+                0xfc,
+            ][..],
+            payload,
+        ]
+        .concat()
+    }
+
+    const IDR: [u8; 5] = [0x65, 0x88, 0x84, 0x21, 0xa0];
+    const NON_IDR: [u8; 4] = [0x41, 0x9a, 0x02, 0x03];
+
+    /// A 320x240 H.264 keyframe and delta frame 3003 ticks apart, with two AAC frames
+    fn segment(first_timestamp: u64) -> Vec<u8> {
+        let start_code = [0, 0, 0, 1];
+        let keyframe = [
+            &start_code[..],
+            &[0x09, 0xf0],
+            &start_code,
+            &sps(19),
+            &start_code,
+            &[0x68, 0xce, 0x38, 0x80],
+            &start_code,
+            &IDR,
+        ]
+        .concat();
+        let delta_frame = [&start_code[..], &NON_IDR].concat();
+        let pmt_pid = [0xe0 | (PMT_PID >> 8) as u8, PMT_PID as u8];
+        let pat = psi(0x00, &[&[0, 1, 0xc1, 0, 0, 0, 1][..], &pmt_pid].concat());
+        let pmt = psi(
+            0x02,
+            &[
+                0,
+                1,
+                0xc1,
+                0,
+                0,
+                0xe1,
+                0x00,
+                0xf0,
+                0,
+                STREAM_TYPE_H264,
+                0xe0 | (VIDEO_PID >> 8) as u8,
+                VIDEO_PID as u8,
+                0xf0,
+                0,
+                STREAM_TYPE_AAC,
+                0xe0 | (AUDIO_PID >> 8) as u8,
+                AUDIO_PID as u8,
+                0xf0,
+                0,
+            ],
+        );
+        let pts = first_timestamp + 3003;
+        [
+            packets(0, &pat),
+            packets(PMT_PID, &pmt),
+            packets(VIDEO_PID, &pes(0xe0, pts, Some(first_timestamp), &keyframe)),
+            packets(VIDEO_PID, &pes(0xe0, pts + 3003, Some(pts), &delta_frame)),
+            packets(
+                AUDIO_PID,
+                &pes(
+                    0xc0,
+                    first_timestamp,
+                    None,
+                    &[adts(&[1; 4]), adts(&[2; 4])].concat(),
+                ),
+            ),
+        ]
+        .concat()
+    }
+
+    /// Body of the first box at `path`
+    fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+        path.iter().fold(data, |mut data, fourcc| {
+            loop {
+                let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+                if &data[4..8] == *fourcc {
+                    break &data[8..size];
+                }
+                data = &data[size..];
+            }
+        })
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[wasm_bindgen_test]
+    fn reads_sps_size() {
+        assert_eq!(sps_size(&sps(19)), Some(UVec2::new(320, 240)));
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_overflowing_sps_size() {
+        assert_eq!(sps_size(&sps(1 << 30)), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn remuxes_h264_and_aac() {
+        let mut transmuxer = Transmuxer::default();
+        let remuxed = transmuxer.remux(&segment(1_000), 10.0).unwrap();
+        assert_eq!(
+            remuxed.mime_type,
+            "video/mp4; codecs=\"avc1.42c01e,mp4a.40.2\""
+        );
+        let init = remuxed.init.expect("first segment has an init segment");
+        let avc1 = find(
+            &init,
+            &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+        );
+        // Width and height of the sample entry, after the stsd header and entry count
+        assert_eq!(&avc1[8 + 8 + 24..8 + 8 + 28], &[1, 64, 0, 240]);
+
+        let fragment = remuxed.fragment;
+        let traf = find(&fragment, &[b"moof", b"traf"]);
+        // Rebased on the playlist start, in 90kHz ticks
+        assert_eq!(
+            u64::from_be_bytes(find(traf, &[b"tfdt"])[4..12].try_into().unwrap()),
+            900_000
+        );
+        let trun = find(traf, &[b"trun"]);
+        assert_eq!(u32_at(trun, 4), 2);
+        // Duration, size, flags and composition offset of each sample
+        assert_eq!(u32_at(trun, 12), 3003);
+        assert_eq!(u32_at(trun, 16), 4 + IDR.len() as u32);
+        assert_eq!(u32_at(trun, 20), SAMPLE_FLAGS_KEY);
+        assert_eq!(u32_at(trun, 24), 3003);
+        assert_eq!(u32_at(trun, 28), 3003);
+        assert_eq!(u32_at(trun, 36), SAMPLE_FLAGS_DELTA);
+        let mdat = find(&fragment, &[b"mdat"]);
+        assert_eq!(&mdat[..4 + IDR.len()], [&[0, 0, 0, 5][..], &IDR].concat());
+        assert_eq!(&mdat[mdat.len() - 4..], &[2; 4]);
+    }
+
+    #[wasm_bindgen_test]
+    fn keeps_timeline_across_segments() {
+        let mut transmuxer = Transmuxer::default();
+        transmuxer.remux(&segment(1_000), 10.0).unwrap();
+        // The next segment follows on the TS timeline, whatever its playlist start
+        let remuxed = transmuxer.remux(&segment(1_000 + 6006), 0.0).unwrap();
+        assert!(remuxed.init.is_none());
+        assert_eq!(u32_at(find(&remuxed.fragment, &[b"moof", b"mfhd"]), 4), 2);
+        let tfdt = find(&remuxed.fragment, &[b"moof", b"traf", b"tfdt"]);
+        assert_eq!(u64::from_be_bytes(tfdt[4..12].try_into().unwrap()), 906_006);
+
+        // A discontinuity restarts the timeline at the playlist start
+        transmuxer.discontinuity();
+        let remuxed = transmuxer.remux(&segment(0), 20.0).unwrap();
+        let tfdt = find(&remuxed.fragment, &[b"moof", b"traf", b"tfdt"]);
+        assert_eq!(
+            u64::from_be_bytes(tfdt[4..12].try_into().unwrap()),
+            1_800_000
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_lost_sync() {
+        let mut data = segment(0);
+        data[PACKET_SIZE] = 0;
+        assert!(Transmuxer::default().remux(&data, 0.0).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn survives_truncated_segments() {
+        let data = segment(0);
+        for len in 0..data.len() {
+            let _ = Transmuxer::default().remux(&data[..len], 0.0);
+        }
+    }
+}
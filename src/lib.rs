@@ -1,37 +1,183 @@
-use bevy::{asset::AsAssetId, prelude::*};
-use wasm_bindgen::prelude::*;
+use bevy::{
+    asset::AsAssetId,
+    ecs::{lifecycle::HookContext, world::DeferredWorld},
+    prelude::*,
+};
 
+mod abr;
+mod animated;
+mod atlas;
+#[cfg(feature = "webcodecs")]
+mod codecs;
+mod dash;
+mod error;
 mod event;
+mod fetch;
+mod hls;
+mod media_source;
+mod playback;
+mod player;
 mod registry;
 pub(crate) mod render;
+mod seek;
+mod segmented;
+mod source;
+mod status;
+mod stream;
+mod texture;
+
+pub use web_sys;
 
+#[cfg(feature = "webcodecs")]
+pub use crate::codecs::{CodecVideo, EncodedVideo, VideoContainer};
 pub use crate::{
+    abr::{
+        AbrContext, AbrController, AbrDisplay, AbrRendition, AbrStrategy, DefaultAbrStrategy,
+        RenditionChanged,
+    },
+    animated::{AnimatedImage, AnimatedImageFormat, EncodedImage},
+    atlas::VideoAtlas,
+    dash::{
+        DashAdaptationSet, DashContentType, DashManifest, DashPeriod, DashRepresentation,
+        DashRepresentationSelection, DashSegment, DashSegmentRef, DashSegments, DashStream,
+    },
+    error::{VideoError, WebVideoError},
     event::{EventListenerAppExt, EventSender, EventType, ListenerEvent, events},
+    hls::{
+        HlsInitSegment, HlsMasterPlaylist, HlsMediaPlaylist, HlsPlaylist, HlsSegment, HlsStream,
+        HlsVariant, HlsVariantSelection,
+    },
+    media_source::{MediaSourceVideo, SourceBufferId},
+    playback::{PlaybackState, VideoPlayback},
+    player::MediaPlayer,
     registry::{
-        VideoElementRegistry,
+        EventSubscription, VideoElementRegistry,
         asset::{VideoElement, VideoElementAssetsExt},
+        loader::{
+            VIDEO_ASSET_SOURCE, VIDEO_IMAGE_LABEL, VideoAssetSourcePlugin, VideoBackend,
+            VideoLoaderSettings, VideoPreload,
+        },
+    },
+    render::{VideoUploadPath, VideoUploadPathChanged},
+    seek::{SeekMode, VideoSeek, VideoSeeked},
+    segmented::RenditionSelection,
+    source::{SourceUpdate, WebTextureSource},
+    status::VideoStatus,
+    stream::{CameraConstraints, FacingMode, MediaStreamPermission, MediaStreamSource},
+    texture::{
+        VideoColorSpace, VideoResolution, VideoTextureDownscaled, VideoTextureFormat,
+        VideoTextureSettings, VideoUploadMode,
     },
 };
+use crate::{event::VideoEntities, registry::asset::PendingMedia};
 
 pub struct WebVideoPlugin;
 
 impl Plugin for WebVideoPlugin {
     fn build(&self, app: &mut App) {
-        // event must be built before registry
-        app.add_plugins((event::plugin, registry::plugin, render::VideoRenderPlugin));
+        // registry must be built before event
+        app.add_plugins((
+            registry::plugin,
+            event::plugin,
+            hls::plugin,
+            dash::plugin,
+            abr::plugin,
+            media_source::plugin,
+            playback::plugin,
+            player::plugin,
+            seek::plugin,
+            source::plugin,
+            status::plugin,
+            render::VideoRenderPlugin,
+        ));
+    }
+}
+
+/// Where a [`WebVideo`] created with [`WebVideo::from_source`] gets its media from.
+#[derive(Clone, Debug)]
+pub enum VideoSource {
+    Url(String),
+    /// Decoded with WebCodecs, see [`CodecVideo`]
+    #[cfg(feature = "webcodecs")]
+    Encoded(std::sync::Arc<EncodedVideo>),
+    /// A GIF, APNG or animated WebP, see [`AnimatedImage`]
+    AnimatedImage(std::sync::Arc<EncodedImage>),
+    /// A camera, screen capture or canvas stream, see [`MediaStreamSource`]
+    MediaStream(MediaStreamSource),
+    /// Chunks appended from Rust, see [`MediaSourceVideo`]
+    MediaSource,
+    /// An `.m3u8` master or media playlist URL, see [`HlsStream`]
+    Hls(String),
+    /// An `.mpd` manifest URL, see [`DashStream`]
+    Dash(String),
+}
+
+impl From<VideoSource> for PendingMedia {
+    fn from(source: VideoSource) -> Self {
+        match source {
+            VideoSource::Url(url) => PendingMedia::Url(url),
+            #[cfg(feature = "webcodecs")]
+            VideoSource::Encoded(video) => PendingMedia::Encoded(video),
+            VideoSource::AnimatedImage(image) => PendingMedia::AnimatedImage(image),
+            VideoSource::MediaStream(source) => PendingMedia::MediaStream(source),
+            VideoSource::MediaSource => PendingMedia::MediaSource,
+            VideoSource::Hls(url) => PendingMedia::Hls(url),
+            VideoSource::Dash(url) => PendingMedia::Dash(url),
+        }
     }
 }
 
+#[derive(Clone, Debug)]
+struct PendingSource {
+    source: VideoSource,
+    settings: VideoLoaderSettings,
+}
+
+/// A video entity.
+///
+/// When spawned with [`WebVideo::from_source`], the [`VideoElement`] asset, its element
+/// and the target image are created when the component is added.
+/// All registered [`EventType`]s of the element are routed to the entity as [`ListenerEvent`]s.
 #[derive(Clone, Component)]
-pub struct WebVideo(Handle<VideoElement>);
+#[component(on_add = on_add_web_video, on_replace = on_replace_web_video)]
+#[require(WebVideoImage, VideoStatus)]
+pub struct WebVideo {
+    handle: Handle<VideoElement>,
+    pending: Option<PendingSource>,
+}
 
 impl WebVideo {
     pub fn new(video_element: Handle<VideoElement>) -> Self {
-        Self(video_element)
+        Self {
+            handle: video_element,
+            pending: None,
+        }
+    }
+
+    pub fn from_source(source: VideoSource) -> Self {
+        Self {
+            handle: Handle::default(),
+            pending: Some(PendingSource {
+                source,
+                settings: VideoLoaderSettings::default(),
+            }),
+        }
+    }
+
+    /// Element settings applied when the element is created by [`WebVideo::from_source`]
+    pub fn with_settings(mut self, settings: VideoLoaderSettings) -> Self {
+        if let Some(pending) = self.pending.as_mut() {
+            pending.settings = settings;
+        }
+        self
+    }
+
+    pub fn handle(&self) -> &Handle<VideoElement> {
+        &self.handle
     }
 
     pub fn asset_id(&self) -> AssetId<VideoElement> {
-        self.0.id()
+        self.handle.id()
     }
 }
 
@@ -39,27 +185,71 @@ impl AsAssetId for WebVideo {
     type Asset = VideoElement;
 
     fn as_asset_id(&self) -> AssetId<Self::Asset> {
-        self.0.id()
+        self.handle.id()
     }
 }
 
-#[derive(Debug)]
-pub struct WebVideoError {
-    message: String,
-}
+/// The [`Image`] a [`WebVideo`] renders into.
+///
+/// Set when the crate creates the image, i.e. for [`WebVideo::from_source`] and loaded videos.
+/// A handle provided at spawn time is used as the target image by [`WebVideo::from_source`].
+#[derive(Component, Clone, Debug, Default, Deref, PartialEq)]
+pub struct WebVideoImage(pub Handle<Image>);
 
-impl std::error::Error for WebVideoError {}
+fn on_add_web_video(mut world: DeferredWorld, HookContext { entity, .. }: HookContext) {
+    let Some(mut web_video) = world.get_mut::<WebVideo>(entity) else {
+        return;
+    };
+    let Some(pending) = web_video.pending.take() else {
+        let asset_id = web_video.asset_id();
+        world
+            .resource_mut::<VideoEntities>()
+            .insert(asset_id, entity);
+        // Loaded videos own their image, set here or once the asset is added
+        let image = world
+            .resource::<Assets<VideoElement>>()
+            .get(asset_id)
+            .and_then(VideoElement::target_image)
+            .cloned();
+        if let Some(image) = image
+            && let Some(mut web_video_image) = world.get_mut::<WebVideoImage>(entity)
+        {
+            web_video_image.0 = image;
+        }
+        return;
+    };
 
-impl std::fmt::Display for WebVideoError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
+    let image = match world.get::<WebVideoImage>(entity) {
+        Some(WebVideoImage(image)) if *image != Handle::default() => image.clone(),
+        _ => {
+            let image = world.resource::<Assets<Image>>().reserve_handle();
+            if let Some(mut web_video_image) = world.get_mut::<WebVideoImage>(entity) {
+                web_video_image.0 = image.clone();
+            }
+            image
+        }
+    };
+    let mut video_element = VideoElement::new(&image);
+    video_element.set_texture_settings(pending.settings.texture.clone());
+    let video_handle = world
+        .resource_mut::<Assets<VideoElement>>()
+        .add(video_element);
+    let asset_id = video_handle.id();
+    if let Some(mut web_video) = world.get_mut::<WebVideo>(entity) {
+        web_video.handle = video_handle;
     }
+    world
+        .resource_mut::<VideoEntities>()
+        .insert(asset_id, entity);
+
+    let mut registry = world.non_send_resource_mut::<VideoElementRegistry>();
+    PendingMedia::from(pending.source).create(asset_id, &pending.settings, &mut registry);
 }
 
-impl From<JsValue> for WebVideoError {
-    fn from(value: JsValue) -> Self {
-        Self {
-            message: format!("{value:?}"),
-        }
+fn on_replace_web_video(mut world: DeferredWorld, HookContext { entity, .. }: HookContext) {
+    if let Some(asset_id) = world.get::<WebVideo>(entity).map(WebVideo::asset_id) {
+        world
+            .resource_mut::<VideoEntities>()
+            .remove(asset_id, entity);
     }
 }
@@ -0,0 +1,429 @@
+//! Media Source Extensions: segments appended from Rust to the `SourceBuffer`s of a
+//! `MediaSource` that the asset's element plays.
+
+use crate::{
+    VideoElementRegistry, VideoError,
+    events::{SourceBufferFailure, SourceBufferUpdate},
+};
+use bevy::prelude::*;
+use gloo_events::EventListener;
+use std::{cell::RefCell, collections::VecDeque, ops::Range, rc::Rc};
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(PostUpdate, update_media_sources);
+}
+
+/// Runs after user systems so chunks queued during the frame are appended right away
+pub(crate) fn update_media_sources(mut registry: NonSendMut<VideoElementRegistry>) {
+    registry.update_media_sources();
+}
+
+/// Seconds kept behind the current time by default, for seeking back
+const DEFAULT_BACK_BUFFER: f64 = 30.0;
+/// Played ranges are evicted in steps of at least this many seconds, not every frame
+const MIN_EVICTION: f64 = 5.0;
+/// Seconds kept behind the current time when an append exceeds the quota
+const QUOTA_BACK_BUFFER: f64 = 1.0;
+
+/// A `SourceBuffer` of a [`MediaSourceVideo`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SourceBufferId(usize);
+
+/// An element playing a `MediaSource`, fed with chunks appended from Rust, e.g. fMP4 or WebM
+/// segments from asset bytes, a custom network layer or a muxer.
+///
+/// Create one with [`VideoSource::MediaSource`](crate::VideoSource::MediaSource) and get it with
+/// [`VideoElementRegistry::media_source_mut`]. Playback, seeking and the element's events work
+/// as for a URL source. Add all source buffers before appending, browsers reject new ones once
+/// the first initialization segment was appended.
+///
+/// Operations are queued per source buffer and run in order, each once the previous one fired
+/// `updateend`, which is fired as [`events::UpdateEnd`](crate::events::UpdateEnd). Failed
+/// operations are fired as [`events::SourceBufferError`](crate::events::SourceBufferError).
+/// Ranges played more than [`back_buffer`](Self::back_buffer) seconds ago are evicted, and when
+/// an append exceeds the buffer quota everything played is evicted and the append retried.
+/// Without anything to evict a [`VideoError::QuotaExceeded`] is fired and the append waits
+/// for playback to advance.
+pub struct MediaSourceVideo {
+    media_source: web_sys::MediaSource,
+    element: web_sys::HtmlVideoElement,
+    url: String,
+    buffers: Vec<RegisteredBuffer>,
+    // Filled by the source buffer listeners
+    buffer_events: Rc<RefCell<Vec<(SourceBufferId, BufferEvent)>>>,
+    duration: Option<f64>,
+    end_of_stream: bool,
+    back_buffer: Option<f64>,
+}
+
+struct RegisteredBuffer {
+    mime_type: String,
+    // Added once the media source is open
+    source_buffer: Option<web_sys::SourceBuffer>,
+    operations: VecDeque<Operation>,
+    // Current time when an append exceeded the quota with nothing left to evict
+    quota_exceeded_at: Option<f64>,
+    _listeners: Vec<EventListener>,
+}
+
+enum Operation {
+    Append(Vec<u8>),
+    Remove(Range<f64>),
+    ChangeType(String),
+    SetTimestampOffset(f64),
+}
+
+#[derive(Copy, Clone)]
+enum BufferEvent {
+    UpdateEnd,
+    Error,
+}
+
+/// Events fired by a [`MediaSourceVideo`]
+pub(crate) enum MediaSourceEvent {
+    UpdateEnd(SourceBufferUpdate),
+    BufferError(SourceBufferFailure),
+    Error(VideoError),
+}
+
+impl MediaSourceVideo {
+    pub(crate) fn new(element: &web_sys::HtmlVideoElement) -> Result<Self, VideoError> {
+        let media_source = web_sys::MediaSource::new().map_err(|err| VideoError::from(&err))?;
+        let url = web_sys::Url::create_object_url_with_source(&media_source)
+            .map_err(|err| VideoError::from(&err))?;
+        element.set_src(&url);
+        Ok(Self {
+            media_source,
+            element: element.clone(),
+            url,
+            buffers: Vec::new(),
+            buffer_events: Rc::default(),
+            duration: None,
+            end_of_stream: false,
+            back_buffer: Some(DEFAULT_BACK_BUFFER),
+        })
+    }
+
+    /// Whether the browser can play `mime_type` through MSE, e.g. `video/mp4; codecs="avc1.64001f"`
+    pub fn is_type_supported(mime_type: &str) -> bool {
+        web_sys::MediaSource::is_type_supported(mime_type)
+    }
+
+    pub fn media_source(&self) -> &web_sys::MediaSource {
+        &self.media_source
+    }
+
+    /// Whether the element opened the media source, operations wait for it
+    pub fn is_open(&self) -> bool {
+        self.media_source.ready_state() != web_sys::MediaSourceReadyState::Closed
+    }
+
+    /// Add a source buffer for `mime_type`, created once the media source is open
+    pub fn add_source_buffer(&mut self, mime_type: &str) -> Result<SourceBufferId, VideoError> {
+        if !Self::is_type_supported(mime_type) {
+            return Err(VideoError::NotSupported);
+        }
+        self.buffers.push(RegisteredBuffer {
+            mime_type: mime_type.to_string(),
+            source_buffer: None,
+            operations: VecDeque::new(),
+            quota_exceeded_at: None,
+            _listeners: Vec::new(),
+        });
+        Ok(SourceBufferId(self.buffers.len() - 1))
+    }
+
+    /// `None` until the media source is open
+    pub fn source_buffer(&self, buffer: SourceBufferId) -> Option<&web_sys::SourceBuffer> {
+        self.buffers
+            .get(buffer.0)
+            .and_then(|registered| registered.source_buffer.as_ref())
+    }
+
+    pub fn mime_type(&self, buffer: SourceBufferId) -> Option<&str> {
+        self.buffers
+            .get(buffer.0)
+            .map(|registered| registered.mime_type.as_str())
+    }
+
+    /// Queue `data` to be appended, e.g. an initialization or media segment
+    pub fn append(&mut self, buffer: SourceBufferId, data: impl Into<Vec<u8>>) {
+        self.push(buffer, Operation::Append(data.into()));
+    }
+
+    /// Queue removal of `range` in seconds
+    pub fn remove(&mut self, buffer: SourceBufferId, range: Range<f64>) {
+        self.push(buffer, Operation::Remove(range));
+    }
+
+    /// Queue a switch to `mime_type` for the following appends, e.g. a rendition with another codec
+    pub fn change_type(&mut self, buffer: SourceBufferId, mime_type: &str) {
+        if let Some(registered) = self.buffers.get_mut(buffer.0) {
+            registered.mime_type = mime_type.to_string();
+        }
+        self.push(buffer, Operation::ChangeType(mime_type.to_string()));
+    }
+
+    /// Queue an offset in seconds added to the timestamps of the following appends
+    pub fn set_timestamp_offset(&mut self, buffer: SourceBufferId, offset: f64) {
+        self.push(buffer, Operation::SetTimestampOffset(offset));
+    }
+
+    /// Drop the queued operations, the running one completes
+    pub fn clear(&mut self, buffer: SourceBufferId) {
+        if let Some(registered) = self.buffers.get_mut(buffer.0) {
+            registered.operations.clear();
+            registered.quota_exceeded_at = None;
+        }
+    }
+
+    /// Bytes waiting to be appended
+    pub fn queued_bytes(&self, buffer: SourceBufferId) -> usize {
+        self.buffers
+            .get(buffer.0)
+            .map(|registered| {
+                registered
+                    .operations
+                    .iter()
+                    .map(|operation| match operation {
+                        Operation::Append(data) => data.len(),
+                        _ => 0,
+                    })
+                    .sum()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether an operation is running or queued
+    pub fn is_busy(&self, buffer: SourceBufferId) -> bool {
+        self.buffers.get(buffer.0).is_some_and(|registered| {
+            !registered.operations.is_empty()
+                || registered
+                    .source_buffer
+                    .as_ref()
+                    .is_some_and(web_sys::SourceBuffer::updating)
+        })
+    }
+
+    /// Buffered ranges in seconds
+    pub fn buffered(&self, buffer: SourceBufferId) -> Vec<Range<f64>> {
+        self.source_buffer(buffer)
+            .map(buffered_ranges)
+            .unwrap_or_default()
+    }
+
+    /// Set the duration in seconds once no source buffer is updating
+    pub fn set_duration(&mut self, duration: f64) {
+        self.duration = Some(duration);
+    }
+
+    /// Signal the end of the stream once all queued operations completed,
+    /// appending afterwards reopens it
+    pub fn end_of_stream(&mut self) {
+        self.end_of_stream = true;
+    }
+
+    /// Seconds kept behind the current time, older ranges are evicted
+    pub fn back_buffer(&self) -> Option<f64> {
+        self.back_buffer
+    }
+
+    /// `None` keeps played ranges until the quota is exceeded
+    pub fn set_back_buffer(&mut self, back_buffer: Option<f64>) {
+        self.back_buffer = back_buffer;
+    }
+
+    fn push(&mut self, buffer: SourceBufferId, operation: Operation) {
+        if let Some(registered) = self.buffers.get_mut(buffer.0) {
+            registered.operations.push_back(operation);
+        }
+    }
+
+    fn idle(&self) -> bool {
+        self.buffers.iter().all(|registered| {
+            registered.operations.is_empty()
+                && registered
+                    .source_buffer
+                    .as_ref()
+                    .is_none_or(|source_buffer| !source_buffer.updating())
+        })
+    }
+
+    /// Create source buffers, run the next queued operations and return the fired events
+    pub(crate) fn update(&mut self) -> Vec<MediaSourceEvent> {
+        let mut fired = Vec::new();
+        if !self.is_open() {
+            return fired;
+        }
+        self.create_source_buffers(&mut fired);
+
+        for (buffer, event) in self.buffer_events.take() {
+            let Some(registered) = self.buffers.get(buffer.0) else {
+                continue;
+            };
+            fired.push(match event {
+                BufferEvent::UpdateEnd => MediaSourceEvent::UpdateEnd(SourceBufferUpdate {
+                    buffer,
+                    buffered: registered
+                        .source_buffer
+                        .as_ref()
+                        .map(buffered_ranges)
+                        .unwrap_or_default(),
+                }),
+                // The append error algorithm also fails the element with a decode error
+                BufferEvent::Error => MediaSourceEvent::BufferError(SourceBufferFailure {
+                    buffer,
+                    error: VideoError::Decode,
+                }),
+            });
+        }
+
+        let current_time = self.element.current_time();
+        for (index, registered) in self.buffers.iter_mut().enumerate() {
+            if let Err(error) = registered.run_next(current_time, self.back_buffer) {
+                fired.push(MediaSourceEvent::BufferError(SourceBufferFailure {
+                    buffer: SourceBufferId(index),
+                    error,
+                }));
+            }
+        }
+
+        // Duration and end of stream require an open media source without updating buffers
+        let open = self.media_source.ready_state() == web_sys::MediaSourceReadyState::Open;
+        if open && self.idle() {
+            if let Some(duration) = self.duration.take() {
+                self.media_source.set_duration(duration);
+            }
+            if std::mem::take(&mut self.end_of_stream)
+                && let Err(err) = self.media_source.end_of_stream()
+            {
+                fired.push(MediaSourceEvent::Error(VideoError::from(&err)));
+            }
+        }
+        fired
+    }
+
+    fn create_source_buffers(&mut self, fired: &mut Vec<MediaSourceEvent>) {
+        for (index, registered) in self.buffers.iter_mut().enumerate() {
+            if registered.source_buffer.is_some() {
+                continue;
+            }
+            let buffer = SourceBufferId(index);
+            match self.media_source.add_source_buffer(&registered.mime_type) {
+                Ok(source_buffer) => {
+                    registered._listeners = [
+                        ("updateend", BufferEvent::UpdateEnd),
+                        ("error", BufferEvent::Error),
+                    ]
+                    .into_iter()
+                    .map(|(event_name, event)| {
+                        let buffer_events = self.buffer_events.clone();
+                        EventListener::new(&source_buffer, event_name, move |_| {
+                            buffer_events.borrow_mut().push((buffer, event));
+                        })
+                    })
+                    .collect();
+                    registered.source_buffer = Some(source_buffer);
+                }
+                Err(err) => {
+                    // Dropped operations can't be appended without a source buffer
+                    registered.operations.clear();
+                    fired.push(MediaSourceEvent::BufferError(SourceBufferFailure {
+                        buffer,
+                        error: VideoError::from(&err),
+                    }));
+                }
+            }
+        }
+    }
+}
+
+impl RegisteredBuffer {
+    /// Evict played ranges or start the next operation, unless one is running
+    fn run_next(&mut self, current_time: f64, back_buffer: Option<f64>) -> Result<(), VideoError> {
+        let Some(source_buffer) = &self.source_buffer else {
+            return Ok(());
+        };
+        if source_buffer.updating() {
+            return Ok(());
+        }
+        if let Some(played) = back_buffer
+            .and_then(|back_buffer| played_range(source_buffer, current_time, back_buffer))
+            .filter(|played| played.end - played.start >= MIN_EVICTION)
+        {
+            return source_buffer
+                .remove(played.start, played.end)
+                .map_err(|err| VideoError::from(&err));
+        }
+        // Retrying every frame would copy the chunk each time
+        if let Some(exceeded_at) = self.quota_exceeded_at
+            && (current_time - exceeded_at).abs() < QUOTA_BACK_BUFFER
+            && matches!(self.operations.front(), Some(Operation::Append(_)))
+        {
+            return Ok(());
+        }
+
+        let Some(mut operation) = self.operations.pop_front() else {
+            return Ok(());
+        };
+        let result = match &mut operation {
+            Operation::Append(data) => source_buffer.append_buffer_with_u8_array(data),
+            Operation::Remove(range) => source_buffer.remove(range.start, range.end),
+            Operation::ChangeType(mime_type) => source_buffer.change_type(mime_type),
+            Operation::SetTimestampOffset(offset) => {
+                source_buffer.set_timestamp_offset(*offset);
+                Ok(())
+            }
+        };
+        let Err(err) = result else {
+            if matches!(operation, Operation::Append(_)) {
+                self.quota_exceeded_at = None;
+            }
+            return Ok(());
+        };
+        let error = VideoError::from(&err);
+        if error != VideoError::QuotaExceeded {
+            return Err(error);
+        }
+
+        self.operations.push_front(operation);
+        if let Some(played) = played_range(source_buffer, current_time, QUOTA_BACK_BUFFER) {
+            source_buffer
+                .remove(played.start, played.end)
+                .map_err(|err| VideoError::from(&err))
+        } else if self.quota_exceeded_at.replace(current_time).is_none() {
+            Err(error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for MediaSourceVideo {
+    fn drop(&mut self) {
+        // The object URL keeps the media source alive until revoked
+        if let Err(err) = web_sys::Url::revoke_object_url(&self.url) {
+            warn!("Failed to revoke media source URL: {err:?}");
+        }
+    }
+}
+
+/// Buffered from the start to `back_buffer` seconds before `current_time`
+fn played_range(
+    source_buffer: &web_sys::SourceBuffer,
+    current_time: f64,
+    back_buffer: f64,
+) -> Option<Range<f64>> {
+    let start = source_buffer.buffered().ok()?.start(0).ok()?;
+    let end = current_time - back_buffer;
+    (end > start).then_some(start..end)
+}
+
+fn buffered_ranges(source_buffer: &web_sys::SourceBuffer) -> Vec<Range<f64>> {
+    let Ok(buffered) = source_buffer.buffered() else {
+        return Vec::new();
+    };
+    (0..buffered.length())
+        .filter_map(|i| Some(buffered.start(i).ok()?..buffered.end(i).ok()?))
+        .collect()
+}
@@ -0,0 +1,175 @@
+use crate::{MediaPlayer, VideoElement, VideoElementRegistry, WebVideo};
+use bevy::{platform::collections::HashSet, prelude::*};
+
+pub fn plugin(app: &mut App) {
+    app.register_type::<VideoPlayback>()
+        .add_systems(Update, sync_playback);
+}
+
+#[derive(Reflect, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PlaybackState {
+    Playing,
+    #[default]
+    Paused,
+}
+
+/// Declarative playback state for a [`WebVideo`] entity.
+///
+/// Changes to this component are pushed to the underlying element,
+/// and changes made on the element (e.g. by the browser or native controls) are pulled back.
+#[derive(Component, Reflect, Clone, Debug, PartialEq)]
+#[reflect(Component, Default)]
+pub struct VideoPlayback {
+    pub state: PlaybackState,
+    pub playback_rate: f64,
+    pub looping: bool,
+    pub muted: bool,
+    pub volume: f64,
+}
+
+impl Default for VideoPlayback {
+    fn default() -> Self {
+        Self {
+            state: PlaybackState::Paused,
+            playback_rate: 1.0,
+            looping: false,
+            muted: false,
+            volume: 1.0,
+        }
+    }
+}
+
+impl VideoPlayback {
+    pub fn playing() -> Self {
+        Self {
+            state: PlaybackState::Playing,
+            ..default()
+        }
+    }
+
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    pub fn with_muted(mut self, muted: bool) -> Self {
+        self.muted = muted;
+        self
+    }
+
+    pub fn with_volume(mut self, volume: f64) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn with_playback_rate(mut self, playback_rate: f64) -> Self {
+        self.playback_rate = playback_rate;
+        self
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state == PlaybackState::Playing
+    }
+
+    fn from_element(element: &web_sys::HtmlVideoElement) -> Self {
+        Self {
+            state: if element.paused() {
+                PlaybackState::Paused
+            } else {
+                PlaybackState::Playing
+            },
+            playback_rate: element.playback_rate(),
+            looping: element.loop_(),
+            muted: element.muted(),
+            volume: element.volume(),
+        }
+    }
+
+    fn from_player(player: &dyn MediaPlayer) -> Self {
+        Self {
+            state: if player.paused() {
+                PlaybackState::Paused
+            } else {
+                PlaybackState::Playing
+            },
+            playback_rate: player.playback_rate(),
+            looping: player.looping(),
+            muted: player.muted(),
+            volume: player.volume(),
+        }
+    }
+
+    fn apply_to_player(&self, player: &mut dyn MediaPlayer) {
+        player.set_playback_rate(self.playback_rate);
+        player.set_looping(self.looping);
+        player.set_muted(self.muted);
+        player.set_volume(self.volume);
+        match self.state {
+            PlaybackState::Playing => player.play(),
+            PlaybackState::Paused => player.pause(),
+        }
+    }
+
+    fn apply_to_element(
+        &self,
+        element: &web_sys::HtmlVideoElement,
+        asset_id: AssetId<VideoElement>,
+        registry: &VideoElementRegistry,
+    ) {
+        // Only touch properties that differ, setters can have side effects (e.g. events)
+        if element.playback_rate() != self.playback_rate {
+            element.set_playback_rate(self.playback_rate);
+        }
+        if element.loop_() != self.looping {
+            element.set_loop(self.looping);
+        }
+        if element.muted() != self.muted {
+            element.set_muted(self.muted);
+        }
+        if element.volume() != self.volume {
+            element.set_volume(self.volume);
+        }
+        match self.state {
+            PlaybackState::Playing if element.paused() => {
+                registry.play(asset_id);
+            }
+            PlaybackState::Paused if !element.paused() => {
+                if let Err(err) = element.pause() {
+                    warn!("Failed to pause video: {err:?}");
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn sync_playback(
+    mut videos: Query<(Entity, &WebVideo, &mut VideoPlayback)>,
+    mut registry: NonSendMut<VideoElementRegistry>,
+    // Changed before their element or player existed, pushed once it does
+    mut pending: Local<HashSet<Entity>>,
+) {
+    pending.retain(|entity| videos.contains(*entity));
+    for (entity, web_video, mut playback) in &mut videos {
+        let changed = pending.remove(&entity) || playback.is_changed();
+        if let Some(player) = registry.player_mut(web_video.asset_id()) {
+            if changed {
+                playback.apply_to_player(player);
+            } else {
+                playback.set_if_neq(VideoPlayback::from_player(player));
+            }
+            continue;
+        }
+        let Some(element) = registry.element(web_video.asset_id()) else {
+            if changed {
+                pending.insert(entity);
+            }
+            continue;
+        };
+        if changed {
+            playback.apply_to_element(element, web_video.asset_id(), &registry);
+        } else {
+            playback.set_if_neq(VideoPlayback::from_element(element));
+        }
+    }
+}
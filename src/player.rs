@@ -0,0 +1,303 @@
+//! Media the crate plays on its own clock instead of through an `HTMLVideoElement`,
+//! i.e. WebCodecs videos and animated images. They fire the media events of an element.
+
+use crate::{
+    SeekMode, VideoElement, VideoElementRegistry, VideoError,
+    events::{self, PlaybackTime, VideoMetadata, Volume},
+};
+use bevy::prelude::*;
+
+pub(crate) use internal::{PlayerEvent, PlayerInternal, PlayerState};
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(PreUpdate, tick_players);
+}
+
+/// Players run in real time, like media elements
+fn tick_players(time: Res<Time<Real>>, mut registry: NonSendMut<VideoElementRegistry>) {
+    registry.tick_players(time.delta_secs_f64());
+}
+
+/// Seconds between [`events::TimeUpdate`] while playing, like media elements
+const TIME_UPDATE_INTERVAL: f64 = 0.25;
+
+/// Media played by the crate rather than an element, e.g. an
+/// [`AnimatedImage`](crate::AnimatedImage). Get one with [`VideoElementRegistry::player`].
+///
+/// [`VideoPlayback`](crate::VideoPlayback), [`VideoSeek`](crate::VideoSeek) and
+/// [`VideoStatus`](crate::VideoStatus) work the same as for elements,
+/// and the element's media events are fired, including [`events::Looped`] and [`events::Ended`].
+pub trait MediaPlayer: PlayerInternal {
+    /// Size of the shown frame
+    fn size(&self) -> UVec2;
+
+    /// Seconds
+    fn duration(&self) -> f64;
+
+    /// Seek to `time` in seconds
+    fn seek(&mut self, time: f64, mode: SeekMode);
+
+    fn seeking(&self) -> bool;
+
+    /// Seconds
+    fn current_time(&self) -> f64 {
+        self.state().current_time
+    }
+
+    /// Whether the first frame is shown
+    fn loaded(&self) -> bool {
+        self.state().loaded
+    }
+
+    fn paused(&self) -> bool {
+        self.state().paused
+    }
+
+    fn ended(&self) -> bool {
+        self.state().ended
+    }
+
+    /// Whether playback has started since it was loaded
+    fn played(&self) -> bool {
+        self.state().played
+    }
+
+    fn error(&self) -> Option<&VideoError> {
+        self.state().error.as_ref()
+    }
+
+    fn looping(&self) -> bool {
+        self.state().looping
+    }
+
+    fn muted(&self) -> bool {
+        self.state().muted
+    }
+
+    fn volume(&self) -> f64 {
+        self.state().volume
+    }
+
+    fn playback_rate(&self) -> f64 {
+        self.state().playback_rate
+    }
+
+    fn play(&mut self) {
+        let state = self.state_mut();
+        if !state.paused {
+            return;
+        }
+        state.paused = false;
+        state.played = true;
+        if state.ended {
+            self.seek(0.0, SeekMode::Exact);
+        }
+        let playing = self.loaded() && !self.seeking();
+        let state = self.state_mut();
+        state.events.push(PlayerEvent::Play);
+        if playing {
+            state.events.push(PlayerEvent::Playing);
+        }
+    }
+
+    fn pause(&mut self) {
+        let state = self.state_mut();
+        if !state.paused {
+            state.paused = true;
+            state
+                .events
+                .extend([PlayerEvent::TimeUpdate, PlayerEvent::Pause]);
+        }
+    }
+
+    fn set_playback_rate(&mut self, playback_rate: f64) {
+        let state = self.state_mut();
+        if state.playback_rate != playback_rate {
+            state.playback_rate = playback_rate;
+            state.events.push(PlayerEvent::RateChange);
+        }
+    }
+
+    fn set_looping(&mut self, looping: bool) {
+        self.state_mut().looping = looping;
+    }
+
+    /// Only reported back, players have no audio
+    fn set_muted(&mut self, muted: bool) {
+        let state = self.state_mut();
+        if state.muted != muted {
+            state.muted = muted;
+            state.events.push(PlayerEvent::VolumeChange);
+        }
+    }
+
+    /// Only reported back, players have no audio
+    fn set_volume(&mut self, volume: f64) {
+        let state = self.state_mut();
+        if state.volume != volume {
+            state.volume = volume;
+            state.events.push(PlayerEvent::VolumeChange);
+        }
+    }
+}
+
+mod internal {
+    use super::TIME_UPDATE_INTERVAL;
+    use crate::{VideoError, WebTextureSource};
+
+    /// Parts of [`MediaPlayer`](super::MediaPlayer) only the crate uses
+    pub trait PlayerInternal {
+        fn state(&self) -> &PlayerState;
+
+        fn state_mut(&mut self) -> &mut PlayerState;
+
+        /// The shown frame, once loaded
+        fn frame(&self) -> Option<WebTextureSource>;
+
+        /// Advance the clock by `delta` seconds, showing the frames up to it
+        fn tick(&mut self, delta: f64);
+
+        /// Whether a new frame was shown since the last call
+        fn take_presented(&mut self) -> bool {
+            std::mem::take(&mut self.state_mut().presented)
+        }
+
+        fn take_events(&mut self) -> Vec<PlayerEvent> {
+            std::mem::take(&mut self.state_mut().events)
+        }
+    }
+
+    /// Playback state shared by all players
+    pub struct PlayerState {
+        pub(crate) current_time: f64,
+        pub(crate) since_time_update: f64,
+        pub(crate) paused: bool,
+        pub(crate) ended: bool,
+        pub(crate) played: bool,
+        pub(crate) loaded: bool,
+        pub(crate) looping: bool,
+        pub(crate) muted: bool,
+        pub(crate) volume: f64,
+        pub(crate) playback_rate: f64,
+        // A new frame is shown that was not uploaded yet
+        pub(crate) presented: bool,
+        pub(crate) error: Option<VideoError>,
+        pub(crate) events: Vec<PlayerEvent>,
+    }
+
+    impl Default for PlayerState {
+        fn default() -> Self {
+            Self {
+                current_time: 0.0,
+                since_time_update: 0.0,
+                paused: true,
+                ended: false,
+                played: false,
+                loaded: false,
+                looping: false,
+                muted: false,
+                volume: 1.0,
+                playback_rate: 1.0,
+                presented: false,
+                error: None,
+                events: Vec::new(),
+            }
+        }
+    }
+
+    impl PlayerState {
+        /// Advance the clock by `delta` seconds of real time, up to `duration`
+        pub(crate) fn advance(&mut self, delta: f64, duration: f64) {
+            self.current_time =
+                (self.current_time + delta * self.playback_rate.max(0.0)).min(duration);
+            self.since_time_update += delta;
+            if self.since_time_update >= TIME_UPDATE_INTERVAL {
+                self.since_time_update = 0.0;
+                self.events.push(PlayerEvent::TimeUpdate);
+            }
+        }
+
+        /// Stop at the end, looping players seek back to the start instead
+        pub(crate) fn end(&mut self) {
+            self.paused = true;
+            self.ended = true;
+            self.events.extend([
+                PlayerEvent::TimeUpdate,
+                PlayerEvent::Pause,
+                PlayerEvent::Ended,
+            ]);
+        }
+
+        pub(crate) fn fail(&mut self, error: VideoError) {
+            self.error = Some(error.clone());
+            self.events.push(PlayerEvent::Error(error));
+        }
+    }
+
+    /// Events fired by a [`MediaPlayer`](super::MediaPlayer), the media element events it stands in for
+    #[derive(Clone, Debug)]
+    pub enum PlayerEvent {
+        LoadedMetadata,
+        LoadedData,
+        CanPlay,
+        Resize,
+        Play,
+        Playing,
+        Pause,
+        Seeking,
+        Seeked,
+        TimeUpdate,
+        Ended,
+        Looped,
+        RateChange,
+        VolumeChange,
+        Error(VideoError),
+    }
+}
+
+impl PlayerEvent {
+    pub(crate) fn send(
+        self,
+        asset_id: AssetId<VideoElement>,
+        player: &dyn MediaPlayer,
+        registry: &VideoElementRegistry,
+    ) {
+        match self {
+            PlayerEvent::LoadedMetadata => registry.send_event::<events::LoadedMetadata>(
+                asset_id,
+                VideoMetadata {
+                    size: player.size(),
+                    duration: player.duration(),
+                },
+            ),
+            PlayerEvent::LoadedData => registry.send_event::<events::LoadedData>(asset_id, ()),
+            PlayerEvent::CanPlay => registry.send_event::<events::CanPlay>(asset_id, ()),
+            PlayerEvent::Resize => registry.send_event::<events::Resize>(asset_id, player.size()),
+            PlayerEvent::Play => registry.send_event::<events::Play>(asset_id, ()),
+            PlayerEvent::Playing => registry.send_event::<events::Playing>(asset_id, ()),
+            PlayerEvent::Pause => registry.send_event::<events::Pause>(asset_id, ()),
+            PlayerEvent::Seeking => registry.send_event::<events::Seeking>(asset_id, ()),
+            PlayerEvent::Seeked => registry.send_event::<events::Seeked>(asset_id, ()),
+            PlayerEvent::TimeUpdate => registry.send_event::<events::TimeUpdate>(
+                asset_id,
+                PlaybackTime {
+                    current_time: player.current_time(),
+                    duration: player.duration(),
+                },
+            ),
+            PlayerEvent::Ended => registry.send_event::<events::Ended>(asset_id, ()),
+            PlayerEvent::Looped => registry.send_event::<events::Looped>(asset_id, ()),
+            PlayerEvent::RateChange => {
+                registry.send_event::<events::RateChange>(asset_id, player.playback_rate())
+            }
+            PlayerEvent::VolumeChange => registry.send_event::<events::VolumeChange>(
+                asset_id,
+                Volume {
+                    volume: player.volume(),
+                    muted: player.muted(),
+                },
+            ),
+            PlayerEvent::Error(error) => registry.send_event::<events::Error>(asset_id, error),
+        }
+    }
+}
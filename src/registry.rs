@@ -1,85 +1,96 @@
-use crate::{EventSender, EventType, VideoElement, event::ListenerEventInternal, events};
+#[cfg(feature = "webcodecs")]
+use crate::codecs::{CodecVideo, EncodedVideo};
+use crate::{
+    AnimatedImage, EncodedImage, EventType, MediaPlayer, VideoElement, VideoError,
+    WebTextureSource,
+    dash::DashStream,
+    event::{ListenerEventInternal, new_listener},
+    events,
+    hls::HlsStream,
+    media_source::{MediaSourceEvent, MediaSourceVideo},
+    segmented::{SegmentedStream, playing_range},
+    source::{RegisteredSource, SourceUpdate},
+    stream::{MediaStreamSource, RegisteredStream},
+};
 use bevy::prelude::*;
+use frame::FrameCallback;
 use gloo_events::EventListener;
-use std::collections::HashMap;
-use wasm_bindgen::UnwrapThrowExt;
+use std::{
+    any::{Any, TypeId},
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+    sync::Arc,
+};
+use wasm_bindgen::prelude::*;
 
 pub mod asset;
+mod frame;
+pub mod loader;
 
 pub fn plugin(app: &mut App) {
-    app.add_plugins(asset::plugin);
-
-    let world = app.world();
-    let tx_loadedmetadata = world
-        .get_resource::<EventSender<events::LoadedMetadata>>()
-        .expect("EventSender<LoadedMetadata>")
-        .tx();
-    let tx_canplay = world
-        .get_resource::<EventSender<events::CanPlay>>()
-        .expect("EventSender<CanPlay>")
-        .tx();
-    let tx_resize = world
-        .get_resource::<EventSender<events::Resize>>()
-        .expect("EventSender<Resize>")
-        .tx();
-    let tx_playing = world
-        .get_resource::<EventSender<events::Playing>>()
-        .expect("EventSender<Playing>")
-        .tx();
-    let tx_ended = world
-        .get_resource::<EventSender<events::Ended>>()
-        .expect("EventSender<Ended>")
-        .tx();
-    let tx_error = world
-        .get_resource::<EventSender<events::Error>>()
-        .expect("EventSender<Error>")
-        .tx();
-
-    app.insert_non_send_resource(VideoElementRegistry::new(
-        tx_loadedmetadata,
-        tx_canplay,
-        tx_resize,
-        tx_playing,
-        tx_ended,
-        tx_error,
-    ));
+    // Listener events added before WebVideoPlugin already initialized it
+    app.init_non_send_resource::<VideoElementRegistry>()
+        .add_plugins((asset::plugin, loader::plugin));
 }
 
+type EventRoute =
+    Box<dyn Fn(AssetId<VideoElement>, &web_sys::EventTarget, Option<Entity>) -> EventListener>;
+
+/// Seconds from the end the playback position must have reached, and from the start a seek
+/// must land, to be reported as [`events::Looped`]. About a frame, timeupdate is not
+/// dispatched at the end so the position is projected from the last one.
+const LOOP_TOLERANCE: f64 = 0.1;
+
 pub struct VideoElementRegistry {
     elements: HashMap<AssetId<VideoElement>, RegisteredElement>,
     document: web_sys::Document,
-    tx_loadedmetadata: crossbeam_channel::Sender<ListenerEventInternal<events::LoadedMetadata>>,
-    tx_canplay: crossbeam_channel::Sender<ListenerEventInternal<events::CanPlay>>,
-    tx_resize: crossbeam_channel::Sender<ListenerEventInternal<events::Resize>>,
-    tx_playing: crossbeam_channel::Sender<ListenerEventInternal<events::Playing>>,
-    tx_ended: crossbeam_channel::Sender<ListenerEventInternal<events::Ended>>,
-    tx_error: crossbeam_channel::Sender<ListenerEventInternal<events::Error>>,
+    // Creates a listener for each registered EventType
+    event_routes: Vec<EventRoute>,
+    senders: HashMap<TypeId, Box<dyn Any>>,
+    texture_sources: HashMap<AssetId<VideoElement>, RegisteredSource>,
+    animated_images: HashMap<AssetId<VideoElement>, AnimatedImage>,
+    media_streams: HashMap<AssetId<VideoElement>, RegisteredStream>,
+    media_sources: HashMap<AssetId<VideoElement>, MediaSourceVideo>,
+    hls_streams: HashMap<AssetId<VideoElement>, HlsStream>,
+    dash_streams: HashMap<AssetId<VideoElement>, DashStream>,
+    #[cfg(feature = "webcodecs")]
+    codec_videos: HashMap<AssetId<VideoElement>, CodecVideo>,
+    next_subscription_id: u64,
+    // Targets that need an EventObserverTarget to clean up on despawn
+    new_targets: Vec<Entity>,
+    tx_frame: crossbeam_channel::Sender<AssetId<VideoElement>>,
+    rx_frame: crossbeam_channel::Receiver<AssetId<VideoElement>>,
 }
 
-impl VideoElementRegistry {
-    fn new(
-        tx_loadedmetadata: crossbeam_channel::Sender<ListenerEventInternal<events::LoadedMetadata>>,
-        tx_canplay: crossbeam_channel::Sender<ListenerEventInternal<events::CanPlay>>,
-        tx_resize: crossbeam_channel::Sender<ListenerEventInternal<events::Resize>>,
-        tx_playing: crossbeam_channel::Sender<ListenerEventInternal<events::Playing>>,
-        tx_ended: crossbeam_channel::Sender<ListenerEventInternal<events::Ended>>,
-        tx_error: crossbeam_channel::Sender<ListenerEventInternal<events::Error>>,
-    ) -> Self {
+impl Default for VideoElementRegistry {
+    fn default() -> Self {
+        let (tx_frame, rx_frame) = crossbeam_channel::unbounded();
         Self {
             elements: HashMap::default(),
             document: web_sys::window()
                 .expect_throw("window")
                 .document()
                 .expect_throw("document"),
-            tx_loadedmetadata,
-            tx_canplay,
-            tx_resize,
-            tx_playing,
-            tx_ended,
-            tx_error,
+            event_routes: Vec::default(),
+            senders: HashMap::default(),
+            texture_sources: HashMap::default(),
+            animated_images: HashMap::default(),
+            media_streams: HashMap::default(),
+            media_sources: HashMap::default(),
+            hls_streams: HashMap::default(),
+            dash_streams: HashMap::default(),
+            #[cfg(feature = "webcodecs")]
+            codec_videos: HashMap::default(),
+            next_subscription_id: 0,
+            new_targets: Vec::default(),
+            tx_frame,
+            rx_frame,
         }
     }
+}
 
+impl VideoElementRegistry {
     pub fn element(
         &self,
         asset_id: impl Into<AssetId<VideoElement>>,
@@ -91,86 +102,770 @@ impl VideoElementRegistry {
         &self.document
     }
 
-    pub(crate) fn add_event_listener(
+    pub fn texture_source(
+        &self,
+        asset_id: impl Into<AssetId<VideoElement>>,
+    ) -> Option<&WebTextureSource> {
+        self.texture_sources
+            .get(&asset_id.into())
+            .map(|registered_source| &registered_source.source)
+    }
+
+    /// Replace the texture source of the asset, e.g. with a new `ImageBitmap`, and upload it.
+    /// Returns `false` if the asset has no texture source.
+    pub fn set_texture_source(
+        &mut self,
+        asset_id: impl Into<AssetId<VideoElement>>,
+        source: impl Into<WebTextureSource>,
+    ) -> bool {
+        if let Some(registered_source) = self.texture_sources.get_mut(&asset_id.into()) {
+            registered_source.source = source.into();
+            registered_source.upload_requested = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Upload the texture source of the asset on the next frame, for [`SourceUpdate::OnRequest`].
+    /// Returns `false` if the asset has no texture source.
+    pub fn request_source_upload(&mut self, asset_id: impl Into<AssetId<VideoElement>>) -> bool {
+        if let Some(registered_source) = self.texture_sources.get_mut(&asset_id.into()) {
+            registered_source.upload_requested = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Video of an asset loaded with [`VideoBackend::WebCodecs`](crate::VideoBackend::WebCodecs)
+    #[cfg(feature = "webcodecs")]
+    pub fn codec_video(&self, asset_id: impl Into<AssetId<VideoElement>>) -> Option<&CodecVideo> {
+        self.codec_videos.get(&asset_id.into())
+    }
+
+    #[cfg(feature = "webcodecs")]
+    pub fn codec_video_mut(
+        &mut self,
+        asset_id: impl Into<AssetId<VideoElement>>,
+    ) -> Option<&mut CodecVideo> {
+        self.codec_videos.get_mut(&asset_id.into())
+    }
+
+    /// Animated image of an asset loaded from a GIF, APNG or WebP file
+    pub fn animated_image(
+        &self,
+        asset_id: impl Into<AssetId<VideoElement>>,
+    ) -> Option<&AnimatedImage> {
+        self.animated_images.get(&asset_id.into())
+    }
+
+    /// Stream of an asset created from a [`MediaStreamSource`], `None` until granted
+    pub fn media_stream(
+        &self,
+        asset_id: impl Into<AssetId<VideoElement>>,
+    ) -> Option<web_sys::MediaStream> {
+        self.media_streams
+            .get(&asset_id.into())
+            .and_then(RegisteredStream::stream)
+    }
+
+    /// Media source of an asset created from [`VideoSource::MediaSource`](crate::VideoSource::MediaSource)
+    pub fn media_source(
+        &self,
+        asset_id: impl Into<AssetId<VideoElement>>,
+    ) -> Option<&MediaSourceVideo> {
+        self.media_sources.get(&asset_id.into())
+    }
+
+    pub fn media_source_mut(
+        &mut self,
+        asset_id: impl Into<AssetId<VideoElement>>,
+    ) -> Option<&mut MediaSourceVideo> {
+        self.media_sources.get_mut(&asset_id.into())
+    }
+
+    /// HLS stream of an asset created from [`VideoSource::Hls`](crate::VideoSource::Hls)
+    /// or loaded from an `.m3u8` file, `None` if the browser plays it natively
+    pub fn hls_stream(&self, asset_id: impl Into<AssetId<VideoElement>>) -> Option<&HlsStream> {
+        self.hls_streams.get(&asset_id.into())
+    }
+
+    pub fn hls_stream_mut(
+        &mut self,
+        asset_id: impl Into<AssetId<VideoElement>>,
+    ) -> Option<&mut HlsStream> {
+        self.hls_streams.get_mut(&asset_id.into())
+    }
+
+    /// DASH stream of an asset created from [`VideoSource::Dash`](crate::VideoSource::Dash)
+    /// or loaded from an `.mpd` file
+    pub fn dash_stream(&self, asset_id: impl Into<AssetId<VideoElement>>) -> Option<&DashStream> {
+        self.dash_streams.get(&asset_id.into())
+    }
+
+    pub fn dash_stream_mut(
+        &mut self,
+        asset_id: impl Into<AssetId<VideoElement>>,
+    ) -> Option<&mut DashStream> {
+        self.dash_streams.get_mut(&asset_id.into())
+    }
+
+    /// HLS or DASH stream of an asset
+    pub(crate) fn segmented_stream(
+        &self,
+        asset_id: AssetId<VideoElement>,
+    ) -> Option<&dyn SegmentedStream> {
+        match self.hls_streams.get(&asset_id) {
+            Some(stream) => Some(stream),
+            None => Some(self.dash_streams.get(&asset_id)?),
+        }
+    }
+
+    pub(crate) fn segmented_stream_mut(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+    ) -> Option<&mut dyn SegmentedStream> {
+        match self.hls_streams.get_mut(&asset_id) {
+            Some(stream) => Some(stream),
+            None => Some(self.dash_streams.get_mut(&asset_id)?),
+        }
+    }
+
+    /// Seconds of video buffered ahead of the current time of a segmented stream
+    pub(crate) fn buffer_level(&self, asset_id: AssetId<VideoElement>) -> Option<f64> {
+        let buffer = self.segmented_stream(asset_id)?.video_buffer()?;
+        let buffered = self.media_sources.get(&asset_id)?.buffered(buffer);
+        let current_time = self.elements.get(&asset_id)?.element().current_time();
+        Some(
+            playing_range(&buffered, current_time)
+                .map(|range| (range.end - current_time).max(0.0))
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Media the crate plays itself instead of an element, i.e. an [`AnimatedImage`]
+    /// or a [`CodecVideo`](crate::CodecVideo)
+    pub fn player(&self, asset_id: impl Into<AssetId<VideoElement>>) -> Option<&dyn MediaPlayer> {
+        let asset_id = asset_id.into();
+        let player = self
+            .animated_images
+            .get(&asset_id)
+            .map(|animated_image| animated_image as &dyn MediaPlayer);
+        #[cfg(feature = "webcodecs")]
+        let player = player.or_else(|| {
+            self.codec_videos
+                .get(&asset_id)
+                .map(|codec_video| codec_video as &dyn MediaPlayer)
+        });
+        player
+    }
+
+    pub fn player_mut(
         &mut self,
         asset_id: impl Into<AssetId<VideoElement>>,
-        listener: EventListener,
+    ) -> Option<&mut dyn MediaPlayer> {
+        let asset_id = asset_id.into();
+        let player = self
+            .animated_images
+            .get_mut(&asset_id)
+            .map(|animated_image| animated_image as &mut dyn MediaPlayer);
+        #[cfg(feature = "webcodecs")]
+        let player = player.or_else(|| {
+            self.codec_videos
+                .get_mut(&asset_id)
+                .map(|codec_video| codec_video as &mut dyn MediaPlayer)
+        });
+        player
+    }
+
+    /// Whether the asset has an element, texture source or player
+    pub(crate) fn contains(&self, asset_id: AssetId<VideoElement>) -> bool {
+        self.elements.contains_key(&asset_id)
+            || self.texture_sources.contains_key(&asset_id)
+            || self.player(asset_id).is_some()
+    }
+
+    /// Intrinsic size of the asset's video, zero until known
+    pub(crate) fn video_size(&self, asset_id: AssetId<VideoElement>) -> Option<UVec2> {
+        self.element(asset_id)
+            .map(|element| UVec2::new(element.video_width(), element.video_height()))
+            .or_else(|| self.texture_source(asset_id).map(WebTextureSource::size))
+            .or_else(|| self.player(asset_id).map(|player| player.size()))
+    }
+
+    /// Keeps `listeners` for `target` until unsubscribed or `target` is despawned.
+    ///
+    /// `routed` is the event type the listeners fire when they listen on the video element
+    /// itself, which is then no longer routed to `target`.
+    pub(crate) fn subscribe(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+        target: Entity,
+        listeners: Vec<EventListener>,
+        routed: Option<TypeId>,
+    ) -> Option<EventSubscription> {
+        let registered_element = self.elements.get_mut(&asset_id)?;
+        self.next_subscription_id += 1;
+        let subscription = EventSubscription {
+            asset_id,
+            id: self.next_subscription_id,
+        };
+        registered_element.subscriptions.push(Subscription {
+            id: subscription.id,
+            target,
+            routed,
+            _listeners: listeners,
+        });
+        self.new_targets.push(target);
+        Some(subscription)
+    }
+
+    /// Removes the listeners created for `subscription`
+    pub fn disable_element_event_observers(&mut self, subscription: EventSubscription) -> bool {
+        if let Some(registered_element) = self.elements.get_mut(&subscription.asset_id) {
+            let count = registered_element.subscriptions.len();
+            registered_element
+                .subscriptions
+                .retain(|s| s.id != subscription.id);
+            count != registered_element.subscriptions.len()
+        } else {
+            false
+        }
+    }
+
+    /// Removes all listeners targeting `target`
+    pub fn disable_entity_event_observers(&mut self, target: Entity) {
+        for registered_element in self.elements.values_mut() {
+            registered_element
+                .subscriptions
+                .retain(|s| s.target != target);
+        }
+    }
+
+    /// A subscription of `target` already fires `event_type` of the video element
+    pub(crate) fn is_routed(
+        &self,
+        asset_id: AssetId<VideoElement>,
+        target: Entity,
+        event_type: TypeId,
+    ) -> bool {
+        self.elements
+            .get(&asset_id)
+            .is_some_and(|registered_element| {
+                registered_element
+                    .subscriptions
+                    .iter()
+                    .any(|s| s.target == target && s.routed == Some(event_type))
+            })
+    }
+
+    pub(crate) fn take_new_targets(&mut self) -> Vec<Entity> {
+        std::mem::take(&mut self.new_targets)
+    }
+
+    /// Listen for `E` on all current and future elements
+    pub(crate) fn add_event_type<E: EventType>(
+        &mut self,
+        tx: crossbeam_channel::Sender<ListenerEventInternal<E>>,
     ) {
-        if let Some(registered_element) = self.elements.get_mut(&asset_id.into()) {
+        let route_tx = tx.clone();
+        let route: EventRoute = Box::new(move |asset_id, element, target| {
+            new_listener(route_tx.clone(), asset_id, element, target)
+        });
+        for (asset_id, registered_element) in self.elements.iter_mut() {
+            let listener = route(*asset_id, &registered_element.element, None);
             registered_element.listeners.push(listener);
         }
+        self.event_routes.push(route);
+        self.senders.insert(TypeId::of::<E>(), Box::new(tx));
+    }
+
+    fn sender<E: EventType>(&self) -> Option<crossbeam_channel::Sender<ListenerEventInternal<E>>> {
+        self.senders
+            .get(&TypeId::of::<E>())
+            .and_then(|tx| tx.downcast_ref::<crossbeam_channel::Sender<ListenerEventInternal<E>>>())
+            .cloned()
+    }
+
+    /// Fire `E` for the asset as if its element dispatched it
+    pub(crate) fn send_event<E: EventType>(
+        &self,
+        asset_id: AssetId<VideoElement>,
+        payload: E::Payload,
+    ) {
+        send_event(self.sender::<E>().as_ref(), asset_id, payload);
+    }
+
+    pub(crate) fn create_element(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+    ) -> web_sys::HtmlVideoElement {
+        let element = self
+            .document
+            .create_element("video")
+            .inspect_err(|e| warn!("{e:?}"))
+            .unwrap_throw()
+            .dyn_into::<web_sys::HtmlVideoElement>()
+            .inspect_err(|e| warn!("{e:?}"))
+            .expect_throw("web_sys::HtmlVideoElement");
+        self.insert(asset_id, element.clone());
+        element
+    }
+
+    pub(crate) fn create_texture_source(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+        source: WebTextureSource,
+        update: SourceUpdate,
+    ) {
+        self.texture_sources
+            .insert(asset_id, RegisteredSource::new(source, update));
+    }
+
+    /// Fire [`events::Resize`] for texture sources whose size changed, including their first size
+    pub(crate) fn update_texture_sources(&mut self) {
+        let mut resized = Vec::new();
+        for (asset_id, registered_source) in &mut self.texture_sources {
+            let size = registered_source.source.size();
+            if size != registered_source.size {
+                registered_source.size = size;
+                registered_source.upload_requested = true;
+                resized.push((*asset_id, size));
+            }
+        }
+        for (asset_id, size) in resized {
+            self.send_event::<events::Resize>(asset_id, size);
+        }
+    }
+
+    /// Plays `video` with WebCodecs instead of an element, failures are fired as [`events::Error`]
+    #[cfg(feature = "webcodecs")]
+    pub(crate) fn create_codec_video(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+        video: Arc<EncodedVideo>,
+        looping: bool,
+        muted: bool,
+    ) {
+        match CodecVideo::new(video) {
+            Ok(mut codec_video) => {
+                codec_video.set_looping(looping);
+                codec_video.set_muted(muted);
+                self.codec_videos.insert(asset_id, codec_video);
+            }
+            Err(error) => self.send_event::<events::Error>(asset_id, error),
+        }
+    }
+
+    /// Plays the frames of `image`, decoded in the background. Decode failures are fired as
+    /// [`events::Error`] by the [`AnimatedImage`] once it ticks, and reported as
+    /// [`VideoStatus::Errored`](crate::VideoStatus::Errored)
+    pub(crate) fn create_animated_image(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+        image: Arc<EncodedImage>,
+        looping: bool,
+        muted: bool,
+    ) {
+        let mut animated_image = AnimatedImage::new(image);
+        animated_image.set_looping(looping);
+        animated_image.set_muted(muted);
+        self.animated_images.insert(asset_id, animated_image);
+    }
+
+    /// Requests the stream of `source` for the asset's element, the outcome is fired as
+    /// [`events::StreamPermission`]
+    pub(crate) fn request_media_stream(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+        source: &MediaStreamSource,
+        element: &web_sys::HtmlVideoElement,
+    ) {
+        let stream = RegisteredStream::request(source, element, &self.document);
+        self.media_streams.insert(asset_id, stream);
+    }
+
+    /// Plays a `MediaSource` on the asset's element, failures are fired as [`events::Error`]
+    pub(crate) fn create_media_source(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+        element: &web_sys::HtmlVideoElement,
+    ) {
+        match MediaSourceVideo::new(element) {
+            Ok(media_source) => {
+                self.media_sources.insert(asset_id, media_source);
+            }
+            Err(error) => self.stream_failed(asset_id, error),
+        }
+    }
+
+    /// Keeps the error of the asset's stream as its [`stream_error`](Self::stream_error)
+    /// and fires it as [`events::Error`]
+    fn stream_failed(&mut self, asset_id: AssetId<VideoElement>, error: VideoError) {
+        if let Some(registered_element) = self.elements.get_mut(&asset_id) {
+            registered_element.stream_error = Some(error.clone());
+        }
+        self.send_event::<events::Error>(asset_id, error);
+    }
+
+    /// Why the MSE, HLS or DASH stream of the asset's element failed, it has stopped
+    pub fn stream_error(&self, asset_id: impl Into<AssetId<VideoElement>>) -> Option<VideoError> {
+        self.elements.get(&asset_id.into())?.stream_error.clone()
+    }
+
+    /// Streams `url` through a `MediaSource` on the asset's element, or as its source if it
+    /// plays HLS natively without MSE. Failures are fired as [`events::Error`]
+    pub(crate) fn create_hls_stream(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+        url: &str,
+        element: &web_sys::HtmlVideoElement,
+    ) {
+        if !HlsStream::is_supported() {
+            if HlsStream::is_natively_supported(element) {
+                element.set_src(url);
+            } else {
+                self.stream_failed(asset_id, VideoError::NotSupported);
+            }
+            return;
+        }
+        self.create_media_source(asset_id, element);
+        if self.media_sources.contains_key(&asset_id) {
+            self.hls_streams.insert(asset_id, HlsStream::new(url));
+        }
+    }
+
+    /// Complete HLS requests and queue their segments, failures are fired as [`events::Error`]
+    pub(crate) fn update_hls_streams(&mut self) {
+        let mut failed = Vec::new();
+        for (asset_id, stream) in &mut self.hls_streams {
+            let (Some(media_source), Some(registered_element)) = (
+                self.media_sources.get_mut(asset_id),
+                self.elements.get(asset_id),
+            ) else {
+                continue;
+            };
+            if let Err(error) = stream.update(media_source, registered_element.element()) {
+                failed.push((*asset_id, error));
+            }
+        }
+        for (asset_id, error) in failed {
+            self.stream_failed(asset_id, error);
+        }
+    }
+
+    /// Streams `url` through a `MediaSource` on the asset's element. Failures are fired as
+    /// [`events::Error`]
+    pub(crate) fn create_dash_stream(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+        url: &str,
+        element: &web_sys::HtmlVideoElement,
+    ) {
+        if !DashStream::is_supported() {
+            self.stream_failed(asset_id, VideoError::NotSupported);
+            return;
+        }
+        self.create_media_source(asset_id, element);
+        if self.media_sources.contains_key(&asset_id) {
+            self.dash_streams.insert(asset_id, DashStream::new(url));
+        }
+    }
+
+    /// Complete DASH requests and queue their segments, failures are fired as [`events::Error`]
+    pub(crate) fn update_dash_streams(&mut self) {
+        let mut failed = Vec::new();
+        for (asset_id, stream) in &mut self.dash_streams {
+            let (Some(media_source), Some(registered_element)) = (
+                self.media_sources.get_mut(asset_id),
+                self.elements.get(asset_id),
+            ) else {
+                continue;
+            };
+            if let Err(error) = stream.update(media_source, registered_element.element()) {
+                failed.push((*asset_id, error));
+            }
+        }
+        for (asset_id, error) in failed {
+            self.stream_failed(asset_id, error);
+        }
+    }
+
+    /// Run queued source buffer operations and fire their events
+    pub(crate) fn update_media_sources(&mut self) {
+        let fired = self
+            .media_sources
+            .iter_mut()
+            .map(|(asset_id, media_source)| (*asset_id, media_source.update()))
+            .collect::<Vec<_>>();
+        for (asset_id, events) in fired {
+            for event in events {
+                match event {
+                    MediaSourceEvent::UpdateEnd(update) => {
+                        self.send_event::<events::UpdateEnd>(asset_id, update);
+                    }
+                    MediaSourceEvent::BufferError(failure) => {
+                        self.send_event::<events::SourceBufferError>(asset_id, failure);
+                    }
+                    MediaSourceEvent::Error(error) => {
+                        self.stream_failed(asset_id, error);
+                    }
+                }
+            }
+        }
+    }
+
+    fn players_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (AssetId<VideoElement>, &mut dyn MediaPlayer)> {
+        let players = self
+            .animated_images
+            .iter_mut()
+            .map(|(asset_id, animated_image)| (*asset_id, animated_image as &mut dyn MediaPlayer));
+        #[cfg(feature = "webcodecs")]
+        let players = players.chain(
+            self.codec_videos
+                .iter_mut()
+                .map(|(asset_id, codec_video)| (*asset_id, codec_video as &mut dyn MediaPlayer)),
+        );
+        players
+    }
+
+    /// Advance players by `delta` seconds and fire their events
+    pub(crate) fn tick_players(&mut self, delta: f64) {
+        let fired = self
+            .players_mut()
+            .map(|(asset_id, player)| {
+                player.tick(delta);
+                (asset_id, player.take_events())
+            })
+            .collect::<Vec<_>>();
+        for (asset_id, events) in fired {
+            if let Some(player) = self.player(asset_id) {
+                for event in events {
+                    event.send(asset_id, player, self);
+                }
+            }
+        }
+    }
+
+    /// Plays the element, a rejected play request is fired as an [`events::Error`] and kept
+    /// as the [`play_error`](Self::play_error) until the next request
+    pub(crate) fn play(&self, asset_id: AssetId<VideoElement>) {
+        let Some(registered_element) = self.elements.get(&asset_id) else {
+            return;
+        };
+        let element = registered_element.element();
+        let play_error = registered_element.play_error.clone();
+        play_error.replace(None);
+        let tx = self.sender::<events::Error>();
+        let send_error = move |err: JsValue| {
+            let error = VideoError::from(&err);
+            // Interrupted by pause() or a new load, not a failure
+            if error != VideoError::Aborted {
+                play_error.replace(Some(error.clone()));
+            }
+            send_event::<events::Error>(tx.as_ref(), asset_id, error);
+        };
+        match element.play() {
+            Ok(promise) => {
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Err(err) = wasm_bindgen_futures::JsFuture::from(promise).await {
+                        send_error(err);
+                    }
+                });
+            }
+            Err(err) => send_error(err),
+        }
+    }
+
+    /// Seeks the element, which is then not reported as [`events::Looped`]
+    pub(crate) fn seek(&self, asset_id: AssetId<VideoElement>, time: f64, fast: bool) {
+        let Some(registered_element) = self.elements.get(&asset_id) else {
+            return;
+        };
+        let element = registered_element.element();
+        registered_element.seek_requested.set(true);
+        if !(fast && element.fast_seek(time).is_ok()) {
+            element.set_current_time(time);
+        }
+    }
+
+    /// Why the last play request of the element was rejected, e.g. [`VideoError::NotAllowed`]
+    /// by the autoplay policy. Cleared once the element plays.
+    pub fn play_error(&self, asset_id: impl Into<AssetId<VideoElement>>) -> Option<VideoError> {
+        let registered_element = self.elements.get(&asset_id.into())?;
+        if !registered_element.element.paused() {
+            registered_element.play_error.replace(None);
+        }
+        registered_element.play_error.borrow().clone()
+    }
+
+    /// Assets whose element or player presented a new frame, or whose texture source
+    /// is due for upload, since the last call
+    pub(crate) fn presented_frames(&mut self) -> impl Iterator<Item = AssetId<VideoElement>> {
+        // Poll current time of elements that don't support requestVideoFrameCallback
+        let polled =
+            self.elements
+                .iter_mut()
+                .filter(|(_, registered_element)| registered_element.frame_callback.is_none())
+                .filter_map(|(asset_id, registered_element)| {
+                    let current_time = registered_element.element.current_time();
+                    (current_time != registered_element.last_time).then(|| {
+                        registered_element.last_time = current_time;
+                        *asset_id
+                    })
+                })
+                .chain(self.texture_sources.iter_mut().filter_map(
+                    |(asset_id, registered_source)| {
+                        registered_source.take_upload().then_some(*asset_id)
+                    },
+                ))
+                .collect::<Vec<_>>();
+        let polled = polled
+            .into_iter()
+            .chain(
+                self.players_mut()
+                    .filter_map(|(asset_id, player)| player.take_presented().then_some(asset_id)),
+            )
+            .collect::<Vec<_>>();
+        self.rx_frame.try_iter().chain(polled)
     }
 
     fn insert(&mut self, asset_id: AssetId<VideoElement>, element: web_sys::HtmlVideoElement) {
         let mut registered_element = RegisteredElement::new(element.clone());
-
-        registered_element
-            .listeners
-            .push(Self::new_internal_listener(
-                self.tx_loadedmetadata.clone(),
-                asset_id,
+        if FrameCallback::is_supported(&element) {
+            registered_element.frame_callback = Some(FrameCallback::new(
                 &element,
-            ));
-        registered_element
-            .listeners
-            .push(Self::new_internal_listener(
-                self.tx_canplay.clone(),
                 asset_id,
-                &element,
-            ));
-        registered_element
-            .listeners
-            .push(Self::new_internal_listener(
-                self.tx_resize.clone(),
-                asset_id,
-                &element,
-            ));
-        registered_element
-            .listeners
-            .push(Self::new_internal_listener(
-                self.tx_playing.clone(),
-                asset_id,
-                &element,
-            ));
-        registered_element
-            .listeners
-            .push(Self::new_internal_listener(
-                self.tx_ended.clone(),
-                asset_id,
-                &element,
-            ));
-        registered_element
-            .listeners
-            .push(Self::new_internal_listener(
-                self.tx_error.clone(),
-                asset_id,
-                &element,
+                self.tx_frame.clone(),
             ));
+        }
+
+        for route in &self.event_routes {
+            registered_element
+                .listeners
+                .push(route(asset_id, &element, None));
+        }
+        registered_element.listeners.extend(new_loop_listeners(
+            &element,
+            registered_element.seek_requested.clone(),
+        ));
 
         self.elements.insert(asset_id, registered_element);
     }
 
-    fn new_internal_listener<E: EventType>(
-        tx: crossbeam_channel::Sender<ListenerEventInternal<E>>,
-        asset_id: AssetId<VideoElement>,
-        element: &web_sys::HtmlVideoElement,
-    ) -> EventListener {
-        EventListener::new(element, E::EVENT_NAME, move |_event: &web_sys::Event| {
-            if let Err(err) = tx.send(ListenerEventInternal::<E>::new(asset_id, None)) {
-                warn!("Failed to fire video event {}: {err:?}", E::EVENT_NAME);
-            };
-        })
+    fn remove(&mut self, asset_id: impl Into<AssetId<VideoElement>>) -> Option<RegisteredElement> {
+        let asset_id = asset_id.into();
+        self.texture_sources.remove(&asset_id);
+        self.animated_images.remove(&asset_id);
+        // Stops the tracks
+        self.media_streams.remove(&asset_id);
+        // Aborts the running request
+        self.hls_streams.remove(&asset_id);
+        self.dash_streams.remove(&asset_id);
+        self.media_sources.remove(&asset_id);
+        #[cfg(feature = "webcodecs")]
+        self.codec_videos.remove(&asset_id);
+        self.elements.remove(&asset_id)
     }
+}
 
-    fn remove(&mut self, asset_id: impl Into<AssetId<VideoElement>>) -> Option<RegisteredElement> {
-        self.elements.remove(&asset_id.into())
+fn send_event<E: EventType>(
+    tx: Option<&crossbeam_channel::Sender<ListenerEventInternal<E>>>,
+    asset_id: AssetId<VideoElement>,
+    payload: E::Payload,
+) {
+    if let Some(tx) = tx
+        && let Err(err) = tx.send(ListenerEventInternal::new(asset_id, None, payload))
+    {
+        warn!("Failed to fire video event {}: {err:?}", E::EVENT_NAME);
     }
 }
 
+/// Dispatches [`events::Looped`] on the element when a looping video wraps around
+fn new_loop_listeners(
+    element: &web_sys::HtmlVideoElement,
+    seek_requested: Rc<Cell<bool>>,
+) -> [EventListener; 2] {
+    // Position, whether playing and timestamp in milliseconds of the last timeupdate
+    let last_update = Rc::new(Cell::new((0.0, false, 0.0)));
+    let timeupdate_last_update = last_update.clone();
+    [
+        EventListener::new(element, "timeupdate", move |event: &web_sys::Event| {
+            if let Some(element) = events::video_element(event) {
+                timeupdate_last_update.set((
+                    element.current_time(),
+                    !element.paused(),
+                    js_sys::Date::now(),
+                ));
+            }
+        }),
+        // Looping seeks back to the start when the end is reached
+        EventListener::new(element, "seeking", move |event: &web_sys::Event| {
+            let Some(element) = events::video_element(event) else {
+                return;
+            };
+            // Seeks requested through the crate are never loops
+            if seek_requested.replace(false) {
+                return;
+            }
+            let (last_time, playing, timestamp) = last_update.get();
+            let projected_time = if playing {
+                last_time + (js_sys::Date::now() - timestamp) / 1000.0 * element.playback_rate()
+            } else {
+                last_time
+            };
+            if element.loop_()
+                && element.current_time() < LOOP_TOLERANCE
+                && projected_time >= element.duration() - LOOP_TOLERANCE
+                && let Ok(looped) = web_sys::Event::new(events::Looped::EVENT_NAME)
+                && let Err(err) = element.dispatch_event(&looped)
+            {
+                warn!(
+                    "Failed to dispatch video event {}: {err:?}",
+                    events::Looped::EVENT_NAME
+                );
+            }
+        }),
+    ]
+}
+
+/// Handle to the listeners created by
+/// [`EventSender::enable_element_event_observers`](crate::EventSender::enable_element_event_observers),
+/// pass to [`VideoElementRegistry::disable_element_event_observers`] to remove them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EventSubscription {
+    asset_id: AssetId<VideoElement>,
+    id: u64,
+}
+
+impl EventSubscription {
+    pub fn asset_id(&self) -> AssetId<VideoElement> {
+        self.asset_id
+    }
+}
+
+#[derive(Debug)]
+struct Subscription {
+    id: u64,
+    target: Entity,
+    routed: Option<TypeId>,
+    // Dropping the listeners removes them from the element
+    _listeners: Vec<EventListener>,
+}
+
 #[derive(Debug)]
 pub struct RegisteredElement {
     element: web_sys::HtmlVideoElement,
     listeners: Vec<EventListener>,
+    subscriptions: Vec<Subscription>,
+    frame_callback: Option<FrameCallback>,
+    last_time: f64,
+    play_error: Rc<RefCell<Option<VideoError>>>,
+    stream_error: Option<VideoError>,
+    seek_requested: Rc<Cell<bool>>,
 }
 
 impl RegisteredElement {
@@ -178,6 +873,12 @@ impl RegisteredElement {
         Self {
             element,
             listeners: Vec::default(),
+            subscriptions: Vec::default(),
+            frame_callback: None,
+            last_time: f64::NAN,
+            play_error: Rc::default(),
+            stream_error: None,
+            seek_requested: Rc::default(),
         }
     }
 
@@ -185,3 +886,64 @@ impl RegisteredElement {
         &self.element
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CameraConstraints, MediaStreamPermission};
+    use wasm_bindgen_futures::JsFuture;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    // Media devices need a browser, webdriver.json gives Chrome a fake camera without prompts
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    /// Resolves with the next `event_name` event on `target`, listening from the call on
+    fn next_event(target: &web_sys::EventTarget, event_name: &'static str) -> JsFuture {
+        let target = target.clone();
+        JsFuture::from(js_sys::Promise::new(&mut |resolve, _| {
+            EventListener::once(&target, event_name, move |event| {
+                let _ = resolve.call1(&JsValue::NULL, event);
+            })
+            .forget();
+        }))
+    }
+
+    #[wasm_bindgen_test]
+    async fn stops_granted_camera_tracks_when_removed() {
+        let mut registry = VideoElementRegistry::default();
+        let asset_id = AssetId::<VideoElement>::default();
+        let element = registry
+            .document()
+            .create_element("video")
+            .unwrap()
+            .unchecked_into::<web_sys::HtmlVideoElement>();
+        let permission = next_event(&element, events::StreamPermission::EVENT_NAME);
+        registry.request_media_stream(
+            asset_id,
+            &MediaStreamSource::Camera(CameraConstraints::default()),
+            &element,
+        );
+
+        let event = permission.await.unwrap().unchecked_into::<web_sys::Event>();
+        assert_eq!(
+            MediaStreamPermission::from_event(&event),
+            MediaStreamPermission::Granted
+        );
+        let stream = registry
+            .media_stream(asset_id)
+            .expect("granted stream is assigned");
+        assert!(element.src_object().is_some());
+        let tracks = stream.get_tracks();
+        assert!(tracks.length() > 0);
+
+        registry.remove(asset_id);
+        for track in tracks.iter() {
+            assert_eq!(
+                track
+                    .unchecked_into::<web_sys::MediaStreamTrack>()
+                    .ready_state(),
+                web_sys::MediaStreamTrackState::Ended
+            );
+        }
+    }
+}
@@ -1,13 +1,18 @@
 use crate::{
-    VideoElementRegistry,
-    event::{ListenerAssetEvent, events},
+    SourceUpdate, VideoElementRegistry, WebTextureSource, WebVideoImage,
+    event::{ListenerAssetEvent, VideoEntities, events},
+    registry::loader::VideoLoaderSettings,
+    texture::{VideoTextureDownscaled, VideoTextureSettings, max_dimension},
 };
 use bevy::{
     asset::{AssetEventSystems, RenderAssetUsages},
+    ecs::system::SystemParam,
     prelude::*,
-    render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        renderer::RenderDevice,
+    },
 };
-use wasm_bindgen::prelude::*;
 
 pub fn plugin(app: &mut App) {
     app.init_asset::<VideoElement>()
@@ -15,23 +20,70 @@ pub fn plugin(app: &mut App) {
         .add_observer(on_canplay)
         .add_observer(on_resize)
         .add_observer(on_error)
-        .add_observer(on_playing)
-        .add_observer(on_ended)
-        .add_systems(Update, mark_assets_modified)
-        .add_systems(PostUpdate, remove_unused_assets.after(AssetEventSystems));
+        .add_observer(on_loadeddata)
+        .add_observer(on_seeked)
+        .add_systems(Update, (mark_assets_modified, sync_target_images))
+        .add_systems(
+            PostUpdate,
+            (create_pending_elements, remove_unused_assets).after(AssetEventSystems),
+        );
 }
 
 #[derive(Asset, Clone, Debug, TypePath)]
 pub struct VideoElement {
     target_image_id: AssetId<Image>,
-    renderable: bool,
+    // Keeps a labeled target image alive for loaded videos
+    target_image: Option<Handle<Image>>,
+    // Element to be created once the asset is added
+    pending: Option<PendingElement>,
+    texture_settings: VideoTextureSettings,
+}
+
+#[derive(Clone, Debug)]
+struct PendingElement {
+    media: PendingMedia,
+    settings: VideoLoaderSettings,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum PendingMedia {
+    /// Streamed by an element
+    Url(String),
+    /// Decoded with WebCodecs
+    #[cfg(feature = "webcodecs")]
+    Encoded(std::sync::Arc<crate::EncodedVideo>),
+    /// Decoded to frames by the browser or in Rust
+    AnimatedImage(std::sync::Arc<crate::EncodedImage>),
+    /// Requested from the browser and assigned to an element
+    MediaStream(crate::MediaStreamSource),
+    /// Fed by a `MediaSourceVideo`
+    MediaSource,
+    /// Fed by an `HlsStream`, or streamed by an element that plays HLS natively
+    Hls(String),
+    /// Fed by a `DashStream`
+    Dash(String),
 }
 
 impl VideoElement {
-    fn new(target_image: impl Into<AssetId<Image>>) -> Self {
+    pub(crate) fn new(target_image: impl Into<AssetId<Image>>) -> Self {
         Self {
             target_image_id: target_image.into(),
-            renderable: false,
+            target_image: None,
+            pending: None,
+            texture_settings: VideoTextureSettings::default(),
+        }
+    }
+
+    pub(crate) fn new_pending(
+        target_image: Handle<Image>,
+        media: PendingMedia,
+        settings: VideoLoaderSettings,
+    ) -> Self {
+        Self {
+            target_image_id: target_image.id(),
+            target_image: Some(target_image),
+            texture_settings: settings.texture.clone(),
+            pending: Some(PendingElement { media, settings }),
         }
     }
 
@@ -39,8 +91,18 @@ impl VideoElement {
         self.target_image_id
     }
 
-    pub(crate) fn is_renderable(&self) -> bool {
-        self.renderable
+    /// Strong handle to the target image, if this video owns it (e.g. it was loaded)
+    pub fn target_image(&self) -> Option<&Handle<Image>> {
+        self.target_image.as_ref()
+    }
+
+    pub fn texture_settings(&self) -> &VideoTextureSettings {
+        &self.texture_settings
+    }
+
+    /// Format and sampler changes are applied to the target image on the next update
+    pub fn set_texture_settings(&mut self, texture_settings: VideoTextureSettings) {
+        self.texture_settings = texture_settings;
     }
 }
 
@@ -50,6 +112,15 @@ pub trait VideoElementAssetsExt {
         target_image: impl Into<AssetId<Image>>,
         registry: &mut VideoElementRegistry,
     ) -> (Handle<VideoElement>, web_sys::HtmlVideoElement);
+
+    /// Stream `source` into `target_image` instead of a video element
+    fn new_texture_source(
+        &mut self,
+        target_image: impl Into<AssetId<Image>>,
+        source: impl Into<WebTextureSource>,
+        update: SourceUpdate,
+        registry: &mut VideoElementRegistry,
+    ) -> Handle<VideoElement>;
 }
 
 impl VideoElementAssetsExt for Assets<VideoElement> {
@@ -63,24 +134,118 @@ impl VideoElementAssetsExt for Assets<VideoElement> {
         self.insert(&video_handle, video_element)
             .expect("insert video");
 
-        let html_video_element = registry
-            .document()
-            .create_element("video")
-            .inspect_err(|e| warn!("{e:?}"))
-            .unwrap_throw()
-            .dyn_into::<web_sys::HtmlVideoElement>()
-            .inspect_err(|e| warn!("{e:?}"))
-            .expect_throw("web_sys::HtmlVideoElement");
-
-        registry.insert(video_handle.id(), html_video_element.clone());
+        let html_video_element = registry.create_element(video_handle.id());
 
         (video_handle, html_video_element)
     }
+
+    fn new_texture_source(
+        &mut self,
+        target_image: impl Into<AssetId<Image>>,
+        source: impl Into<WebTextureSource>,
+        update: SourceUpdate,
+        registry: &mut VideoElementRegistry,
+    ) -> Handle<VideoElement> {
+        let video_handle = self.add(VideoElement::new(target_image));
+        registry.create_texture_source(video_handle.id(), source.into(), update);
+        video_handle
+    }
 }
 
-fn mark_assets_modified(mut video_elements: ResMut<Assets<VideoElement>>) {
-    // Mark modified every frame so RenderAsset prepares the texture
-    video_elements.iter_mut().for_each(drop);
+fn create_pending_elements(
+    mut events: MessageReader<AssetEvent<VideoElement>>,
+    video_elements: Res<Assets<VideoElement>>,
+    video_entities: Res<VideoEntities>,
+    mut web_video_images: Query<&mut WebVideoImage>,
+    mut registry: NonSendMut<VideoElementRegistry>,
+) {
+    for event in events.read() {
+        let AssetEvent::Added { id: asset_id } = *event else {
+            continue;
+        };
+        let Some(video_element) = video_elements.get(asset_id) else {
+            continue;
+        };
+        // Entities spawned with the handle before the asset loaded
+        if let Some(image) = video_element.target_image() {
+            for entity in video_entities.get(asset_id) {
+                if let Ok(mut web_video_image) = web_video_images.get_mut(*entity) {
+                    web_video_image.set_if_neq(WebVideoImage(image.clone()));
+                }
+            }
+        }
+        if let Some(pending) = &video_element.pending
+            && !registry.contains(asset_id)
+        {
+            pending
+                .media
+                .create(asset_id, &pending.settings, &mut registry);
+        }
+    }
+}
+
+impl PendingMedia {
+    pub(crate) fn create(
+        &self,
+        asset_id: AssetId<VideoElement>,
+        settings: &VideoLoaderSettings,
+        registry: &mut VideoElementRegistry,
+    ) {
+        match self {
+            PendingMedia::Url(src) => {
+                let element = registry.create_element(asset_id);
+                settings.apply_to_element(&element);
+                element.set_src(src);
+            }
+            #[cfg(feature = "webcodecs")]
+            PendingMedia::Encoded(video) => {
+                registry.create_codec_video(
+                    asset_id,
+                    video.clone(),
+                    settings.looping,
+                    settings.muted,
+                );
+            }
+            PendingMedia::AnimatedImage(image) => {
+                registry.create_animated_image(
+                    asset_id,
+                    image.clone(),
+                    settings.looping,
+                    settings.muted,
+                );
+            }
+            PendingMedia::MediaStream(source) => {
+                let element = registry.create_element(asset_id);
+                settings.apply_to_element(&element);
+                registry.request_media_stream(asset_id, source, &element);
+            }
+            PendingMedia::MediaSource => {
+                let element = registry.create_element(asset_id);
+                settings.apply_to_element(&element);
+                registry.create_media_source(asset_id, &element);
+            }
+            PendingMedia::Hls(url) => {
+                let element = registry.create_element(asset_id);
+                settings.apply_to_element(&element);
+                registry.create_hls_stream(asset_id, url, &element);
+            }
+            PendingMedia::Dash(url) => {
+                let element = registry.create_element(asset_id);
+                settings.apply_to_element(&element);
+                registry.create_dash_stream(asset_id, url, &element);
+            }
+        }
+    }
+}
+
+fn mark_assets_modified(
+    mut video_elements: ResMut<Assets<VideoElement>>,
+    mut registry: NonSendMut<VideoElementRegistry>,
+) {
+    // Mark modified only when a new frame was presented so RenderAsset uploads the texture
+    for asset_id in registry.presented_frames() {
+        video_elements.get_mut(asset_id);
+    }
 }
 
 fn remove_unused_assets(
@@ -97,27 +262,100 @@ fn remove_unused_assets(
     }
 }
 
-fn resize_image(
-    video_element: &VideoElement,
-    element: &web_sys::HtmlVideoElement,
-    images: &mut Assets<Image>,
+/// Keep target image format and sampler in line with the video's texture settings
+fn sync_target_images(
+    video_elements: Res<Assets<VideoElement>>,
+    mut images: ResMut<Assets<Image>>,
 ) {
-    let width = element.video_width();
-    let height = element.video_height();
-    if width == 0 || height == 0 {
-        return;
+    for (_, video_element) in video_elements.iter() {
+        let settings = video_element.texture_settings();
+        let format = TextureFormat::from(settings.format);
+        if let Some(image) = images.get(video_element.target_image_id())
+            && let mip_level_count = settings.mip_level_count(image.width(), image.height())
+            && (image.texture_descriptor.format != format
+                || image.texture_descriptor.mip_level_count != mip_level_count
+                || image.sampler != settings.sampler)
+            && let Some(image) = images.get_mut(video_element.target_image_id())
+        {
+            image.texture_descriptor.mip_level_count = mip_level_count;
+            if image.texture_descriptor.format != format {
+                // Existing pixel data is laid out for the old format, frames replace it anyway
+                image.data = None;
+                image.texture_descriptor.format = format;
+            }
+            image.sampler = settings.sampler.clone();
+        }
     }
-    if let Some(image) = images.get_mut(video_element.target_image_id()) {
-        if image.size() != UVec2::new(width, height) {
+}
+
+/// Target images sized to fit their video frames and the device's limits
+#[derive(SystemParam)]
+struct TargetImages<'w, 's> {
+    images: ResMut<'w, Assets<Image>>,
+    render_device: Option<Res<'w, RenderDevice>>,
+    commands: Commands<'w, 's>,
+}
+
+impl TargetImages<'_, '_> {
+    fn resize(
+        &mut self,
+        asset_id: AssetId<VideoElement>,
+        video_element: &VideoElement,
+        video_size: UVec2,
+    ) {
+        let settings = video_element.texture_settings();
+        // A target region means the image is shared and sized by its owner
+        if video_size.cmpeq(UVec2::ZERO).any() || settings.target_rect.is_some() {
+            return;
+        }
+        let max_dimension = max_dimension(self.render_device.as_deref());
+        let size = settings.frame_size(video_size, max_dimension);
+        let UVec2 {
+            x: width,
+            y: height,
+        } = size;
+        if width == 0 || height == 0 {
+            return;
+        }
+        if let Some(image) = self.images.get(video_element.target_image_id())
+            && image.size() == size
+        {
+            return;
+        }
+
+        let requested_size = settings.frame_size(video_size, u32::MAX);
+        if requested_size != size {
+            warn!(
+                "Video asset {asset_id:?} is {requested_size}, larger than the max texture size \
+                 {max_dimension}, downscaling to {size}"
+            );
+            self.commands.trigger(VideoTextureDownscaled {
+                asset_id,
+                requested_size,
+                size,
+                max_dimension,
+            });
+        }
+
+        if let Some(image) = self.images.get_mut(video_element.target_image_id()) {
             image.texture_descriptor.size = Extent3d {
                 width,
                 height,
                 depth_or_array_layers: 1,
             };
+            image.texture_descriptor.mip_level_count = settings.mip_level_count(width, height);
+        } else {
+            self.images
+                .insert(
+                    video_element.target_image_id(),
+                    new_target_image(width, height, settings),
+                )
+                .expect("insert image");
         }
-        return;
     }
+}
 
+pub(crate) fn new_target_image(width: u32, height: u32, settings: &VideoTextureSettings) -> Image {
     let mut image = Image::new_uninit(
         Extent3d {
             width,
@@ -125,81 +363,84 @@ fn resize_image(
             depth_or_array_layers: 1,
         },
         TextureDimension::D2,
-        TextureFormat::Rgba8Unorm,
+        settings.format.into(),
         RenderAssetUsages::default(),
     );
     image.texture_descriptor.usage |= TextureUsages::RENDER_ATTACHMENT;
-    images
-        .insert(video_element.target_image_id(), image)
-        .expect("insert image");
+    image.texture_descriptor.mip_level_count = settings.mip_level_count(width, height);
+    image.sampler = settings.sampler.clone();
+    image
 }
 
 fn on_loadedmetadata(
     listener_event: On<ListenerAssetEvent<events::LoadedMetadata>>,
     video_elements: Res<Assets<VideoElement>>,
-    mut images: ResMut<Assets<Image>>,
+    mut target_images: TargetImages,
     registry: NonSend<VideoElementRegistry>,
 ) {
     let asset_id = listener_event.asset_id();
     if let Some(video_element) = video_elements.get(asset_id)
-        && let Some(element) = registry.element(asset_id)
+        && let Some(video_size) = registry.video_size(asset_id)
     {
-        resize_image(video_element, element, &mut images);
+        target_images.resize(asset_id, video_element, video_size);
     };
 }
 
 fn on_canplay(
     listener_event: On<ListenerAssetEvent<events::CanPlay>>,
     video_elements: Res<Assets<VideoElement>>,
-    mut images: ResMut<Assets<Image>>,
+    mut target_images: TargetImages,
     registry: NonSend<VideoElementRegistry>,
 ) {
     let asset_id = listener_event.asset_id();
     if let Some(video_element) = video_elements.get(asset_id)
-        && let Some(element) = registry.element(asset_id)
+        && let Some(video_size) = registry.video_size(asset_id)
     {
-        resize_image(video_element, element, &mut images);
+        target_images.resize(asset_id, video_element, video_size);
     };
 }
 
 fn on_resize(
     listener_event: On<ListenerAssetEvent<events::Resize>>,
     video_elements: Res<Assets<VideoElement>>,
-    mut images: ResMut<Assets<Image>>,
+    mut target_images: TargetImages,
     registry: NonSend<VideoElementRegistry>,
 ) {
     let asset_id = listener_event.asset_id();
     if let Some(video_element) = video_elements.get(asset_id)
-        && let Some(element) = registry.element(asset_id)
+        && let Some(video_size) = registry.video_size(asset_id)
     {
-        resize_image(video_element, element, &mut images);
+        target_images.resize(asset_id, video_element, video_size);
     };
 }
 
-fn on_error(
-    listener_event: On<ListenerAssetEvent<events::Error>>,
-    video_elements: Res<Assets<VideoElement>>,
-) {
-    let asset_id = listener_event.asset_id();
-    if video_elements.get(asset_id).is_none() {
-        warn!("Video asset {:?} failed to load with error", asset_id);
-    };
+fn on_error(listener_event: On<ListenerAssetEvent<events::Error>>) {
+    warn!(
+        "Video asset {:?} failed with error: {}",
+        listener_event.asset_id(),
+        listener_event.payload()
+    );
 }
 
-fn on_playing(
-    listener_event: On<ListenerAssetEvent<events::Playing>>,
+fn on_loadeddata(
+    listener_event: On<ListenerAssetEvent<events::LoadedData>>,
     mut video_elements: ResMut<Assets<VideoElement>>,
+    mut target_images: TargetImages,
+    registry: NonSend<VideoElementRegistry>,
 ) {
-    if let Some(video_element) = video_elements.get_mut(listener_event.asset_id()) {
-        video_element.renderable = true;
+    let asset_id = listener_event.asset_id();
+    // Upload the first frame, a paused video may not present any other
+    if let Some(video_element) = video_elements.get_mut(asset_id)
+        && let Some(video_size) = registry.video_size(asset_id)
+    {
+        target_images.resize(asset_id, video_element, video_size);
     };
 }
 
-fn on_ended(
-    listener_event: On<ListenerAssetEvent<events::Ended>>,
+fn on_seeked(
+    listener_event: On<ListenerAssetEvent<events::Seeked>>,
     mut video_elements: ResMut<Assets<VideoElement>>,
 ) {
-    if let Some(video_element) = video_elements.get_mut(listener_event.asset_id()) {
-        video_element.renderable = false;
-    };
+    // Upload the frame at the new position, even if paused
+    video_elements.get_mut(listener_event.asset_id());
 }
@@ -0,0 +1,90 @@
+use crate::VideoElement;
+use bevy::prelude::*;
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(extends = web_sys::HtmlVideoElement)]
+    #[derive(Debug, Clone)]
+    type FrameCallbackElement;
+
+    #[wasm_bindgen(method, js_name = requestVideoFrameCallback)]
+    fn request_video_frame_callback(
+        this: &FrameCallbackElement,
+        callback: &js_sys::Function,
+    ) -> u32;
+
+    #[wasm_bindgen(method, js_name = cancelVideoFrameCallback)]
+    fn cancel_video_frame_callback(this: &FrameCallbackElement, handle: u32);
+}
+
+type FrameClosure = Closure<dyn FnMut(JsValue, JsValue)>;
+
+/// Notifies when the element presents a new frame, using `requestVideoFrameCallback`
+pub(crate) struct FrameCallback {
+    element: FrameCallbackElement,
+    handle: Rc<Cell<u32>>,
+    // Dropping the closure stops the callback chain
+    _closure: Rc<RefCell<Option<FrameClosure>>>,
+}
+
+impl FrameCallback {
+    pub(crate) fn is_supported(element: &web_sys::HtmlVideoElement) -> bool {
+        js_sys::Reflect::has(element, &JsValue::from_str("requestVideoFrameCallback"))
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn new(
+        element: &web_sys::HtmlVideoElement,
+        asset_id: AssetId<VideoElement>,
+        tx: crossbeam_channel::Sender<AssetId<VideoElement>>,
+    ) -> Self {
+        let element: FrameCallbackElement = element.clone().unchecked_into();
+        let handle = Rc::new(Cell::new(0));
+        let closure: Rc<RefCell<Option<FrameClosure>>> = Rc::new(RefCell::new(None));
+
+        let callback_element = element.clone();
+        let callback_handle = handle.clone();
+        let callback_closure = Rc::downgrade(&closure);
+        *closure.borrow_mut() = Some(Closure::new(move |_now: JsValue, _metadata: JsValue| {
+            if let Err(err) = tx.send(asset_id) {
+                warn!("Failed to send video frame: {err:?}");
+            }
+            // Callbacks are one-shot, request the next frame
+            if let Some(closure) = callback_closure.upgrade()
+                && let Some(closure) = closure.borrow().as_ref()
+            {
+                callback_handle.set(
+                    callback_element.request_video_frame_callback(closure.as_ref().unchecked_ref()),
+                );
+            }
+        }));
+        if let Some(callback) = closure.borrow().as_ref() {
+            handle.set(element.request_video_frame_callback(callback.as_ref().unchecked_ref()));
+        }
+
+        Self {
+            element,
+            handle,
+            _closure: closure,
+        }
+    }
+}
+
+impl Drop for FrameCallback {
+    fn drop(&mut self) {
+        self.element.cancel_video_frame_callback(self.handle.get());
+    }
+}
+
+impl std::fmt::Debug for FrameCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FrameCallback")
+            .field("handle", &self.handle.get())
+            .finish()
+    }
+}
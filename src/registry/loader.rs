@@ -0,0 +1,245 @@
+use crate::{
+    AnimatedImageFormat, EncodedImage, VideoElement, VideoError, VideoTextureSettings,
+    WebVideoError,
+    fetch::fetch_bytes,
+    registry::asset::{PendingMedia, new_target_image},
+};
+use bevy::{
+    asset::{
+        AssetLoader, LoadContext,
+        io::{
+            AssetReader, AssetReaderError, AssetSourceBuilder, AssetSourceId, PathStream, Reader,
+            VecReader,
+        },
+    },
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Label of the target [`Image`] sub-asset of a loaded [`VideoElement`],
+/// e.g. `asset_server.load("video://clips/intro.mp4#image")`.
+///
+/// Labeled paths pick their loader by extension, so animated images, whose extensions are
+/// left to `bevy_image`, have no such path. Use [`VideoElement::target_image`] or the
+/// [`WebVideoImage`](crate::WebVideoImage) of their entity.
+pub const VIDEO_IMAGE_LABEL: &str = "image";
+
+/// Name of the asset source registered by [`VideoAssetSourcePlugin`],
+/// e.g. `asset_server.load("video://clips/intro.mp4")`
+pub const VIDEO_ASSET_SOURCE: &str = "video";
+
+pub fn plugin(app: &mut App) {
+    let asset_root = app
+        .get_added_plugins::<AssetPlugin>()
+        .first()
+        .map(|asset_plugin| asset_plugin.file_path.clone())
+        .unwrap_or_else(|| AssetPlugin::default().file_path);
+    app.register_asset_loader(VideoElementLoader { asset_root });
+}
+
+/// Registers the [`VIDEO_ASSET_SOURCE`] asset source, which resolves paths like the default
+/// source but reads no bytes. Paths may also be absolute URLs,
+/// e.g. `video://https://example.com/intro.mp4`.
+///
+/// Videos should always be loaded from this source: the default source fetches the whole
+/// file before the loader runs, so a video played by its element would be downloaded twice,
+/// once by the asset reader and once by the element. From `video://` the element fetches it,
+/// and [`VideoBackend::WebCodecs`] videos and animated images are fetched once by the loader.
+///
+/// Asset sources are built by [`AssetPlugin`], so this must be added before `DefaultPlugins`.
+pub struct VideoAssetSourcePlugin;
+
+impl Plugin for VideoAssetSourcePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_asset_source(
+            VIDEO_ASSET_SOURCE,
+            AssetSourceBuilder::default().with_reader(|| Box::new(UrlOnlyReader)),
+        );
+    }
+}
+
+/// Reads every asset as empty, the loader only needs its path
+struct UrlOnlyReader;
+
+impl AssetReader for UrlOnlyReader {
+    async fn read<'a>(&'a self, _path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        Ok(VecReader::new(Vec::new()))
+    }
+
+    async fn read_meta<'a>(&'a self, path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        Err::<VecReader, _>(AssetReaderError::NotFound(path.to_path_buf()))
+    }
+
+    async fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> Result<Box<PathStream>, AssetReaderError> {
+        Err(AssetReaderError::NotFound(path.to_path_buf()))
+    }
+
+    async fn is_directory<'a>(&'a self, _path: &'a Path) -> Result<bool, AssetReaderError> {
+        Ok(false)
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VideoPreload {
+    None,
+    Metadata,
+    #[default]
+    Auto,
+}
+
+impl VideoPreload {
+    fn as_str(&self) -> &'static str {
+        match self {
+            VideoPreload::None => "none",
+            VideoPreload::Metadata => "metadata",
+            VideoPreload::Auto => "auto",
+        }
+    }
+}
+
+/// How a loaded video is decoded
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VideoBackend {
+    /// An `HTMLVideoElement` streams and decodes the video
+    #[default]
+    Element,
+    /// The asset bytes are demuxed and decoded with WebCodecs, presenting frames at their
+    /// exact timestamps, see [`CodecVideo`](crate::CodecVideo). MP4 and WebM only, without audio.
+    #[cfg(feature = "webcodecs")]
+    WebCodecs,
+}
+
+/// Initial element state for a loaded [`VideoElement`].
+///
+/// A [`VideoPlayback`](crate::VideoPlayback) component on the video entity takes precedence
+/// over `looping` and `muted`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct VideoLoaderSettings {
+    pub looping: bool,
+    pub muted: bool,
+    pub cross_origin: Option<String>,
+    pub preload: VideoPreload,
+    pub backend: VideoBackend,
+    /// Format, color handling and sampler of the target image
+    pub texture: VideoTextureSettings,
+}
+
+impl VideoLoaderSettings {
+    pub(crate) fn apply_to_element(&self, element: &web_sys::HtmlVideoElement) {
+        element.set_cross_origin(self.cross_origin.as_deref());
+        element.set_preload(self.preload.as_str());
+        element.set_loop(self.looping);
+        element.set_muted(self.muted);
+    }
+}
+
+/// Loads a [`VideoElement`] whose element streams the asset from its URL.
+///
+/// The bytes are only used by [`VideoBackend::WebCodecs`] and for animated images, which are
+/// played by an [`AnimatedImage`](crate::AnimatedImage) whatever the backend. Otherwise the
+/// browser fetches the video itself, see [`VideoAssetSourcePlugin`].
+///
+/// GIF, APNG and WebP files are also images, their extensions are left to `bevy_image`.
+/// They load as videos when the asset type is given, e.g.
+/// `asset_server.load::<VideoElement>("video://spinner.gif")`.
+struct VideoElementLoader {
+    asset_root: String,
+}
+
+impl VideoElementLoader {
+    fn url(&self, load_context: &LoadContext) -> Result<String, WebVideoError> {
+        let path = load_context.path().to_string_lossy().replace('\\', "/");
+        match load_context.asset_path().source() {
+            AssetSourceId::Name(scheme) if matches!(&**scheme, "http" | "https") => {
+                Ok(format!("{scheme}://{path}"))
+            }
+            AssetSourceId::Name(name)
+                if &**name == VIDEO_ASSET_SOURCE
+                    && (path.starts_with("http://") || path.starts_with("https://")) =>
+            {
+                Ok(path)
+            }
+            // The video source resolves other paths like the default source
+            AssetSourceId::Name(name) if &**name != VIDEO_ASSET_SOURCE => {
+                Err(VideoError::Other(format!("Unsupported video asset source {name}")).into())
+            }
+            _ if self.asset_root.is_empty() => Ok(path),
+            _ => Ok(format!("{}/{path}", self.asset_root)),
+        }
+    }
+
+    /// Bytes of the asset, fetched here when the video source read none
+    async fn read_bytes(
+        &self,
+        reader: &mut dyn Reader,
+        load_context: &LoadContext<'_>,
+    ) -> Result<Vec<u8>, WebVideoError> {
+        if load_context.asset_path().source().as_str() == Some(VIDEO_ASSET_SOURCE) {
+            return Ok(fetch_bytes(&self.url(load_context)?).await?);
+        }
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(|err| VideoError::Other(err.to_string()))?;
+        Ok(bytes)
+    }
+}
+
+impl AssetLoader for VideoElementLoader {
+    type Asset = VideoElement;
+    type Settings = VideoLoaderSettings;
+    type Error = WebVideoError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let extension = load_context
+            .path()
+            .extension()
+            .map(|extension| extension.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let media = if let Some(format) = AnimatedImageFormat::from_extension(&extension) {
+            let bytes = self.read_bytes(reader, load_context).await?;
+            PendingMedia::AnimatedImage(EncodedImage::new(bytes, format)?)
+        } else if extension == "m3u8" {
+            PendingMedia::Hls(self.url(load_context)?)
+        } else if extension == "mpd" {
+            PendingMedia::Dash(self.url(load_context)?)
+        } else {
+            match settings.backend {
+                VideoBackend::Element => PendingMedia::Url(self.url(load_context)?),
+                #[cfg(feature = "webcodecs")]
+                VideoBackend::WebCodecs => {
+                    let container =
+                        crate::VideoContainer::from_extension(&extension).ok_or_else(|| {
+                            VideoError::Demux("WebCodecs videos must be MP4 or WebM".into())
+                        })?;
+                    let bytes = self.read_bytes(reader, load_context).await?;
+                    PendingMedia::Encoded(crate::EncodedVideo::demux(bytes, container)?)
+                }
+            }
+        };
+        // Placeholder, resized once the video metadata loads
+        let target_image = load_context.add_labeled_asset(
+            VIDEO_IMAGE_LABEL.into(),
+            new_target_image(1, 1, &settings.texture),
+        );
+        Ok(VideoElement::new_pending(
+            target_image,
+            media,
+            settings.clone(),
+        ))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["mp4", "m4v", "webm", "ogv", "mov", "m3u8", "mpd"]
+    }
+}
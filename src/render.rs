@@ -1,48 +1,371 @@
-use crate::{VideoElement, VideoElementRegistry};
+use crate::{
+    VideoElement, VideoElementRegistry, WebTextureSource,
+    texture::{VideoUploadMode, max_dimension},
+};
 use bevy::{
-    ecs::system::{SystemParamItem, lifetimeless::SRes},
-    platform::collections::HashMap,
+    ecs::system::{
+        SystemParamItem,
+        lifetimeless::{SRes, SResMut},
+    },
+    math::URect,
+    platform::collections::{HashMap, hash_map::Entry},
     prelude::*,
     render::{
-        Extract, RenderApp,
-        render_asset::{PrepareAssetError, RenderAsset, RenderAssetPlugin, RenderAssets},
-        renderer::RenderQueue,
+        Extract, Render, RenderApp, RenderSystems,
+        render_asset::{
+            PrepareAssetError, RenderAsset, RenderAssetPlugin, RenderAssets, prepare_assets,
+        },
+        render_resource::{Extent3d, TextureFormat},
+        renderer::{RenderAdapter, RenderAdapterInfo, RenderDevice, RenderQueue},
         texture::GpuImage,
     },
 };
+use canvas::FrameCanvas;
+use mipmap::{MipmapGenerator, generate_mipmaps};
+use std::{cell::RefCell, rc::Rc};
+use wasm_bindgen::prelude::*;
 use wgpu_types::{
-    CopyExternalImageDestInfo, CopyExternalImageSourceInfo, ExternalImageSource, Origin2d,
-    Origin3d, PredefinedColorSpace, TextureAspect,
+    Backend, CopyExternalImageDestInfo, CopyExternalImageSourceInfo, DownlevelFlags,
+    ExternalImageSource, Origin2d, Origin3d, PredefinedColorSpace, TexelCopyBufferLayout,
+    TexelCopyTextureInfo, TextureAspect,
 };
 
+mod canvas;
+mod mipmap;
+
 pub struct VideoRenderPlugin;
 
 impl Plugin for VideoRenderPlugin {
     fn build(&self, app: &mut App) {
         // Render videos after GpuImage is prepared
         app.add_plugins(RenderAssetPlugin::<RenderVideoElement, GpuImage>::default());
+        let (tx, rx) = crossbeam_channel::unbounded();
+        app.insert_resource(UploadedFrames(rx))
+            .add_message::<UploadedFrame>()
+            .add_systems(PreUpdate, read_uploaded_frames);
         if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
             render_app
+                .insert_resource(UploadedFrameSender(tx))
                 .add_systems(ExtractSchedule, extract_elements)
+                .add_systems(
+                    Render,
+                    generate_mipmaps
+                        .in_set(RenderSystems::PrepareAssets)
+                        .after(prepare_assets::<RenderVideoElement>),
+                )
                 .world_mut()
                 .init_non_send_resource::<RenderElements>();
+            render_app
+                .world_mut()
+                .init_non_send_resource::<VideoUploads>();
+        }
+    }
+
+    fn finish(&self, app: &mut App) {
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<MipmapGenerator>()
+                .init_resource::<VideoCopyCapabilities>();
+        }
+    }
+}
+
+/// A frame copied into a video's target image by the render world
+#[derive(Message, Clone, Debug)]
+pub(crate) struct UploadedFrame {
+    pub(crate) asset_id: AssetId<VideoElement>,
+    /// Playback position of the copied frame, the element's `currentTime`
+    pub(crate) time: f64,
+    /// The element was still seeking, so the frame is from before the seek
+    pub(crate) seeking: bool,
+    path: VideoUploadPath,
+}
+
+/// Frames uploaded by the render world, received in the main world
+#[derive(Resource, Deref)]
+struct UploadedFrames(crossbeam_channel::Receiver<UploadedFrame>);
+
+/// How frames of a video reach its target image
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VideoUploadPath {
+    /// `copyExternalImageToTexture` on WebGPU, `texSubImage2D` on WebGL2
+    ExternalCopy,
+    /// The frame is drawn to a canvas, read back and written with `write_texture`
+    CanvasReadback,
+    /// Frames can't be uploaded, e.g. a cross-origin source without CORS
+    Unavailable,
+}
+
+/// Fired when the [`VideoUploadPath`] of a video is first chosen or changes
+#[derive(Event, Clone, Debug)]
+pub struct VideoUploadPathChanged {
+    pub asset_id: AssetId<VideoElement>,
+    pub path: VideoUploadPath,
+}
+
+/// Forward uploads from the render world and report upload path changes
+fn read_uploaded_frames(
+    uploaded_frames: Res<UploadedFrames>,
+    video_elements: Res<Assets<VideoElement>>,
+    mut paths: Local<HashMap<AssetId<VideoElement>, VideoUploadPath>>,
+    mut messages: MessageWriter<UploadedFrame>,
+    mut commands: Commands,
+) {
+    paths.retain(|asset_id, _| video_elements.contains(*asset_id));
+    for uploaded in uploaded_frames.try_iter() {
+        if paths.insert(uploaded.asset_id, uploaded.path) != Some(uploaded.path) {
+            commands.trigger(VideoUploadPathChanged {
+                asset_id: uploaded.asset_id,
+                path: uploaded.path,
+            });
+        }
+        if uploaded.path != VideoUploadPath::Unavailable {
+            messages.write(uploaded);
         }
     }
 }
 
+#[derive(Resource, Deref)]
+struct UploadedFrameSender(crossbeam_channel::Sender<UploadedFrame>);
+
 #[derive(Default, Deref, DerefMut)]
-struct RenderElements(HashMap<AssetId<VideoElement>, web_sys::HtmlVideoElement>);
+struct RenderElements(HashMap<AssetId<VideoElement>, FrameSource>);
+
+/// Where the current frame of a video is copied from
+enum FrameSource {
+    Element(web_sys::HtmlVideoElement),
+    /// A [`WebTextureSource`] or the shown frame of a [`MediaPlayer`](crate::MediaPlayer)
+    Texture {
+        source: WebTextureSource,
+        time: f64,
+        seeking: bool,
+    },
+}
+
+impl FrameSource {
+    fn size(&self) -> UVec2 {
+        match self {
+            FrameSource::Element(element) => {
+                UVec2::new(element.video_width(), element.video_height())
+            }
+            FrameSource::Texture { source, .. } => source.size(),
+        }
+    }
+
+    /// Playback position of the frame in seconds
+    fn time(&self) -> f64 {
+        match self {
+            FrameSource::Element(element) => element.current_time(),
+            FrameSource::Texture { time, .. } => *time,
+        }
+    }
+
+    fn seeking(&self) -> bool {
+        match self {
+            FrameSource::Element(element) => element.seeking(),
+            FrameSource::Texture { seeking, .. } => *seeking,
+        }
+    }
+
+    /// URL of a video or image, whether frames can be read back depends on its origin
+    fn src(&self) -> Option<String> {
+        match self {
+            FrameSource::Element(element) => Some(element.current_src()),
+            FrameSource::Texture {
+                source: WebTextureSource::Image(image),
+                ..
+            } => Some(image.current_src()),
+            FrameSource::Texture { .. } => None,
+        }
+    }
+
+    fn into_external_image(self) -> ExternalImageSource {
+        match self {
+            FrameSource::Element(element) => ExternalImageSource::HTMLVideoElement(element),
+            FrameSource::Texture { source, .. } => source.into_external_image(),
+        }
+    }
+}
+
+#[derive(Resource)]
+struct VideoCopyCapabilities {
+    /// Without this WebGL2 only copies whole frames and into sRGB
+    unrestricted: bool,
+    /// Larger frames are downscaled through a canvas
+    max_dimension: u32,
+    /// WebGPU implementations may lack `copyExternalImageToTexture`
+    external_copy: bool,
+}
+
+impl FromWorld for VideoCopyCapabilities {
+    fn from_world(world: &mut World) -> Self {
+        let flags = world
+            .resource::<RenderAdapter>()
+            .get_downlevel_capabilities()
+            .flags;
+        Self {
+            unrestricted: flags.contains(DownlevelFlags::UNRESTRICTED_EXTERNAL_TEXTURE_COPIES),
+            max_dimension: max_dimension(world.get_resource::<RenderDevice>()),
+            external_copy: world.resource::<RenderAdapterInfo>().backend != Backend::BrowserWebGpu
+                || has_copy_external_image(),
+        }
+    }
+}
+
+fn has_copy_external_image() -> bool {
+    js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("GPUQueue"))
+        .ok()
+        .filter(|gpu_queue| !gpu_queue.is_undefined())
+        .and_then(|gpu_queue| {
+            js_sys::Reflect::get(&gpu_queue, &JsValue::from_str("prototype")).ok()
+        })
+        .and_then(|prototype| {
+            js_sys::Reflect::has(&prototype, &JsValue::from_str("copyExternalImageToTexture")).ok()
+        })
+        .unwrap_or(false)
+}
+
+/// Per video state of the render world
+struct VideoUpload {
+    mode: VideoUploadMode,
+    /// [`FrameSource::src`] the path was chosen for
+    src: Option<String>,
+    path: VideoUploadPath,
+    canvas: Option<FrameCanvas>,
+    copy_check: CopyCheck,
+}
+
+/// Error scope around the first external copy of [`VideoUploadMode::Auto`], which falls back
+/// to canvas readback if the copy fails
+enum CopyCheck {
+    Pending,
+    Running(Rc<RefCell<Option<Option<wgpu::Error>>>>),
+    Done,
+}
+
+impl VideoUpload {
+    fn new(
+        mode: VideoUploadMode,
+        source: &FrameSource,
+        format: TextureFormat,
+        copy_capabilities: &VideoCopyCapabilities,
+    ) -> Self {
+        let readback = canvas_readback_supported(format);
+        let path = match mode {
+            VideoUploadMode::ExternalCopy => VideoUploadPath::ExternalCopy,
+            VideoUploadMode::CanvasReadback if readback => VideoUploadPath::CanvasReadback,
+            VideoUploadMode::CanvasReadback => VideoUploadPath::Unavailable,
+            // A cross-origin source without CORS can't be copied or read back
+            VideoUploadMode::Auto if !FrameCanvas::is_readable(source) => {
+                VideoUploadPath::Unavailable
+            }
+            VideoUploadMode::Auto if copy_capabilities.external_copy => {
+                VideoUploadPath::ExternalCopy
+            }
+            VideoUploadMode::Auto if readback => VideoUploadPath::CanvasReadback,
+            VideoUploadMode::Auto => VideoUploadPath::Unavailable,
+        };
+        let copy_check = if mode == VideoUploadMode::Auto && path == VideoUploadPath::ExternalCopy {
+            CopyCheck::Pending
+        } else {
+            CopyCheck::Done
+        };
+        Self {
+            mode,
+            src: source.src(),
+            path,
+            canvas: None,
+            copy_check,
+        }
+    }
+
+    /// Switch to canvas readback once the checked copy failed, returning its error
+    fn check_copy(&mut self, format: TextureFormat) -> Option<wgpu::Error> {
+        let CopyCheck::Running(result) = &self.copy_check else {
+            return None;
+        };
+        let error = result.borrow_mut().take()?;
+        self.copy_check = CopyCheck::Done;
+        let error = error?;
+        self.path = if canvas_readback_supported(format) {
+            VideoUploadPath::CanvasReadback
+        } else {
+            VideoUploadPath::Unavailable
+        };
+        Some(error)
+    }
+
+    fn canvas(&mut self) -> Result<&FrameCanvas, JsValue> {
+        if self.canvas.is_none() {
+            self.canvas = Some(FrameCanvas::new()?);
+        }
+        Ok(self.canvas.as_ref().expect("canvas"))
+    }
+}
+
+/// Part of `source` that ends up in a `target_size` region when the frame is scaled to
+/// `frame_size` and cut off at the target's bottom right
+fn visible_source(source: URect, frame_size: UVec2, target_size: UVec2) -> URect {
+    if target_size == frame_size {
+        return source;
+    }
+    let size = (source.size().as_u64vec2() * target_size.as_u64vec2()
+        / frame_size.max(UVec2::ONE).as_u64vec2())
+    .as_uvec2()
+    .max(UVec2::ONE);
+    URect::from_corners(source.min, source.min + size.min(source.size()))
+}
+
+/// Canvas readback produces 8 bit RGBA pixels, which are swizzled for BGRA formats
+fn canvas_readback_supported(format: TextureFormat) -> bool {
+    matches!(
+        format,
+        TextureFormat::Rgba8Unorm
+            | TextureFormat::Rgba8UnormSrgb
+            | TextureFormat::Bgra8Unorm
+            | TextureFormat::Bgra8UnormSrgb
+    )
+}
+
+#[derive(Default, Deref, DerefMut)]
+struct VideoUploads(HashMap<AssetId<VideoElement>, VideoUpload>);
 
 fn extract_elements(
     registry: Extract<NonSend<VideoElementRegistry>>,
     video_elements: Extract<Res<Assets<VideoElement>>>,
     mut render_elements: NonSendMut<RenderElements>,
+    mut video_uploads: NonSendMut<VideoUploads>,
 ) {
-    for (asset_id, video_element) in video_elements.iter() {
-        if video_element.is_renderable()
-            && let Some(element) = registry.element(asset_id)
+    video_uploads.retain(|asset_id, _| video_elements.contains(*asset_id));
+    for (asset_id, _) in video_elements.iter() {
+        if let Some(element) = registry.element(asset_id)
+            && element.ready_state() >= web_sys::HtmlMediaElement::HAVE_CURRENT_DATA
         {
-            render_elements.insert(asset_id, element.clone());
+            render_elements.insert(asset_id, FrameSource::Element(element.clone()));
+        }
+        if let Some(source) = registry.texture_source(asset_id)
+            && source.size() != UVec2::ZERO
+        {
+            render_elements.insert(
+                asset_id,
+                FrameSource::Texture {
+                    source: source.clone(),
+                    time: 0.0,
+                    seeking: false,
+                },
+            );
+        }
+        if let Some(player) = registry.player(asset_id)
+            && let Some(source) = player.frame()
+        {
+            render_elements.insert(
+                asset_id,
+                FrameSource::Texture {
+                    source,
+                    time: player.current_time(),
+                    seeking: player.seeking(),
+                },
+            );
         }
     }
 }
@@ -52,36 +375,227 @@ struct RenderVideoElement;
 impl RenderAsset for RenderVideoElement {
     type SourceAsset = VideoElement;
     type Param = (
+        SRes<RenderDevice>,
         SRes<RenderQueue>,
+        SResMut<MipmapGenerator>,
+        SRes<VideoCopyCapabilities>,
         SRes<RenderAssets<GpuImage>>,
+        SRes<UploadedFrameSender>,
         NonSendMut<'static, RenderElements>,
+        NonSendMut<'static, VideoUploads>,
     );
 
     fn prepare_asset(
         video_element: Self::SourceAsset,
         asset_id: AssetId<Self::SourceAsset>,
-        (render_queue, gpu_images, render_elements): &mut SystemParamItem<Self::Param>,
+        (
+            render_device,
+            render_queue,
+            mipmap_generator,
+            copy_capabilities,
+            gpu_images,
+            uploaded_frame_sender,
+            render_elements,
+            video_uploads,
+        ): &mut SystemParamItem<Self::Param>,
         _previous_asset: Option<&Self>,
     ) -> Result<Self, PrepareAssetError<Self::SourceAsset>> {
         if let Some(gpu_image) = gpu_images.get(video_element.target_image_id())
-            && let Some(element) = render_elements.remove(&asset_id)
+            && let Some(frame_source) = render_elements.remove(&asset_id)
         {
-            render_queue.copy_external_image_to_texture(
-                &CopyExternalImageSourceInfo {
-                    source: ExternalImageSource::HTMLVideoElement(element),
-                    origin: Origin2d::ZERO,
-                    flip_y: false,
-                },
-                CopyExternalImageDestInfo {
-                    texture: &gpu_image.texture,
-                    mip_level: 0,
-                    origin: Origin3d::ZERO,
-                    aspect: TextureAspect::All,
-                    color_space: PredefinedColorSpace::Srgb,
-                    premultiplied_alpha: true,
-                },
-                gpu_image.size,
+            let texture_settings = video_element.texture_settings();
+            let upload = match video_uploads.entry(asset_id) {
+                // A new source may have a different origin
+                Entry::Occupied(entry)
+                    if entry.get().mode == texture_settings.upload_mode
+                        && entry.get().src == frame_source.src() =>
+                {
+                    entry.into_mut()
+                }
+                entry => {
+                    let upload = VideoUpload::new(
+                        texture_settings.upload_mode,
+                        &frame_source,
+                        gpu_image.texture_format,
+                        copy_capabilities,
+                    );
+                    if upload.path == VideoUploadPath::Unavailable {
+                        warn!(
+                            "Video asset {asset_id:?} can't be uploaded, the source may be \
+                             cross-origin without CORS or the texture format unsupported"
+                        );
+                    }
+                    match entry {
+                        Entry::Occupied(mut entry) => {
+                            entry.insert(upload);
+                            entry.into_mut()
+                        }
+                        Entry::Vacant(entry) => entry.insert(upload),
+                    }
+                }
+            };
+            if let Some(err) = upload.check_copy(gpu_image.texture_format) {
+                warn!(
+                    "Failed to copy frames of video asset {asset_id:?}, switching to {:?}: {err}",
+                    upload.path
+                );
+            }
+            let uploaded = UploadedFrame {
+                asset_id,
+                time: frame_source.time(),
+                seeking: frame_source.seeking(),
+                path: upload.path,
+            };
+            let video_size = frame_source.size();
+            let max_dimension = copy_capabilities.max_dimension;
+            let frame_size = texture_settings.frame_size(video_size, max_dimension);
+            let (source, target) = texture_settings.copy_rects(
+                video_size,
+                UVec2::new(gpu_image.size.width, gpu_image.size.height),
+                max_dimension,
             );
+            if target.is_empty() {
+                return Ok(RenderVideoElement);
+            }
+            let unrestricted = copy_capabilities.unrestricted;
+            match upload.path {
+                VideoUploadPath::Unavailable => {
+                    // Report the path so users learn why the image stays blank
+                    if let Err(err) = uploaded_frame_sender.send(uploaded) {
+                        warn!("Failed to report uploaded video frame: {err:?}");
+                    }
+                    return Ok(RenderVideoElement);
+                }
+                VideoUploadPath::ExternalCopy => {
+                    // WebGL2 only copies whole frames, a crop or a target smaller than the
+                    // frame goes through the canvas
+                    let (source, origin) = if frame_size != source.size()
+                        || (!unrestricted
+                            && (source.min != UVec2::ZERO
+                                || source.max != video_size
+                                || target.size() != frame_size
+                                || matches!(&frame_source, FrameSource::Texture { source, .. } if source.needs_unrestricted_copies())))
+                    {
+                        let frame_canvas = match upload.canvas() {
+                            Ok(frame_canvas) => frame_canvas,
+                            Err(err) => {
+                                warn!("Failed to create video canvas: {err:?}");
+                                return Ok(RenderVideoElement);
+                            }
+                        };
+                        let visible = visible_source(source, frame_size, target.size());
+                        if let Err(err) = frame_canvas.draw(&frame_source, visible, target.size()) {
+                            warn!("Failed to draw video frame: {err:?}");
+                            return Ok(RenderVideoElement);
+                        }
+                        (
+                            ExternalImageSource::HTMLCanvasElement(frame_canvas.canvas().clone()),
+                            Origin2d::ZERO,
+                        )
+                    } else {
+                        (
+                            frame_source.into_external_image(),
+                            Origin2d {
+                                x: source.min.x,
+                                y: source.min.y,
+                            },
+                        )
+                    };
+                    let checked = matches!(upload.copy_check, CopyCheck::Pending);
+                    if checked {
+                        render_device
+                            .wgpu_device()
+                            .push_error_scope(wgpu::ErrorFilter::Validation);
+                    }
+                    render_queue.copy_external_image_to_texture(
+                        &CopyExternalImageSourceInfo {
+                            source,
+                            origin,
+                            flip_y: texture_settings.flip_y,
+                        },
+                        CopyExternalImageDestInfo {
+                            texture: &gpu_image.texture,
+                            mip_level: 0,
+                            origin: Origin3d {
+                                x: target.min.x,
+                                y: target.min.y,
+                                z: 0,
+                            },
+                            aspect: TextureAspect::All,
+                            color_space: if unrestricted {
+                                texture_settings.color_space.into()
+                            } else {
+                                PredefinedColorSpace::Srgb
+                            },
+                            premultiplied_alpha: texture_settings.premultiplied_alpha,
+                        },
+                        Extent3d {
+                            width: target.width(),
+                            height: target.height(),
+                            depth_or_array_layers: 1,
+                        },
+                    );
+                    if checked {
+                        let result = Rc::new(RefCell::new(None));
+                        let task_result = result.clone();
+                        let error = render_device.wgpu_device().pop_error_scope();
+                        wasm_bindgen_futures::spawn_local(async move {
+                            *task_result.borrow_mut() = Some(error.await);
+                        });
+                        upload.copy_check = CopyCheck::Running(result);
+                    }
+                }
+                VideoUploadPath::CanvasReadback => {
+                    let visible = visible_source(source, frame_size, target.size());
+                    let pixels = upload.canvas().and_then(|frame_canvas| {
+                        frame_canvas.draw(&frame_source, visible, target.size())?;
+                        frame_canvas.read_pixels(
+                            texture_settings.flip_y,
+                            texture_settings.premultiplied_alpha,
+                            matches!(
+                                gpu_image.texture_format,
+                                TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+                            ),
+                        )
+                    });
+                    let pixels = match pixels {
+                        Ok(pixels) => pixels,
+                        Err(err) => {
+                            warn!("Failed to read back video frame: {err:?}");
+                            return Ok(RenderVideoElement);
+                        }
+                    };
+                    render_queue.write_texture(
+                        TexelCopyTextureInfo {
+                            texture: &gpu_image.texture,
+                            mip_level: 0,
+                            origin: Origin3d {
+                                x: target.min.x,
+                                y: target.min.y,
+                                z: 0,
+                            },
+                            aspect: TextureAspect::All,
+                        },
+                        &pixels,
+                        TexelCopyBufferLayout {
+                            offset: 0,
+                            bytes_per_row: Some(target.width() * 4),
+                            rows_per_image: None,
+                        },
+                        Extent3d {
+                            width: target.width(),
+                            height: target.height(),
+                            depth_or_array_layers: 1,
+                        },
+                    );
+                }
+            }
+            if texture_settings.generate_mipmaps {
+                mipmap_generator.queue(video_element.target_image_id());
+            }
+            if let Err(err) = uploaded_frame_sender.send(uploaded) {
+                warn!("Failed to report uploaded video frame: {err:?}");
+            }
             // Marker asset, we already did the work above
             Ok(RenderVideoElement)
         } else {
@@ -0,0 +1,143 @@
+use super::FrameSource;
+use crate::WebTextureSource;
+use bevy::{math::URect, prelude::*};
+use wasm_bindgen::prelude::*;
+
+/// Canvas a video frame is drawn to when it can't be copied to the texture directly,
+/// i.e. to scale it or to crop it on WebGL2.
+pub(super) struct FrameCanvas {
+    canvas: web_sys::HtmlCanvasElement,
+    context: web_sys::CanvasRenderingContext2d,
+}
+
+impl FrameCanvas {
+    pub(super) fn new() -> Result<Self, JsValue> {
+        let canvas = web_sys::window()
+            .and_then(|window| window.document())
+            .ok_or_else(|| JsValue::from_str("no document"))?
+            .create_element("canvas")?
+            .dyn_into::<web_sys::HtmlCanvasElement>()?;
+        let context = canvas
+            .get_context("2d")?
+            .ok_or_else(|| JsValue::from_str("no 2d context"))?
+            .dyn_into::<web_sys::CanvasRenderingContext2d>()?;
+        Ok(Self { canvas, context })
+    }
+
+    /// Whether frames of `source` can be read, i.e. it is not a cross-origin source without CORS
+    pub(super) fn is_readable(source: &FrameSource) -> bool {
+        let probe = |frame_canvas: Self| {
+            frame_canvas.draw(source, URect::new(0, 0, 1, 1), UVec2::ONE)?;
+            frame_canvas.context.get_image_data(0.0, 0.0, 1.0, 1.0)
+        };
+        Self::new().and_then(probe).is_ok()
+    }
+
+    pub(super) fn canvas(&self) -> &web_sys::HtmlCanvasElement {
+        &self.canvas
+    }
+
+    /// Draw the `source` region of the current frame scaled to `size`
+    pub(super) fn draw(
+        &self,
+        frame_source: &FrameSource,
+        source: URect,
+        size: UVec2,
+    ) -> Result<(), JsValue> {
+        // Resizing clears the canvas, only do it when needed
+        if self.canvas.width() != size.x {
+            self.canvas.set_width(size.x);
+        }
+        if self.canvas.height() != size.y {
+            self.canvas.set_height(size.y);
+        }
+        let (sx, sy, sw, sh) = (
+            source.min.x as f64,
+            source.min.y as f64,
+            source.width() as f64,
+            source.height() as f64,
+        );
+        let (dw, dh) = (size.x as f64, size.y as f64);
+        match frame_source {
+            FrameSource::Element(element) => self
+                .context
+                .draw_image_with_html_video_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    element, sx, sy, sw, sh, 0.0, 0.0, dw, dh,
+                ),
+            FrameSource::Texture {
+                source: WebTextureSource::Canvas(canvas),
+                ..
+            } => self
+                .context
+                .draw_image_with_html_canvas_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    canvas, sx, sy, sw, sh, 0.0, 0.0, dw, dh,
+                ),
+            FrameSource::Texture {
+                source: WebTextureSource::OffscreenCanvas(canvas),
+                ..
+            } => self
+                .context
+                .draw_image_with_offscreen_canvas_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    canvas, sx, sy, sw, sh, 0.0, 0.0, dw, dh,
+                ),
+            FrameSource::Texture {
+                source: WebTextureSource::ImageBitmap(bitmap),
+                ..
+            } => self
+                .context
+                .draw_image_with_image_bitmap_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    bitmap, sx, sy, sw, sh, 0.0, 0.0, dw, dh,
+                ),
+            FrameSource::Texture {
+                source: WebTextureSource::Image(image),
+                ..
+            } => self
+                .context
+                .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    image, sx, sy, sw, sh, 0.0, 0.0, dw, dh,
+                ),
+            #[cfg(feature = "webcodecs")]
+            FrameSource::Texture {
+                source: WebTextureSource::VideoFrame(frame),
+                ..
+            } => self
+                .context
+                .draw_image_with_video_frame_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    frame, sx, sy, sw, sh, 0.0, 0.0, dw, dh,
+                ),
+        }
+    }
+
+    /// Read back the last drawn frame as tightly packed 8 bit RGBA, or BGRA if `bgra`
+    pub(super) fn read_pixels(
+        &self,
+        flip_y: bool,
+        premultiplied_alpha: bool,
+        bgra: bool,
+    ) -> Result<Vec<u8>, JsValue> {
+        let (width, height) = (self.canvas.width(), self.canvas.height());
+        let image_data = self
+            .context
+            .get_image_data(0.0, 0.0, width as f64, height as f64)?;
+        // Canvas pixels are straight alpha
+        let mut pixels = image_data.data().0;
+        if flip_y {
+            let row = width as usize * 4;
+            pixels = pixels.chunks_exact(row).rev().flatten().copied().collect();
+        }
+        if premultiplied_alpha || bgra {
+            for pixel in pixels.as_chunks_mut::<4>().0 {
+                if premultiplied_alpha {
+                    let alpha = pixel[3] as u16;
+                    for channel in &mut pixel[..3] {
+                        *channel = ((*channel as u16 * alpha + 127) / 255) as u8;
+                    }
+                }
+                if bgra {
+                    pixel.swap(0, 2);
+                }
+            }
+        }
+        Ok(pixels)
+    }
+}
@@ -0,0 +1,205 @@
+use bevy::{
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_resource::{
+            BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, BindingResource, BindingType,
+            ColorTargetState, ColorWrites, CommandEncoderDescriptor, FilterMode, LoadOp,
+            MultisampleState, Operations, PipelineCompilationOptions, PipelineLayout,
+            PipelineLayoutDescriptor, PrimitiveState, RawFragmentState,
+            RawRenderPipelineDescriptor, RawVertexState, RenderPassColorAttachment,
+            RenderPassDescriptor, RenderPipeline, Sampler, SamplerBindingType, SamplerDescriptor,
+            ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, StoreOp,
+            TextureFormat, TextureSampleType, TextureViewDescriptor, TextureViewDimension,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::GpuImage,
+    },
+};
+
+/// Regenerates the mip chain of video images after frames are copied to mip level 0.
+///
+/// Images are queued by each upload and regenerated once per frame by [`generate_mipmaps`],
+/// so an atlas shared by several videos gets a single pass. Each level is rendered from
+/// the previous one with a fullscreen triangle, which unlike a compute pass also works
+/// on WebGL2.
+#[derive(Resource)]
+pub(super) struct MipmapGenerator {
+    shader: ShaderModule,
+    layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    sampler: Sampler,
+    // Created on first use, one per target format
+    pipelines: HashMap<TextureFormat, RenderPipeline>,
+    // Written this frame
+    queued: HashSet<AssetId<Image>>,
+}
+
+impl FromWorld for MipmapGenerator {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let shader = render_device.create_and_validate_shader_module(ShaderModuleDescriptor {
+            label: Some("video_mipmap_shader"),
+            source: ShaderSource::Wgsl(include_str!("mipmap.wgsl").into()),
+        });
+        let layout = render_device.create_bind_group_layout(
+            "video_mipmap_layout",
+            &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        );
+        let pipeline_layout = render_device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("video_mipmap_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("video_mipmap_sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+        Self {
+            shader,
+            layout,
+            pipeline_layout,
+            sampler,
+            pipelines: HashMap::default(),
+            queued: HashSet::default(),
+        }
+    }
+}
+
+impl MipmapGenerator {
+    /// Regenerate the mips of `image` after this frame's uploads
+    pub(super) fn queue(&mut self, image: AssetId<Image>) {
+        self.queued.insert(image);
+    }
+
+    fn pipeline(&mut self, render_device: &RenderDevice, format: TextureFormat) -> &RenderPipeline {
+        self.pipelines.entry(format).or_insert_with(|| {
+            render_device.create_render_pipeline(&RawRenderPipelineDescriptor {
+                label: Some("video_mipmap_pipeline"),
+                layout: Some(&self.pipeline_layout),
+                vertex: RawVertexState {
+                    module: &self.shader,
+                    entry_point: Some("vertex"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                fragment: Some(RawFragmentState {
+                    module: &self.shader,
+                    entry_point: Some("fragment"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    targets: &[Some(ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+                cache: None,
+            })
+        })
+    }
+
+    /// Render mip levels `1..` of `gpu_image` from level 0
+    fn generate(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        gpu_image: &GpuImage,
+    ) {
+        if gpu_image.mip_level_count <= 1 {
+            return;
+        }
+        let views = (0..gpu_image.mip_level_count)
+            .map(|mip_level| {
+                gpu_image.texture.create_view(&TextureViewDescriptor {
+                    label: Some("video_mipmap_view"),
+                    base_mip_level: mip_level,
+                    mip_level_count: Some(1),
+                    ..default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let pipeline = self
+            .pipeline(render_device, gpu_image.texture_format)
+            .clone();
+
+        let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("video_mipmap_encoder"),
+        });
+        for pair in views.windows(2) {
+            let [source, target] = pair else {
+                continue;
+            };
+            let bind_group = render_device.create_bind_group(
+                "video_mipmap_bind_group",
+                &self.layout,
+                &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(source),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            );
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("video_mipmap_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: target,
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: StoreOp::Store,
+                    },
+                })],
+                ..default()
+            });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        // Submitted after the frame copy already on the queue
+        render_queue.submit([encoder.finish()]);
+    }
+}
+
+/// Runs after all video uploads of the frame
+pub(super) fn generate_mipmaps(
+    mut mipmap_generator: ResMut<MipmapGenerator>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+) {
+    let queued = std::mem::take(&mut mipmap_generator.queued);
+    for image in queued {
+        if let Some(gpu_image) = gpu_images.get(image) {
+            mipmap_generator.generate(&render_device, &render_queue, gpu_image);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{VideoElement, VideoElementRegistry, WebVideo, render::UploadedFrame};
+use bevy::prelude::*;
+
+pub fn plugin(app: &mut App) {
+    app.register_type::<VideoSeek>()
+        .init_resource::<PendingSeeks>()
+        .add_systems(Update, (complete_seeks, start_seeks).chain());
+}
+
+#[derive(Reflect, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SeekMode {
+    /// Seek to the exact frame at the requested time
+    #[default]
+    Exact,
+    /// Seek to the nearest keyframe using `fastSeek`, falls back to [`SeekMode::Exact`]
+    /// where unsupported
+    Fast,
+}
+
+/// Seeks a [`WebVideo`] entity's element, inserting or changing this component starts a seek.
+///
+/// [`VideoSeeked`] is fired at the entity once the frame at the new position
+/// has been copied into its image.
+#[derive(Component, Reflect, Copy, Clone, Debug, Default, PartialEq)]
+#[reflect(Component, Default)]
+pub struct VideoSeek {
+    /// Target time in seconds
+    pub time: f64,
+    pub mode: SeekMode,
+}
+
+impl VideoSeek {
+    pub fn exact(time: f64) -> Self {
+        Self {
+            time,
+            mode: SeekMode::Exact,
+        }
+    }
+
+    pub fn fast(time: f64) -> Self {
+        Self {
+            time,
+            mode: SeekMode::Fast,
+        }
+    }
+}
+
+/// Fired when the frame for a [`VideoSeek`] has been uploaded to the [`WebVideo`]'s image.
+///
+/// Unlike [`ListenerEvent<events::Seeked>`](crate::ListenerEvent), which fires when the element
+/// has seeked, the image already shows the new frame when this is observed.
+#[derive(EntityEvent, Clone, Debug)]
+pub struct VideoSeeked {
+    pub entity: Entity,
+    /// Position the element seeked to, may differ from the requested time for [`SeekMode::Fast`]
+    pub time: f64,
+}
+
+#[derive(Resource, Default, Deref, DerefMut)]
+struct PendingSeeks(Vec<(Entity, AssetId<VideoElement>)>);
+
+fn start_seeks(
+    mut seeks: Query<(Entity, &WebVideo, Mut<VideoSeek>), Changed<VideoSeek>>,
+    mut registry: NonSendMut<VideoElementRegistry>,
+    mut pending_seeks: ResMut<PendingSeeks>,
+) {
+    for (entity, web_video, mut seek) in &mut seeks {
+        let asset_id = web_video.asset_id();
+        if let Some(player) = registry.player_mut(asset_id) {
+            player.seek(seek.time, seek.mode);
+            pending_seeks.retain(|(pending_entity, _)| *pending_entity != entity);
+            pending_seeks.push((entity, asset_id));
+            continue;
+        }
+        // Before metadata, currentTime only sets the start position and no seek happens
+        if registry
+            .element(asset_id)
+            .is_none_or(|element| element.ready_state() < web_sys::HtmlMediaElement::HAVE_METADATA)
+        {
+            // Keep the component dirty until the element can seek
+            seek.set_changed();
+            continue;
+        }
+        registry.seek(asset_id, seek.time, seek.mode == SeekMode::Fast);
+        pending_seeks.retain(|(pending_entity, _)| *pending_entity != entity);
+        pending_seeks.push((entity, asset_id));
+    }
+}
+
+fn complete_seeks(
+    mut uploaded_frames: MessageReader<UploadedFrame>,
+    mut pending_seeks: ResMut<PendingSeeks>,
+    mut commands: Commands,
+) {
+    // Frames uploaded while seeking are from before the new position
+    for uploaded in uploaded_frames.read().filter(|frame| !frame.seeking) {
+        pending_seeks.retain(|(entity, asset_id)| {
+            if *asset_id != uploaded.asset_id {
+                return true;
+            }
+            commands.trigger(VideoSeeked {
+                entity: *entity,
+                time: uploaded.time,
+            });
+            false
+        });
+    }
+}
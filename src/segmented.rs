@@ -0,0 +1,236 @@
+//! Shared by the streams fetching segments into a `MediaSource`, i.e. HLS and DASH
+
+use crate::{AbrRendition, SourceBufferId, VideoError, fetch::Fetch};
+use bevy::prelude::*;
+use std::ops::Range;
+
+/// Seconds buffered ahead of the current time before fetching pauses
+pub(crate) const MAX_BUFFER_AHEAD: f64 = 30.0;
+/// Buffered ranges end up to this many seconds off the manifest's segment boundaries
+pub(crate) const GAP_TOLERANCE: f64 = 0.5;
+/// Fraction of the measured throughput a rendition's bandwidth may use
+const BANDWIDTH_FACTOR: f64 = 0.8;
+/// Weight of the latest segment in the throughput estimate
+const THROUGHPUT_WEIGHT: f64 = 0.3;
+const MAX_RETRIES: u32 = 3;
+/// Milliseconds before the first retry, doubled for each following one
+const RETRY_DELAY: f64 = 1000.0;
+
+/// Which rendition of a segmented stream is fetched, i.e. an HLS variant
+/// or a DASH representation
+#[derive(Reflect, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RenditionSelection {
+    /// The highest bandwidth the measured throughput sustains
+    #[default]
+    Auto,
+    /// Index into the renditions
+    Fixed(usize),
+}
+
+impl RenditionSelection {
+    /// `Auto` fetches `choice` of an [`AbrController`](crate::AbrController) if there is one
+    pub(crate) fn with_choice(self, choice: Option<usize>) -> Self {
+        match (self, choice) {
+            (RenditionSelection::Auto, Some(rendition)) => RenditionSelection::Fixed(rendition),
+            (selection, _) => selection,
+        }
+    }
+
+    /// Index into renditions with `bandwidths` in bits per second, `None` without renditions
+    pub(crate) fn select(
+        &self,
+        bandwidths: &[u64],
+        current: Option<usize>,
+        throughput: Option<f64>,
+    ) -> Option<usize> {
+        if bandwidths.is_empty() {
+            return None;
+        }
+        match *self {
+            RenditionSelection::Fixed(rendition) => Some(rendition.min(bandwidths.len() - 1)),
+            RenditionSelection::Auto => {
+                let Some(throughput) = throughput else {
+                    return Some(current.unwrap_or_default());
+                };
+                let available = throughput * BANDWIDTH_FACTOR;
+                let sustained = bandwidths
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, bandwidth)| **bandwidth as f64 <= available)
+                    .max_by_key(|(_, bandwidth)| **bandwidth);
+                let lowest = bandwidths
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, bandwidth)| **bandwidth);
+                sustained.or(lowest).map(|(index, _)| index)
+            }
+        }
+    }
+}
+
+/// A stream whose renditions an [`AbrController`](crate::AbrController) picks
+pub(crate) trait SegmentedStream {
+    /// Renditions a [`RenditionSelection`] indexes into
+    fn renditions(&self) -> Vec<AbrRendition>;
+    fn current_rendition(&self) -> Option<usize>;
+    /// Bits per second
+    fn measured_throughput(&self) -> Option<f64>;
+    /// Buffer of the video track, `None` until added
+    fn video_buffer(&self) -> Option<SourceBufferId>;
+    /// Rendition fetched for [`RenditionSelection::Auto`], `None` for the built-in choice
+    fn set_abr_choice(&mut self, rendition: Option<usize>);
+}
+
+/// Moving average of segment download throughput
+#[derive(Default)]
+pub(crate) struct Throughput(Option<f64>);
+
+impl Throughput {
+    pub(crate) fn measure(&mut self, bytes: usize, seconds: f64) {
+        let sample = bytes as f64 * 8.0 / seconds.max(0.001);
+        self.0 = Some(match self.0 {
+            Some(throughput) => throughput + (sample - throughput) * THROUGHPUT_WEIGHT,
+            None => sample,
+        });
+    }
+
+    /// Bits per second
+    pub(crate) fn estimate(&self) -> Option<f64> {
+        self.0
+    }
+}
+
+/// One request at a time, network failures are retried with a growing delay
+pub(crate) struct Downloader<R> {
+    running: Option<Download<R>>,
+    retry: Option<(Download<R>, f64)>,
+    retries: u32,
+}
+
+/// A finished request with its body and the seconds it took
+pub(crate) type Completed<R> = (R, Result<(Vec<u8>, f64), VideoError>);
+
+struct Download<R> {
+    request: R,
+    url: String,
+    byte_range: Option<Range<u64>>,
+    fetch: Option<Fetch>,
+}
+
+impl<R> Default for Downloader<R> {
+    fn default() -> Self {
+        Self {
+            running: None,
+            retry: None,
+            retries: 0,
+        }
+    }
+}
+
+impl<R> Downloader<R> {
+    /// Fetch `byte_range` of `url`, replacing a running request
+    pub(crate) fn start(&mut self, request: R, url: &str, byte_range: Option<Range<u64>>) {
+        self.retry = None;
+        self.running = Some(Download {
+            fetch: Some(Fetch::start(url, byte_range.clone())),
+            request,
+            url: url.to_string(),
+            byte_range,
+        });
+    }
+
+    /// Neither running nor waiting to retry
+    pub(crate) fn is_idle(&self) -> bool {
+        self.running.is_none() && self.retry.is_none()
+    }
+
+    /// The running request, or the one waiting to retry
+    pub(crate) fn request(&self) -> Option<&R> {
+        self.running
+            .as_ref()
+            .or(self.retry.as_ref().map(|(download, _)| download))
+            .map(|download| &download.request)
+    }
+
+    pub(crate) fn abort(&mut self) {
+        self.running = None;
+        self.retry = None;
+    }
+
+    /// The completed request, an error once retries are exhausted
+    pub(crate) fn poll(&mut self) -> Option<Completed<R>> {
+        if let Some((_, due)) = &self.retry
+            && js_sys::Date::now() >= *due
+            && let Some((mut download, _)) = self.retry.take()
+        {
+            download.fetch = Some(Fetch::start(&download.url, download.byte_range.clone()));
+            self.running = Some(download);
+        }
+
+        let (result, seconds) = self.running.as_ref()?.fetch.as_ref()?.take()?;
+        let mut download = self.running.take()?;
+        match result {
+            Ok(bytes) => {
+                self.retries = 0;
+                Some((download.request, Ok((bytes, seconds))))
+            }
+            Err(VideoError::Network) if self.retries < MAX_RETRIES => {
+                let delay = RETRY_DELAY * 2f64.powi(self.retries as i32);
+                self.retries += 1;
+                download.fetch = None;
+                self.retry = Some((download, js_sys::Date::now() + delay));
+                None
+            }
+            Err(error) => Some((download.request, Err(error))),
+        }
+    }
+}
+
+/// Buffered range playing at `time`, tolerating a gap before its start
+pub(crate) fn playing_range(buffered: &[Range<f64>], time: f64) -> Option<Range<f64>> {
+    buffered
+        .iter()
+        .find(|range| range.start - GAP_TOLERANCE <= time && time < range.end)
+        .cloned()
+}
+
+/// Boxes of an ISO BMFF byte range as type and body
+pub(crate) fn mp4_boxes(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        let fourcc = data.get(4..8)?.try_into().ok()?;
+        let body = data.get(8..size)?;
+        data = &data[size..];
+        Some((fourcc, body))
+    })
+}
+
+/// Body of the first box at `path`
+pub(crate) fn find_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    let (first, rest) = path.split_first()?;
+    let (_, body) = mp4_boxes(data).find(|(fourcc, _)| fourcc == *first)?;
+    if rest.is_empty() {
+        Some(body)
+    } else {
+        find_box(body, rest)
+    }
+}
+
+/// Timescale of the first track of an initialization segment
+pub(crate) fn first_timescale(init: &[u8]) -> Option<u32> {
+    let mdhd = find_box(init, &[b"moov", b"trak", b"mdia", b"mdhd"])?;
+    // Creation and modification times are 64 bit in version 1
+    let offset = if mdhd.first()? == &1 { 20 } else { 12 };
+    Some(u32::from_be_bytes(
+        mdhd.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Base media decode time of the first track of a media segment
+pub(crate) fn first_decode_time(segment: &[u8]) -> Option<u64> {
+    let tfdt = find_box(segment, &[b"moof", b"traf", b"tfdt"])?;
+    match tfdt.first()? {
+        1 => Some(u64::from_be_bytes(tfdt.get(4..12)?.try_into().ok()?)),
+        _ => Some(u32::from_be_bytes(tfdt.get(4..8)?.try_into().ok()?) as u64),
+    }
+}
@@ -0,0 +1,149 @@
+use crate::VideoElementRegistry;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use wgpu_types::ExternalImageSource;
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(Update, update_texture_sources);
+}
+
+/// A web image source streamed into a texture through the same asset and render pipeline
+/// as video elements, e.g. the canvas of a JS chart library or a Lottie animation.
+///
+/// Create one with [`VideoElementAssetsExt::new_texture_source`](crate::VideoElementAssetsExt::new_texture_source)
+/// and display it with [`WebVideo::new`](crate::WebVideo::new), [`VideoPlayback`](crate::VideoPlayback)
+/// and [`VideoSeek`](crate::VideoSeek) don't apply to it. The target image follows the size
+/// of the source, firing [`events::Resize`](crate::events::Resize).
+///
+/// JS objects can't be sent between threads, so unlike [`VideoSource`](crate::VideoSource)
+/// a source is handed straight to the [`VideoElementRegistry`].
+#[derive(Clone, Debug)]
+pub enum WebTextureSource {
+    Canvas(web_sys::HtmlCanvasElement),
+    /// Drawn through a canvas on WebGL2, which can't copy it directly
+    OffscreenCanvas(web_sys::OffscreenCanvas),
+    /// Bitmaps are immutable, replace it with
+    /// [`VideoElementRegistry::set_texture_source`] to show a new frame
+    ImageBitmap(web_sys::ImageBitmap),
+    /// Uploaded once the image has loaded
+    Image(web_sys::HtmlImageElement),
+    /// Closed frames are not uploaded, replace it like an `ImageBitmap`
+    #[cfg(feature = "webcodecs")]
+    VideoFrame(web_sys::VideoFrame),
+}
+
+impl WebTextureSource {
+    /// Size in pixels, zero while an image is loading or once a frame is closed
+    pub fn size(&self) -> UVec2 {
+        match self {
+            WebTextureSource::Canvas(canvas) => UVec2::new(canvas.width(), canvas.height()),
+            WebTextureSource::OffscreenCanvas(canvas) => {
+                UVec2::new(canvas.width(), canvas.height())
+            }
+            WebTextureSource::ImageBitmap(bitmap) => UVec2::new(bitmap.width(), bitmap.height()),
+            WebTextureSource::Image(image) if image.complete() => {
+                UVec2::new(image.natural_width(), image.natural_height())
+            }
+            WebTextureSource::Image(_) => UVec2::ZERO,
+            #[cfg(feature = "webcodecs")]
+            WebTextureSource::VideoFrame(frame) => {
+                UVec2::new(frame.display_width(), frame.display_height())
+            }
+        }
+    }
+
+    /// Whether copying requires [`DownlevelFlags::UNRESTRICTED_EXTERNAL_TEXTURE_COPIES`](wgpu_types::DownlevelFlags)
+    pub(crate) fn needs_unrestricted_copies(&self) -> bool {
+        matches!(self, WebTextureSource::OffscreenCanvas(_))
+    }
+
+    pub(crate) fn into_external_image(self) -> ExternalImageSource {
+        match self {
+            WebTextureSource::Canvas(canvas) => ExternalImageSource::HTMLCanvasElement(canvas),
+            WebTextureSource::OffscreenCanvas(canvas) => {
+                ExternalImageSource::OffscreenCanvas(canvas)
+            }
+            WebTextureSource::ImageBitmap(bitmap) => ExternalImageSource::ImageBitmap(bitmap),
+            WebTextureSource::Image(image) => ExternalImageSource::HTMLImageElement(image),
+            #[cfg(feature = "webcodecs")]
+            WebTextureSource::VideoFrame(frame) => ExternalImageSource::VideoFrame(frame),
+        }
+    }
+}
+
+impl From<web_sys::HtmlCanvasElement> for WebTextureSource {
+    fn from(canvas: web_sys::HtmlCanvasElement) -> Self {
+        WebTextureSource::Canvas(canvas)
+    }
+}
+
+impl From<web_sys::OffscreenCanvas> for WebTextureSource {
+    fn from(canvas: web_sys::OffscreenCanvas) -> Self {
+        WebTextureSource::OffscreenCanvas(canvas)
+    }
+}
+
+impl From<web_sys::ImageBitmap> for WebTextureSource {
+    fn from(bitmap: web_sys::ImageBitmap) -> Self {
+        WebTextureSource::ImageBitmap(bitmap)
+    }
+}
+
+impl From<web_sys::HtmlImageElement> for WebTextureSource {
+    fn from(image: web_sys::HtmlImageElement) -> Self {
+        WebTextureSource::Image(image)
+    }
+}
+
+#[cfg(feature = "webcodecs")]
+impl From<web_sys::VideoFrame> for WebTextureSource {
+    fn from(frame: web_sys::VideoFrame) -> Self {
+        WebTextureSource::VideoFrame(frame)
+    }
+}
+
+/// When a [`WebTextureSource`] is uploaded to its target image
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SourceUpdate {
+    /// Every frame, for sources that animate on their own
+    #[default]
+    EveryFrame,
+    /// Only when requested with [`VideoElementRegistry::request_source_upload`]
+    /// or when the source is replaced or resized
+    OnRequest,
+}
+
+#[derive(Debug)]
+pub(crate) struct RegisteredSource {
+    pub(crate) source: WebTextureSource,
+    pub(crate) update: SourceUpdate,
+    pub(crate) upload_requested: bool,
+    // Last size, a change fires Resize
+    pub(crate) size: UVec2,
+}
+
+impl RegisteredSource {
+    pub(crate) fn new(source: WebTextureSource, update: SourceUpdate) -> Self {
+        Self {
+            source,
+            update,
+            upload_requested: true,
+            size: UVec2::ZERO,
+        }
+    }
+
+    /// Whether the source should be uploaded this frame
+    pub(crate) fn take_upload(&mut self) -> bool {
+        let upload = self.update == SourceUpdate::EveryFrame || self.upload_requested;
+        if upload && self.size != UVec2::ZERO {
+            self.upload_requested = false;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn update_texture_sources(mut registry: NonSendMut<VideoElementRegistry>) {
+    registry.update_texture_sources();
+}
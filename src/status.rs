@@ -0,0 +1,90 @@
+use crate::{MediaPlayer, VideoElementRegistry, VideoError, WebVideo};
+use bevy::prelude::*;
+
+pub fn plugin(app: &mut App) {
+    app.register_type::<VideoStatus>()
+        .add_systems(Update, update_status);
+}
+
+/// Current state of a [`WebVideo`] entity, kept up to date from its element
+#[derive(Component, Reflect, Clone, Debug, Default, PartialEq, Eq)]
+#[reflect(Component, Default)]
+pub enum VideoStatus {
+    /// No frame is available yet
+    #[default]
+    Loading,
+    /// A frame is available but the video has not started playing
+    Ready,
+    Playing,
+    Paused,
+    Ended,
+    /// The element or its MSE, HLS or DASH stream failed, or a play request was rejected,
+    /// e.g. with [`VideoError::NotAllowed`] by the autoplay policy
+    Errored(VideoError),
+}
+
+impl VideoStatus {
+    fn from_element(
+        element: &web_sys::HtmlVideoElement,
+        stream_error: Option<VideoError>,
+        play_error: Option<VideoError>,
+    ) -> Self {
+        if let Some(error) = element.error() {
+            VideoStatus::Errored(VideoError::from(&error))
+        } else if let Some(error) = stream_error.or(play_error) {
+            VideoStatus::Errored(error)
+        } else if element.ended() {
+            VideoStatus::Ended
+        } else if element.ready_state() < web_sys::HtmlMediaElement::HAVE_CURRENT_DATA {
+            VideoStatus::Loading
+        } else if !element.paused() {
+            VideoStatus::Playing
+        } else if element.played().length() == 0 {
+            VideoStatus::Ready
+        } else {
+            VideoStatus::Paused
+        }
+    }
+
+    fn from_player(player: &dyn MediaPlayer) -> Self {
+        if let Some(error) = player.error() {
+            VideoStatus::Errored(error.clone())
+        } else if player.ended() {
+            VideoStatus::Ended
+        } else if !player.loaded() {
+            VideoStatus::Loading
+        } else if !player.paused() {
+            VideoStatus::Playing
+        } else if !player.played() {
+            VideoStatus::Ready
+        } else {
+            VideoStatus::Paused
+        }
+    }
+}
+
+fn update_status(
+    mut videos: Query<(&WebVideo, &mut VideoStatus)>,
+    registry: NonSend<VideoElementRegistry>,
+) {
+    for (web_video, mut status) in &mut videos {
+        if let Some(element) = registry.element(web_video.asset_id()) {
+            status.set_if_neq(VideoStatus::from_element(
+                element,
+                registry.stream_error(web_video.asset_id()),
+                registry.play_error(web_video.asset_id()),
+            ));
+        }
+        // Texture sources have no playback, they are ready once they have a size
+        if let Some(source) = registry.texture_source(web_video.asset_id()) {
+            status.set_if_neq(if source.size() == UVec2::ZERO {
+                VideoStatus::Loading
+            } else {
+                VideoStatus::Ready
+            });
+        }
+        if let Some(player) = registry.player(web_video.asset_id()) {
+            status.set_if_neq(VideoStatus::from_player(player));
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! Live video from a `MediaStream`: a camera, screen capture or a canvas captured as a stream.
+
+use crate::{EventType, events};
+use bevy::prelude::*;
+use gloo_events::EventListener;
+use std::{cell::RefCell, rc::Rc};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+/// Where a [`VideoSource::MediaStream`](crate::VideoSource::MediaStream) gets its stream from.
+///
+/// The stream is requested when the element is created and assigned to its `srcObject`,
+/// the outcome is fired as [`events::StreamPermission`]. Play it like any other video with
+/// [`VideoPlayback`](crate::VideoPlayback), seeking doesn't apply. The tracks are stopped
+/// when the asset is removed, releasing the camera or ending the capture.
+///
+/// Browsers only expose media devices in secure contexts. Headless browsers can run it without
+/// prompts or hardware, e.g. Chrome with `--use-fake-ui-for-media-stream` and
+/// `--use-fake-device-for-media-stream` streams a test pattern for the camera, and
+/// `--auto-select-desktop-capture-source=<window title>` picks the screen capture source.
+/// Firefox does the same with the `media.navigator.streams.fake` and
+/// `media.navigator.permission.disabled` preferences.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MediaStreamSource {
+    /// `getUserMedia`
+    Camera(CameraConstraints),
+    /// `getDisplayMedia`, which browsers only allow shortly after a user gesture
+    Display { audio: bool },
+    /// `captureStream` of the `<canvas>` with element id `id`, captured on every change
+    /// or at most `frame_rate` times per second
+    Canvas { id: String, frame_rate: Option<f64> },
+}
+
+/// Camera selection for [`MediaStreamSource::Camera`], unset fields are up to the browser
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraConstraints {
+    /// `deviceId` from `enumerateDevices`, required if set
+    pub device_id: Option<String>,
+    pub facing_mode: Option<FacingMode>,
+    /// Preferred resolution, the camera's closest is used
+    pub ideal_size: Option<UVec2>,
+    pub ideal_frame_rate: Option<f64>,
+    /// Also request the microphone, mute the video to avoid feedback
+    pub audio: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FacingMode {
+    /// Front camera
+    User,
+    /// Back camera
+    Environment,
+}
+
+impl FacingMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FacingMode::User => "user",
+            FacingMode::Environment => "environment",
+        }
+    }
+}
+
+/// Outcome of a [`MediaStreamSource`] request, from the `DOMException` name of a rejection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MediaStreamPermission {
+    /// The stream was assigned to the element
+    Granted,
+    /// `NotAllowedError` or `SecurityError`, the user or a permissions policy refused
+    Denied,
+    /// `NotFoundError`, no matching device or no canvas with the id
+    NoDevice,
+    /// `NotReadableError` or `AbortError`, e.g. the camera is in use by another application
+    Unreadable,
+    /// `OverconstrainedError` for the named constraint, e.g. an unknown `deviceId`
+    Overconstrained(String),
+    /// `InvalidStateError`, screen capture was requested without a recent user gesture
+    NoUserGesture,
+    /// `NotSupportedError`, e.g. media devices are unavailable outside secure contexts
+    Unsupported,
+    Failed(String),
+}
+
+impl MediaStreamPermission {
+    pub fn is_granted(&self) -> bool {
+        *self == MediaStreamPermission::Granted
+    }
+
+    /// The stream or rejection passed as the `detail` of the dispatched event
+    pub(crate) fn from_event(event: &web_sys::Event) -> Self {
+        let Some(detail) = event
+            .dyn_ref::<web_sys::CustomEvent>()
+            .map(|event| event.detail())
+        else {
+            return MediaStreamPermission::Failed("missing request result".into());
+        };
+        if detail.is_instance_of::<web_sys::MediaStream>() {
+            return MediaStreamPermission::Granted;
+        }
+        // OverconstrainedError is no DOMException in older browsers, read its fields instead
+        let field = |name: &str| {
+            js_sys::Reflect::get(&detail, &JsValue::from_str(name))
+                .ok()
+                .and_then(|value| value.as_string())
+                .unwrap_or_default()
+        };
+        match field("name").as_str() {
+            "NotAllowedError" | "SecurityError" => MediaStreamPermission::Denied,
+            "NotFoundError" => MediaStreamPermission::NoDevice,
+            "NotReadableError" | "AbortError" => MediaStreamPermission::Unreadable,
+            "OverconstrainedError" => MediaStreamPermission::Overconstrained(field("constraint")),
+            "InvalidStateError" => MediaStreamPermission::NoUserGesture,
+            "NotSupportedError" => MediaStreamPermission::Unsupported,
+            "" => MediaStreamPermission::Failed(format!("{detail:?}")),
+            name => MediaStreamPermission::Failed(format!("{name}: {}", field("message"))),
+        }
+    }
+}
+
+impl MediaStreamSource {
+    /// Promise of the stream, rejected with a `DOMException` like the media device requests
+    fn request(&self, document: &web_sys::Document) -> Result<js_sys::Promise, JsValue> {
+        match self {
+            MediaStreamSource::Camera(camera) => {
+                let constraints = web_sys::MediaStreamConstraints::new();
+                constraints.set_video(&camera.video_constraints());
+                constraints.set_audio(&JsValue::from_bool(camera.audio));
+                media_devices()?.get_user_media_with_constraints(&constraints)
+            }
+            MediaStreamSource::Display { audio } => {
+                let constraints = web_sys::DisplayMediaStreamConstraints::new();
+                constraints.set_video(&JsValue::TRUE);
+                constraints.set_audio(&JsValue::from_bool(*audio));
+                media_devices()?.get_display_media_with_constraints(&constraints)
+            }
+            MediaStreamSource::Canvas { id, frame_rate } => {
+                let canvas = document
+                    .get_element_by_id(id)
+                    .and_then(|element| element.dyn_into::<web_sys::HtmlCanvasElement>().ok())
+                    .ok_or_else(|| exception(&format!("no canvas #{id}"), "NotFoundError"))?;
+                let stream = match frame_rate {
+                    Some(frame_rate) => canvas.capture_stream_with_frame_request_rate(*frame_rate),
+                    None => canvas.capture_stream(),
+                }?;
+                Ok(js_sys::Promise::resolve(&stream))
+            }
+        }
+    }
+}
+
+impl CameraConstraints {
+    fn video_constraints(&self) -> JsValue {
+        let video = web_sys::MediaTrackConstraints::new();
+        if let Some(device_id) = &self.device_id {
+            let exact = web_sys::ConstrainDomStringParameters::new();
+            exact.set_exact(&JsValue::from_str(device_id));
+            video.set_device_id(&exact);
+        }
+        if let Some(facing_mode) = self.facing_mode {
+            video.set_facing_mode(&JsValue::from_str(facing_mode.as_str()));
+        }
+        if let Some(size) = self.ideal_size {
+            let width = web_sys::ConstrainLongRange::new();
+            width.set_ideal(size.x as i32);
+            video.set_width(&width);
+            let height = web_sys::ConstrainLongRange::new();
+            height.set_ideal(size.y as i32);
+            video.set_height(&height);
+        }
+        if let Some(frame_rate) = self.ideal_frame_rate {
+            let ideal = web_sys::ConstrainDoubleRange::new();
+            ideal.set_ideal(frame_rate);
+            video.set_frame_rate(&ideal);
+        }
+        video.into()
+    }
+}
+
+/// `navigator.mediaDevices` is undefined outside secure contexts
+fn media_devices() -> Result<web_sys::MediaDevices, JsValue> {
+    web_sys::window()
+        .and_then(|window| window.navigator().media_devices().ok())
+        .filter(|media_devices| !media_devices.is_undefined())
+        .ok_or_else(|| exception("media devices unavailable", "NotSupportedError"))
+}
+
+fn exception(message: &str, name: &str) -> JsValue {
+    web_sys::DomException::new_with_message_and_name(message, name)
+        .map(JsValue::from)
+        .unwrap_or_else(|err| err)
+}
+
+/// Stream of an element, requested on creation and stopped when dropped with the asset
+pub(crate) struct RegisteredStream {
+    state: Rc<RefCell<StreamState>>,
+}
+
+enum StreamState {
+    Requested,
+    Active {
+        stream: web_sys::MediaStream,
+        // Dispatch events::StreamEnded on the element
+        _listeners: Vec<EventListener>,
+    },
+    Stopped,
+}
+
+impl RegisteredStream {
+    /// Assign the stream of `source` to `element` once granted and dispatch
+    /// [`events::StreamPermission`] on it with the outcome
+    pub(crate) fn request(
+        source: &MediaStreamSource,
+        element: &web_sys::HtmlVideoElement,
+        document: &web_sys::Document,
+    ) -> Self {
+        let state = Rc::new(RefCell::new(StreamState::Requested));
+        let task_state = state.clone();
+        let element = element.clone();
+        let request = source.request(document);
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = match request {
+                Ok(promise) => JsFuture::from(promise).await,
+                Err(err) => Err(err),
+            };
+            let detail = {
+                let mut state = task_state.borrow_mut();
+                // The asset was removed while the user was prompted
+                if matches!(*state, StreamState::Stopped) {
+                    if let Ok(stream) = result {
+                        stop_tracks(&stream.unchecked_into());
+                    }
+                    return;
+                }
+                match result {
+                    Ok(stream) => {
+                        let stream = stream.unchecked_into::<web_sys::MediaStream>();
+                        element.set_src_object(Some(&stream));
+                        *state = StreamState::Active {
+                            _listeners: ended_listeners(&stream, &element),
+                            stream: stream.clone(),
+                        };
+                        stream.into()
+                    }
+                    Err(err) => err,
+                }
+            };
+            dispatch(
+                &element,
+                events::StreamPermission::EVENT_NAME,
+                Some(&detail),
+            );
+        });
+        Self { state }
+    }
+
+    /// `None` until granted
+    pub(crate) fn stream(&self) -> Option<web_sys::MediaStream> {
+        match &*self.state.borrow() {
+            StreamState::Active { stream, .. } => Some(stream.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for RegisteredStream {
+    fn drop(&mut self) {
+        // Tracks keep capturing, and the camera light on, until stopped
+        if let StreamState::Active { stream, .. } = self.state.replace(StreamState::Stopped) {
+            stop_tracks(&stream);
+        }
+    }
+}
+
+fn stop_tracks(stream: &web_sys::MediaStream) {
+    for track in stream.get_tracks() {
+        track.unchecked_into::<web_sys::MediaStreamTrack>().stop();
+    }
+}
+
+/// Video tracks end when the device is unplugged or the user stops sharing in the browser UI
+fn ended_listeners(
+    stream: &web_sys::MediaStream,
+    element: &web_sys::HtmlVideoElement,
+) -> Vec<EventListener> {
+    stream
+        .get_video_tracks()
+        .iter()
+        .map(|track| {
+            let element = element.clone();
+            EventListener::new(
+                &track.unchecked_into::<web_sys::MediaStreamTrack>(),
+                "ended",
+                move |_| dispatch(&element, events::StreamEnded::EVENT_NAME, None),
+            )
+        })
+        .collect()
+}
+
+fn dispatch(element: &web_sys::HtmlVideoElement, event_name: &str, detail: Option<&JsValue>) {
+    let init = web_sys::CustomEventInit::new();
+    if let Some(detail) = detail {
+        init.set_detail(detail);
+    }
+    if let Err(err) = web_sys::CustomEvent::new_with_event_init_dict(event_name, &init)
+        .and_then(|event| element.dispatch_event(&event))
+    {
+        warn!("Failed to dispatch video event {event_name}: {err:?}");
+    }
+}
@@ -0,0 +1,209 @@
+use crate::VideoElement;
+use bevy::{
+    image::ImageSampler,
+    math::URect,
+    prelude::*,
+    render::{render_resource::TextureFormat, renderer::RenderDevice},
+};
+use serde::{Deserialize, Serialize};
+use wgpu_types::PredefinedColorSpace;
+
+/// Formats a video frame can be copied into
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VideoTextureFormat {
+    #[default]
+    Rgba8Unorm,
+    /// Decoded to linear when sampled, use for gamma-correct PBR materials
+    Rgba8UnormSrgb,
+    Bgra8Unorm,
+    Bgra8UnormSrgb,
+    Rgb10a2Unorm,
+    Rgba16Float,
+}
+
+impl From<VideoTextureFormat> for TextureFormat {
+    fn from(format: VideoTextureFormat) -> Self {
+        match format {
+            VideoTextureFormat::Rgba8Unorm => TextureFormat::Rgba8Unorm,
+            VideoTextureFormat::Rgba8UnormSrgb => TextureFormat::Rgba8UnormSrgb,
+            VideoTextureFormat::Bgra8Unorm => TextureFormat::Bgra8Unorm,
+            VideoTextureFormat::Bgra8UnormSrgb => TextureFormat::Bgra8UnormSrgb,
+            VideoTextureFormat::Rgb10a2Unorm => TextureFormat::Rgb10a2Unorm,
+            VideoTextureFormat::Rgba16Float => TextureFormat::Rgba16Float,
+        }
+    }
+}
+
+/// Largest target image side supported by `render_device`
+pub(crate) fn max_dimension(render_device: Option<&RenderDevice>) -> u32 {
+    render_device
+        .map(|render_device| render_device.limits().max_texture_dimension_2d)
+        .unwrap_or(u32::MAX)
+}
+
+/// Fired when a video's target image was downscaled to fit the device's max texture size
+#[derive(Event, Clone, Debug)]
+pub struct VideoTextureDownscaled {
+    pub asset_id: AssetId<VideoElement>,
+    /// Size the target image would have had
+    pub requested_size: UVec2,
+    /// Size the target image was allocated with
+    pub size: UVec2,
+    /// `max_texture_dimension_2d` of the device
+    pub max_dimension: u32,
+}
+
+/// How frames are uploaded, see [`VideoUploadPath`](crate::VideoUploadPath)
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VideoUploadMode {
+    /// Copy frames directly when supported, falling back to canvas readback when the source
+    /// can't be copied or the first copy fails
+    #[default]
+    Auto,
+    /// Always copy frames directly
+    ExternalCopy,
+    /// Always draw frames to a canvas and upload its pixels, only supports 8 bit RGBA
+    /// and BGRA formats
+    CanvasReadback,
+}
+
+/// Resolution of a video's target image relative to its frame
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+pub enum VideoResolution {
+    /// Size of the video, or of its source rect
+    #[default]
+    Intrinsic,
+    Fixed(UVec2),
+    /// Downscale so neither side exceeds this, keeping the aspect ratio
+    MaxDimension(u32),
+    /// Multiply both sides by this, keeping the aspect ratio
+    Scale(f32),
+}
+
+impl VideoResolution {
+    pub(crate) fn apply(&self, size: UVec2) -> UVec2 {
+        if size.cmpeq(UVec2::ZERO).any() {
+            return size;
+        }
+        let scaled = |scale: f32| (size.as_vec2() * scale).round().as_uvec2().max(UVec2::ONE);
+        match *self {
+            VideoResolution::Intrinsic => size,
+            VideoResolution::Fixed(fixed) => fixed,
+            VideoResolution::MaxDimension(max) if size.max_element() > max => {
+                scaled(max as f32 / size.max_element() as f32)
+            }
+            VideoResolution::MaxDimension(_) => size,
+            VideoResolution::Scale(scale) => scaled(scale),
+        }
+    }
+}
+
+/// Color space the frame is converted to when copied.
+///
+/// WebGL2 only supports [`VideoColorSpace::Srgb`], other color spaces are ignored there.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VideoColorSpace {
+    #[default]
+    Srgb,
+    DisplayP3,
+}
+
+impl From<VideoColorSpace> for PredefinedColorSpace {
+    fn from(color_space: VideoColorSpace) -> Self {
+        match color_space {
+            VideoColorSpace::Srgb => PredefinedColorSpace::Srgb,
+            VideoColorSpace::DisplayP3 => PredefinedColorSpace::DisplayP3,
+        }
+    }
+}
+
+/// How a video's frames are stored in its target [`Image`](bevy::prelude::Image)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VideoTextureSettings {
+    pub format: VideoTextureFormat,
+    pub color_space: VideoColorSpace,
+    /// Premultiply color by alpha when copying, `false` keeps straight alpha
+    pub premultiplied_alpha: bool,
+    pub sampler: ImageSampler,
+    pub flip_y: bool,
+    /// Allocate a full mip chain and regenerate it on the GPU after each frame.
+    ///
+    /// The sampler needs a linear `mipmap_filter` to blend between levels.
+    pub generate_mipmaps: bool,
+    /// Resolution of the frame in the target image, frames are scaled through a canvas
+    /// when it differs from the (cropped) video size.
+    ///
+    /// Frames are always downscaled to fit the device's max texture size,
+    /// firing [`VideoTextureDownscaled`].
+    pub resolution: VideoResolution,
+    /// Region of the video frame to copy, in video pixels. `None` copies the whole frame.
+    pub source_rect: Option<URect>,
+    /// Region of the target image the frame is copied into.
+    ///
+    /// `None` sizes the target image to the (cropped) frame. When set, the target image is
+    /// left at its size so it can be shared with other videos, e.g. as a [`VideoAtlas`](crate::VideoAtlas).
+    /// Frames larger than the region are cut off at its bottom and right edges.
+    pub target_rect: Option<URect>,
+    pub upload_mode: VideoUploadMode,
+}
+
+impl Default for VideoTextureSettings {
+    fn default() -> Self {
+        Self {
+            format: VideoTextureFormat::default(),
+            color_space: VideoColorSpace::default(),
+            premultiplied_alpha: true,
+            sampler: ImageSampler::default(),
+            flip_y: false,
+            generate_mipmaps: false,
+            resolution: VideoResolution::default(),
+            source_rect: None,
+            target_rect: None,
+            upload_mode: VideoUploadMode::default(),
+        }
+    }
+}
+
+impl VideoTextureSettings {
+    /// Part of a `video_size` frame that is copied
+    pub(crate) fn source_rect(&self, video_size: UVec2) -> URect {
+        let frame = URect::from_corners(UVec2::ZERO, video_size);
+        self.source_rect
+            .map(|rect| rect.intersect(frame))
+            .unwrap_or(frame)
+    }
+
+    /// Size of the frame stored in the target image, after cropping and applying the resolution,
+    /// downscaled to fit `max_dimension`
+    pub(crate) fn frame_size(&self, video_size: UVec2, max_dimension: u32) -> UVec2 {
+        let frame_size = self.resolution.apply(self.source_rect(video_size).size());
+        VideoResolution::MaxDimension(max_dimension).apply(frame_size)
+    }
+
+    /// Region of a `video_size` frame to copy and the region of a `target_size` image it is
+    /// copied to, which may be empty or smaller than [`frame_size`](Self::frame_size)
+    pub(crate) fn copy_rects(
+        &self,
+        video_size: UVec2,
+        target_size: UVec2,
+        max_dimension: u32,
+    ) -> (URect, URect) {
+        let source = self.source_rect(video_size);
+        let frame_size = self.frame_size(video_size, max_dimension);
+        let target = self
+            .target_rect
+            .unwrap_or(URect::from_corners(UVec2::ZERO, frame_size))
+            .intersect(URect::from_corners(UVec2::ZERO, target_size));
+        let size = frame_size.min(target.size());
+        (source, URect::from_corners(target.min, target.min + size))
+    }
+
+    /// Mip levels of a target image of `width` x `height`
+    pub(crate) fn mip_level_count(&self, width: u32, height: u32) -> u32 {
+        if self.generate_mipmaps {
+            width.max(height).max(1).ilog2() + 1
+        } else {
+            1
+        }
+    }
+}